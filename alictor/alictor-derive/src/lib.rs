@@ -9,31 +9,221 @@ pub fn alictor(
     item: proc_macro::TokenStream,
 ) -> proc_macro::TokenStream {
     let options = parse_macro_input!(attr as RawOptions);
-    let inherent_impl = parse_macro_input!(item as syn::ItemImpl);
+    let mut inherent_impl = parse_macro_input!(item as syn::ItemImpl);
 
     let mut blocking = None;
+    let mut handle_trait = None;
+    let mut send_timeout_ms = None;
+    let mut timeout_ms = None;
+    let mut batch_size = None;
+    let mut batch_window_ms = None;
+    let mut trace = false;
+    let mut debug_args = None;
+    let mut flatten_results = None;
+    let mut capacity = None;
+    let mut workers = None;
+    let mut send_many = vec![];
     for option in options.0 {
         match option {
             RawOption::Kind { kind, .. } => {
                 assert!(blocking.is_none(), "Must only set one kind");
                 blocking = Some(matches!(kind, RawKind::Blocking { .. }));
             }
+            RawOption::HandleTrait { path, .. } => {
+                assert!(handle_trait.is_none(), "Must only set one handle_trait");
+                handle_trait = Some(path);
+            }
+            RawOption::SendTimeoutMs { value, .. } => {
+                assert!(
+                    send_timeout_ms.is_none(),
+                    "Must only set one send_timeout_ms"
+                );
+                send_timeout_ms = Some(value);
+            }
+            RawOption::Timeout { millis, .. } => {
+                assert!(timeout_ms.is_none(), "Must only set one timeout");
+                timeout_ms = Some(millis);
+            }
+            RawOption::BatchSize { value, .. } => {
+                assert!(batch_size.is_none(), "Must only set one batch_size");
+                batch_size = Some(value);
+            }
+            RawOption::BatchWindowMs { value, .. } => {
+                assert!(
+                    batch_window_ms.is_none(),
+                    "Must only set one batch_window_ms"
+                );
+                batch_window_ms = Some(value);
+            }
+            RawOption::Trace { .. } => {
+                assert!(!trace, "Must only set trace once");
+                trace = true;
+            }
+            RawOption::DebugArgs { value, .. } => {
+                assert!(debug_args.is_none(), "Must only set one debug_args");
+                debug_args = Some(value.value);
+            }
+            RawOption::FlattenResults { value, .. } => {
+                assert!(
+                    flatten_results.is_none(),
+                    "Must only set one flatten_results"
+                );
+                flatten_results = Some(value.value);
+            }
+            RawOption::Capacity { value, .. } => {
+                assert!(capacity.is_none(), "Must only set one capacity");
+                capacity = Some(value);
+            }
+            RawOption::Workers { value, .. } => {
+                assert!(workers.is_none(), "Must only set one workers");
+                workers = Some(value);
+            }
+            RawOption::Batch { method, .. } => {
+                send_many.push(method);
+            }
         }
     }
 
+    assert!(
+        batch_size.is_some() || batch_window_ms.is_none(),
+        "batch_window_ms requires batch_size"
+    );
+
     const DEFAULT_BLOCKING: bool = false;
     let blocking = blocking.unwrap_or(DEFAULT_BLOCKING);
 
-    let ty = match &*inherent_impl.self_ty {
-        syn::Type::Path(p) => p,
-        _ => panic!("TODO: Only paths"),
+    assert!(
+        blocking || workers.is_none(),
+        "workers requires kind = blocking"
+    );
+
+    const DEFAULT_DEBUG_ARGS: bool = true;
+    let debug_args = debug_args.unwrap_or(DEFAULT_DEBUG_ARGS);
+
+    const DEFAULT_FLATTEN_RESULTS: bool = true;
+    let flatten_results = flatten_results.unwrap_or(DEFAULT_FLATTEN_RESULTS);
+
+    let capacity = capacity.map_or_else(|| quote! { 10 }, |c| quote! { #c });
+
+    let self_ty = inherent_impl.self_ty.clone();
+    let ty = match actor_name(&self_ty) {
+        Ok(ty) => ty.clone(),
+        Err(e) => return e.to_compile_error().into(),
     };
-    let ty = ty.path.get_ident().expect("TODO: Only locals");
+
+    for item in &inherent_impl.items {
+        match item {
+            syn::ImplItem::Method(_) | syn::ImplItem::Const(_) => {}
+            _ => {
+                return syn::Error::new_spanned(
+                    item,
+                    "Actors may only contain methods and constants",
+                )
+                .to_compile_error()
+                .into()
+            }
+        }
+    }
+
+    // Per-method options are only meaningful to us; the compiler must
+    // not see them.
+    let mut method_kinds = vec![];
+    let mut fatal_error_ty = None;
+    for item in &mut inherent_impl.items {
+        let m = match item {
+            syn::ImplItem::Method(m) => m,
+            _ => continue,
+        };
+
+        let mut kind = None;
+        let mut fatal = false;
+        let mut invalid_option = None;
+        m.attrs.retain(|attr| {
+            if !attr.path.is_ident("alictor") {
+                return true;
+            }
+
+            let option = match attr.parse_args::<RawMethodOption>() {
+                Ok(option) => option,
+                Err(e) => {
+                    invalid_option.get_or_insert(e);
+                    return false;
+                }
+            };
+            match option {
+                RawMethodOption::Kind(k) => {
+                    assert!(kind.is_none(), "Must only set one kind per method");
+                    kind = Some(matches!(k, RawKind::Blocking { .. }));
+                }
+                RawMethodOption::Fatal { .. } => {
+                    assert!(!fatal, "Must only set fatal once per method");
+                    fatal = true;
+                }
+            }
+            false
+        });
+
+        if let Some(e) = invalid_option {
+            return e.to_compile_error().into();
+        }
+
+        // Errors from the fatal method stop the actor and become the
+        // result of its task, so we need to know the error's type.
+        if fatal {
+            if fatal_error_ty.is_some() {
+                return syn::Error::new_spanned(
+                    &m.sig.ident,
+                    "Only one method may be `#[alictor(fatal)]`",
+                )
+                .to_compile_error()
+                .into();
+            }
+
+            let error_ty = match &m.sig.output {
+                syn::ReturnType::Type(_, t) => result_error_ty(t),
+                syn::ReturnType::Default => None,
+            };
+            match error_ty {
+                Some(t) => fatal_error_ty = Some(t.clone()),
+                None => {
+                    return syn::Error::new_spanned(
+                        &m.sig,
+                        "`#[alictor(fatal)]` methods must return `Result<T, E>`",
+                    )
+                    .to_compile_error()
+                    .into()
+                }
+            }
+        }
+
+        // An `async fn` is only useful if its future is awaited
+        let kind = kind.or_else(|| m.sig.asyncness.map(|_| false));
+
+        if kind == Some(false) {
+            assert!(
+                m.sig.asyncness.is_some(),
+                "#[alictor(async)] requires an `async fn`"
+            );
+        }
+
+        method_kinds.push((kind, fatal));
+    }
+    assert!(
+        workers.is_none() || fatal_error_ty.is_none(),
+        "workers cannot be combined with a fatal method"
+    );
+    let mut method_kinds = method_kinds.into_iter();
 
     struct Method<'a> {
         name: &'a syn::Ident,
         ret_ty: Option<&'a syn::Type>,
+        // The actor's failures are folded into the method's own
+        // `Result` instead of wrapping it
+        flatten: bool,
         arg_tys: Vec<&'a syn::Type>,
+        blocking: Option<bool>,
+        fatal: bool,
+        docs: Vec<&'a syn::Attribute>,
     }
 
     impl Method<'_> {
@@ -41,6 +231,37 @@ pub fn alictor(
             self.ret_ty.map_or_else(|| quote! { () }, |t| quote! { #t })
         }
 
+        // How the run loop invokes the method, depending on the kind
+        // of the actor and the method
+        //
+        // When traced, each command runs in its own span. Whether the
+        // span exists at all is decided by alictor's `tracing` feature.
+        fn call(&self, actor_blocking: bool, traced: Option<&str>) -> proc_macro2::TokenStream {
+            let name = self.name;
+            let arg_names = self.arg_names();
+            let call = quote! { self.#name(#(#arg_names),*) };
+            let command = name.to_string();
+
+            let call = match (actor_blocking, self.blocking) {
+                (false, Some(false)) => {
+                    return match traced {
+                        Some(actor) => quote! { alictor::__traced_async!(#actor, #command, #call) },
+                        None => quote! { #call.await },
+                    };
+                }
+                (_, None) | (true, Some(true)) => call,
+                (true, Some(false)) => quote! { __rt.block_on(#call) },
+                (false, Some(true)) => quote! {
+                    alictor::reexport::tokio::task::block_in_place(|| #call)
+                },
+            };
+
+            match traced {
+                Some(actor) => quote! { alictor::__traced!(#actor, #command, #call) },
+                None => call,
+            }
+        }
+
         fn arg_names(&self) -> Vec<syn::Ident> {
             (0u32..)
                 .map(|i| format_ident!("a{}", i))
@@ -56,32 +277,49 @@ pub fn alictor(
             _ => continue,
         };
 
-        let name = &m.sig.ident;
-        let ret_ty = match &m.sig.output {
-            syn::ReturnType::Default => None,
-            syn::ReturnType::Type(_, t) => Some(&**t),
-        };
+        let (blocking, fatal) = method_kinds.next().expect("Every method has a kind");
 
+        // Associated functions, such as constructors, stay on the
+        // actor but aren't commands.
         let mut inputs = m.sig.inputs.iter();
         let first = inputs.next();
         if !matches!(first, Some(syn::FnArg::Receiver(_))) {
-            panic!("TODO: Must be a method");
+            continue;
         }
 
+        let name = &m.sig.ident;
+        let ret_ty = match &m.sig.output {
+            syn::ReturnType::Default => None,
+            syn::ReturnType::Type(_, t) => Some(&**t),
+        };
+        let flatten = flatten_results && ret_ty.map_or(false, is_result);
+
         let mut arg_tys = vec![];
         for arg in inputs {
             let arg = match arg {
                 syn::FnArg::Typed(a) => a,
-                _ => panic!("TODO: What is this function"),
+                syn::FnArg::Receiver(r) => {
+                    return syn::Error::new_spanned(r, "Only the first argument may be `self`")
+                        .to_compile_error()
+                        .into()
+                }
             };
 
             arg_tys.push(&*arg.ty);
         }
 
+        // Only the documentation carries over to the handle; other
+        // attributes, such as `#[inline]`, describe the actor's method.
+        let docs = m.attrs.iter().filter(|a| a.path.is_ident("doc")).collect();
+
         methods.push(Method {
             name,
             ret_ty,
+            flatten,
             arg_tys,
+            blocking,
+            fatal,
+            docs,
         })
     }
 
@@ -97,63 +335,611 @@ pub fn alictor(
     });
 
     let command_enum_name = format_ident!("{}Command", ty);
+
+    // The generated types take the same generics as the actor's
+    // `impl`, bounds and all.
+    let generics = inherent_impl.generics.clone();
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let batch_variant = batch_size.as_ref().map(|_| {
+        quote! {
+            __Batch(alictor::reexport::futures::channel::oneshot::Sender<()>, Vec<#command_enum_name #ty_generics>),
+        }
+    });
+
+    // The commands might not mention every generic parameter, but
+    // the actor type does. This variant can never be constructed.
+    let phantom = if generics.params.is_empty() {
+        None
+    } else {
+        Some((
+            quote! { __Phantom(std::marker::PhantomData<fn() -> #self_ty>, std::convert::Infallible), },
+            quote! { Self::__Phantom(_, never) => match *never {}, },
+            quote! { #command_enum_name::__Phantom(_, never) => match never {}, },
+        ))
+    };
+    let phantom_variant = phantom.as_ref().map(|(v, _, _)| v);
+    let phantom_debug_arm = phantom.as_ref().map(|(_, a, _)| a);
+    let phantom_dispatch_arm = phantom.as_ref().map(|(_, _, a)| a);
+
+    let mut debug_generics = generics.clone();
+    if debug_args {
+        let predicates = &mut debug_generics.make_where_clause().predicates;
+        for ty in methods.iter().flat_map(|m| &m.arg_tys) {
+            predicates.push(syn::parse_quote! { #ty: std::fmt::Debug });
+        }
+    }
+    let debug_where_clause = &debug_generics.where_clause;
+
+    // Written by hand so that the reply senders (and thus the return
+    // types) never need to be `Debug`. The arguments can opt out too.
+    let debug_arms = methods.iter().map(|m| {
+        let Method { name, .. } = m;
+        let arg_names = m.arg_names();
+        let name_str = name.to_string();
+
+        if debug_args {
+            quote! {
+                Self::#name(_, #(#arg_names),*) => f.debug_tuple(#name_str)#(.field(#arg_names))*.finish(),
+            }
+        } else {
+            quote! {
+                Self::#name(..) => f.write_str(#name_str),
+            }
+        }
+    });
+
+    let batch_debug_arm = batch_size.as_ref().map(|_| {
+        quote! {
+            Self::__Batch(_, cmds) => f.debug_tuple("__Batch").field(cmds).finish(),
+        }
+    });
+
     let command_enum = quote! {
-        #[derive(Debug)]
         #[allow(non_camel_case_types)]
-        enum #command_enum_name {
-            #(#command_enum_variants),*
+        enum #command_enum_name #generics #where_clause {
+            #(#command_enum_variants,)*
+            #batch_variant
+            #phantom_variant
+        }
+
+        impl #impl_generics std::fmt::Debug for #command_enum_name #ty_generics #debug_where_clause {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    #(#debug_arms)*
+                    #batch_debug_arm
+                    #phantom_debug_arm
+                }
+            }
         }
     };
 
     // ----------
 
+    let send = match &send_timeout_ms {
+        Some(ms) => quote! {
+            let timeout = std::time::Duration::from_millis(#ms);
+            if alictor::reexport::tokio::time::timeout(timeout, send).await.is_err() {
+                return alictor::MailboxFullSnafu { timeout }.fail();
+            }
+        },
+        None => quote! {
+            let _ = send.await;
+        },
+    };
+
+    // When tracing, the handle records that the actor was closed on
+    // purpose so that the run loop can tell an orderly shutdown from
+    // an unexpected one.
+    let traced = if trace { Some(ty.to_string()) } else { None };
+
+    // A wedged actor otherwise leaves every caller waiting forever. The
+    // command still runs; only its reply is abandoned.
+    let reply = match &timeout_ms {
+        Some(ms) => quote! {
+            let timeout = std::time::Duration::from_millis(#ms);
+            match alictor::reexport::tokio::time::timeout(timeout, rx).await {
+                Ok(r) => alictor::reexport::snafu::ResultExt::context(r, alictor::StoppedSnafu),
+                Err(_) => alictor::TimeoutSnafu { timeout }.fail(),
+            }
+        },
+        None => quote! {
+            alictor::reexport::snafu::ResultExt::context(rx.await, alictor::StoppedSnafu)
+        },
+    };
+
     let handle_methods = methods.iter().map(|m| {
-        let Method { name, arg_tys, .. } = m;
+        let Method { name, arg_tys, flatten, docs, .. } = m;
         let ret_ty = m.ret_ty();
         let arg_names = m.arg_names();
 
         let try_name = format_ident!("try_{}", name);
         let args: Vec<_> = arg_names.iter().zip(arg_tys).map(|(n, ty)| quote! { #n: #ty }).collect();
 
-        quote! {
-            pub async fn #try_name(&mut self, #(#args),*) -> Result<#ret_ty, alictor::ActorError> {
+        let send_command = quote! {
+            let (tx, rx) = alictor::reexport::futures::channel::oneshot::channel();
+
+            let send = alictor::reexport::futures::SinkExt::send(&mut self.0, #command_enum_name::#name(tx, #(#arg_names),*));
+
+            // Ignore send errors. If this send fails, so does the
+            // rx.await below. There's no reason to check for the
+            // same failure twice.
+            #send
+            #reply
+        };
+
+        // The caller's span covers waiting for room in the mailbox as
+        // well as for the reply, unlike the actor's own span
+        let send_command = match &traced {
+            Some(actor) => {
+                let command = name.to_string();
+                quote! { alictor::__traced_call!(#actor, #command, async { #send_command }) }
+            }
+            None => send_command,
+        };
+
+        // Fails instead of waiting when the mailbox is full, leaving
+        // it to the caller to decide if the reply is worth awaiting
+        let enqueue_name = format_ident!("enqueue_{}", name);
+        let enqueue = quote! {
+            #(#docs)*
+            pub fn #enqueue_name(&mut self, #(#args),*) -> Result<alictor::reexport::futures::channel::oneshot::Receiver<#ret_ty>, alictor::ActorError> {
                 let (tx, rx) = alictor::reexport::futures::channel::oneshot::channel();
 
-                // Ignore send errors. If this send fails, so does the
-                // rx.await below. There's no reason to check for the
-                // same failure twice.
-                let _ = alictor::reexport::futures::SinkExt::send(&mut self.0, #command_enum_name::#name(tx, #(#arg_names),*)).await;
-                alictor::reexport::snafu::ResultExt::context(rx.await, alictor::ActorSnafu)
+                self.0
+                    .try_send(#command_enum_name::#name(tx, #(#arg_names),*))
+                    .map_err(alictor::__try_send_error)?;
+                Ok(rx)
+            }
+        };
+
+        if *flatten {
+            // The plain method still panics when the actor is gone,
+            // so it needs to see the two layers separately.
+            let send_name = format_ident!("__send_{}", name);
+
+            quote! {
+                async fn #send_name(&mut self, #(#args),*) -> Result<#ret_ty, alictor::ActorError> {
+                    #send_command
+                }
+
+                #(#docs)*
+                pub async fn #try_name(&mut self, #(#args),*) -> #ret_ty {
+                    match self.#send_name(#(#arg_names),*).await {
+                        Ok(r) => r,
+                        Err(e) => Err(std::convert::From::from(e)),
+                    }
+                }
+
+                #(#docs)*
+                pub async fn #name(&mut self, #(#args),*) -> #ret_ty {
+                    self.#send_name(#(#arg_names),*).await.expect("Actor is no longer running")
+                }
+
+                #enqueue
             }
+        } else {
+            quote! {
+                #(#docs)*
+                pub async fn #try_name(&mut self, #(#args),*) -> Result<#ret_ty, alictor::ActorError> {
+                    #send_command
+                }
+
+                #(#docs)*
+                pub async fn #name(&mut self, #(#args),*) -> #ret_ty {
+                    self.#try_name(#(#arg_names),*).await.expect("Actor is no longer running")
+                }
 
-            pub async fn #name(&mut self, #(#args),*) -> #ret_ty {
-                self.#try_name(#(#arg_names),*).await.expect("Actor is no longer running")
+                #enqueue
             }
         }
     });
 
+    let (closed_field, closed_new, closed_init, closed_clone, closed_mark, closed_guard) = if trace
+    {
+        let ty_name = ty.to_string();
+        (
+            quote! { , std::sync::Arc<std::sync::atomic::AtomicBool> },
+            quote! { let __closed = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false)); },
+            quote! { , __closed.clone() },
+            quote! { , self.1.clone() },
+            quote! { self.1.store(true, std::sync::atomic::Ordering::SeqCst); },
+            quote! {
+                struct __Guard(std::sync::Arc<std::sync::atomic::AtomicBool>);
+
+                impl Drop for __Guard {
+                    fn drop(&mut self) {
+                        let closed = self.0.load(std::sync::atomic::Ordering::SeqCst);
+                        if !closed || std::thread::panicking() {
+                            alictor::__stopped_unexpectedly!(#ty_name);
+                        }
+                    }
+                }
+
+                let __guard = __Guard(__closed);
+            },
+        )
+    } else {
+        (
+            quote! {},
+            quote! {},
+            quote! {},
+            quote! {},
+            quote! {},
+            quote! {},
+        )
+    };
+
+    let (task_output, loop_exit) = match &fatal_error_ty {
+        Some(e) => (quote! { Result<(), #e> }, quote! { Ok(()) }),
+        None => (quote! { () }, quote! {}),
+    };
+
     let handle_name = format_ident!("{}Handle", ty);
+    let handle_name_str = handle_name.to_string();
     let handle = quote! {
-        #[derive(Debug, Clone)]
-        pub struct #handle_name(alictor::reexport::futures::channel::mpsc::Sender<#command_enum_name>);
+        pub struct #handle_name #generics (alictor::reexport::futures::channel::mpsc::Sender<#command_enum_name #ty_generics> #closed_field) #where_clause;
+
+        // Written by hand so that the generic parameters don't need
+        // to be `Clone` or `Debug`
+        impl #impl_generics Clone for #handle_name #ty_generics #where_clause {
+            fn clone(&self) -> Self {
+                Self(self.0.clone() #closed_clone)
+            }
+        }
 
-        impl #handle_name {
+        impl #impl_generics std::fmt::Debug for #handle_name #ty_generics #where_clause {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.debug_struct(#handle_name_str).finish_non_exhaustive()
+            }
+        }
+
+        impl #impl_generics #handle_name #ty_generics #where_clause {
             #(#handle_methods)*
+
+            // Closes the mailbox for every handle. Commands that were
+            // already queued are still processed before the actor
+            // exits.
+            pub fn close(&mut self) {
+                #closed_mark
+                self.0.close_channel();
+            }
+
+            // Closes the mailbox and hands back the actor's task so
+            // that the caller can wait for queued commands to finish
+            // instead of aborting them.
+            pub fn shutdown(
+                mut self,
+                task: alictor::reexport::tokio::task::JoinHandle<#task_output>,
+            ) -> alictor::reexport::tokio::task::JoinHandle<#task_output> {
+                self.close();
+                task
+            }
         }
     };
 
     // ----------
 
+    // The actor moves to another task, so it can't borrow anything
+    let spawn_bounds: Vec<_> = generics
+        .params
+        .iter()
+        .map(|p| match p {
+            syn::GenericParam::Lifetime(l) => {
+                let l = &l.lifetime;
+                quote! { #l: 'static, }
+            }
+            syn::GenericParam::Type(t) => {
+                let t = &t.ident;
+                quote! { #t: Send + 'static, }
+            }
+            syn::GenericParam::Const(_) => quote! {},
+        })
+        .collect();
+
+    let batch = batch_size.as_ref().map(|batch_size| {
+        let batch_name = format_ident!("{}Batch", ty);
+
+        let fire_and_forget: Vec<_> = methods.iter().filter(|m| m.ret_ty.is_none()).collect();
+        assert!(
+            !fire_and_forget.is_empty(),
+            "batch_size requires at least one method without a return value"
+        );
+
+        // The window starts with the first command of a batch. A timer
+        // task sends the batch if nothing else has by the time it ends.
+        let (timer_field, timer_init, timer_stop, timer_start) = match &batch_window_ms {
+            Some(ms) => (
+                quote! { timer: Option<alictor::reexport::tokio::task::JoinHandle<()>>, },
+                quote! { timer: None, },
+                quote! {
+                    if let Some(timer) = self.timer.take() {
+                        timer.abort();
+                    }
+                },
+                quote! {
+                    if len == 1 {
+                        let pending = std::sync::Arc::clone(&self.pending);
+                        let tx = self.handle.0.clone();
+                        self.timer = Some(alictor::reexport::tokio::task::spawn(async move {
+                            alictor::reexport::tokio::time::sleep(std::time::Duration::from_millis(#ms)).await;
+                            Self::__send_now(&pending, tx);
+                        }));
+                    }
+                },
+            ),
+            None => (quote! {}, quote! {}, quote! {}, quote! {}),
+        };
+
+        let batch_methods = fire_and_forget.iter().map(|m| {
+            let Method { name, arg_tys, .. } = m;
+            let arg_names = m.arg_names();
+
+            let args = arg_names
+                .iter()
+                .zip(arg_tys)
+                .map(|(n, ty)| quote! { #n: #ty });
+
+            quote! {
+                pub async fn #name(&mut self, #(#args),*) -> Result<(), alictor::ActorError>
+                where
+                    #(#spawn_bounds)*
+                {
+                    // Nobody waits for an individual reply; the batch
+                    // as a whole is confirmed.
+                    let (tx, _) = alictor::reexport::futures::channel::oneshot::channel();
+                    let len = {
+                        let mut pending = Self::__lock(&self.pending);
+                        pending.push(#command_enum_name::#name(tx, #(#arg_names),*));
+                        pending.len()
+                    };
+
+                    if len >= #batch_size {
+                        return self.flush().await;
+                    }
+                    #timer_start
+                    Ok(())
+                }
+            }
+        });
+
+        let ready = match &send_timeout_ms {
+            Some(ms) => quote! {
+                let timeout = std::time::Duration::from_millis(#ms);
+                if alictor::reexport::tokio::time::timeout(timeout, ready).await.is_err() {
+                    self.__restore(cmd);
+                    return alictor::MailboxFullSnafu { timeout }.fail();
+                }
+            },
+            None => quote! {
+                let _ = ready.await;
+            },
+        };
+
+        quote! {
+            #[derive(Debug)]
+            pub struct #batch_name #generics #where_clause {
+                handle: #handle_name #ty_generics,
+                pending: std::sync::Arc<std::sync::Mutex<Vec<#command_enum_name #ty_generics>>>,
+                #timer_field
+            }
+
+            impl #impl_generics #handle_name #ty_generics #where_clause {
+                pub fn batch(&self) -> #batch_name #ty_generics {
+                    #batch_name {
+                        handle: self.clone(),
+                        pending: Default::default(),
+                        #timer_init
+                    }
+                }
+            }
+
+            impl #impl_generics #batch_name #ty_generics #where_clause {
+                #(#batch_methods)*
+
+                // The commands that haven't been sent yet
+                pub fn len(&self) -> usize {
+                    Self::__lock(&self.pending).len()
+                }
+
+                pub fn is_empty(&self) -> bool {
+                    self.len() == 0
+                }
+
+                // When the commands can't be sent, they are kept for the
+                // next flush.
+                pub async fn flush(&mut self) -> Result<(), alictor::ActorError> {
+                    #timer_stop
+
+                    let commands = std::mem::take(&mut *Self::__lock(&self.pending));
+                    if commands.is_empty() {
+                        return Ok(());
+                    }
+
+                    let (tx, rx) = alictor::reexport::futures::channel::oneshot::channel();
+                    let cmd = #command_enum_name::__Batch(tx, commands);
+
+                    // Waiting for room before handing over the commands
+                    // means they are still ours if there isn't any.
+                    let sender = &mut self.handle.0;
+                    let ready = alictor::reexport::futures::future::poll_fn(|cx| sender.poll_ready(cx));
+                    #ready
+
+                    // A closed mailbox drops the reply sender along with
+                    // the commands, so the rx.await below fails.
+                    if let Err(e) = self.handle.0.try_send(cmd) {
+                        self.__restore(e.into_inner());
+                    }
+                    alictor::reexport::snafu::ResultExt::context(rx.await, alictor::StoppedSnafu)
+                }
+
+                fn __lock(
+                    pending: &std::sync::Mutex<Vec<#command_enum_name #ty_generics>>,
+                ) -> std::sync::MutexGuard<'_, Vec<#command_enum_name #ty_generics>> {
+                    pending.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+                }
+
+                // Ahead of anything added since, to keep the order
+                fn __restore(&mut self, cmd: #command_enum_name #ty_generics) {
+                    if let #command_enum_name::__Batch(_, mut commands) = cmd {
+                        let mut pending = Self::__lock(&self.pending);
+                        commands.append(&mut pending);
+                        *pending = commands;
+                    }
+                }
+
+                // Every sender has room for one message of its own, so a
+                // new one never has to wait.
+                fn __send_now(
+                    pending: &std::sync::Mutex<Vec<#command_enum_name #ty_generics>>,
+                    mut tx: alictor::reexport::futures::channel::mpsc::Sender<#command_enum_name #ty_generics>,
+                ) {
+                    let commands = std::mem::take(&mut *Self::__lock(pending));
+                    if !commands.is_empty() {
+                        let (reply, _) = alictor::reexport::futures::channel::oneshot::channel();
+                        let _ = tx.try_send(#command_enum_name::__Batch(reply, commands));
+                    }
+                }
+            }
+
+            // Commands that were never flushed are still sent
+            impl #impl_generics Drop for #batch_name #ty_generics #where_clause {
+                fn drop(&mut self) {
+                    #timer_stop
+                    Self::__send_now(&self.pending, self.handle.0.clone());
+                }
+            }
+        }
+    });
+
+    // ----------
+
+    // Sends many commands without waiting for any of their replies,
+    // only for room in the mailbox
+    let mut many_methods = vec![];
+    for method in &send_many {
+        let m = match methods.iter().find(|m| m.name == method) {
+            Some(m) => m,
+            None => {
+                return syn::Error::new_spanned(method, "The actor has no method with this name")
+                    .to_compile_error()
+                    .into()
+            }
+        };
+        let Method { name, arg_tys, .. } = m;
+        let arg_names = m.arg_names();
+        let many_name = format_ident!("{}_many", name);
+
+        let send_all = match &send_timeout_ms {
+            Some(ms) => quote! {
+                let timeout = std::time::Duration::from_millis(#ms);
+                match alictor::reexport::tokio::time::timeout(timeout, send).await {
+                    Ok(r) => r.map_err(alictor::__send_error),
+                    Err(_) => alictor::MailboxFullSnafu { timeout }.fail(),
+                }
+            },
+            None => quote! {
+                send.await.map_err(alictor::__send_error)
+            },
+        };
+
+        many_methods.push(quote! {
+            pub async fn #many_name(&mut self, items: Vec<(#(#arg_tys),*)>) -> Result<(), alictor::ActorError> {
+                let commands = items.into_iter().map(|(#(#arg_names),*)| {
+                    let (tx, _) = alictor::reexport::futures::channel::oneshot::channel();
+                    Ok(#command_enum_name::#name(tx, #(#arg_names),*))
+                });
+                let mut commands = alictor::reexport::futures::stream::iter(commands);
+
+                let send = alictor::reexport::futures::SinkExt::send_all(&mut self.0, &mut commands);
+                #send_all
+            }
+        });
+    }
+
+    let many = if many_methods.is_empty() {
+        None
+    } else {
+        Some(quote! {
+            impl #impl_generics #handle_name #ty_generics #where_clause {
+                #(#many_methods)*
+            }
+        })
+    };
+
+    // The compiler checks that the trait's methods match the actor's
+    // methods when it sees the `impl`.
+    let handle_trait_impl = handle_trait.map(|handle_trait| {
+        let trait_methods = methods.iter().map(|m| {
+            let Method { name, arg_tys, .. } = m;
+            let ret_ty = m.ret_ty();
+            let arg_names = m.arg_names();
+
+            let args = arg_names
+                .iter()
+                .zip(arg_tys)
+                .map(|(n, ty)| quote! { #n: #ty });
+
+            quote! {
+                async fn #name(&mut self, #(#args),*) -> #ret_ty {
+                    #handle_name::#name(self, #(#arg_names),*).await
+                }
+            }
+        });
+
+        quote! {
+            #[alictor::reexport::async_trait::async_trait]
+            impl #impl_generics #handle_trait for #handle_name #ty_generics #where_clause {
+                #(#trait_methods)*
+            }
+        }
+    });
+
+    // ----------
+
     let command_enum_variants = methods.iter().map(|m| {
-        let Method { name, .. } = m;
+        let Method { name, fatal, .. } = m;
         let arg_names = m.arg_names();
+        let call = m.call(blocking, traced.as_deref());
+
+        if *fatal {
+            quote! {
+                #command_enum_name::#name(__r, #(#arg_names),*) => {
+                    match #call {
+                        // If we couldn't respond, that's OK
+                        Ok(retval) => { let _ = __r.send(Ok(retval)); }
+                        // The caller sees that the actor stopped
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        } else {
+            quote! {
+                #command_enum_name::#name(__r, #(#arg_names),*) => {
+                    let retval = #call;
+
+                    // If we couldn't respond, that's OK
+                    let _ = __r.send(retval);
+                }
+            }
+        }
+    });
+
+    let command_enum_variants: Vec<_> = command_enum_variants.collect();
 
+    let batch_arm = batch_size.as_ref().map(|_| {
         quote! {
-            #command_enum_name::#name(__r, #(#arg_names),*) => {
-                let retval = self.#name(#(#arg_names),*);
+            #command_enum_name::__Batch(__r, cmds) => {
+                for cmd in cmds {
+                    match cmd {
+                        #(#command_enum_variants)*
+                        #command_enum_name::__Batch(..) => unreachable!("Batches are never nested"),
+                        #phantom_dispatch_arm
+                    }
+                }
 
                 // If we couldn't respond, that's OK
-                let _ = __r.send(retval);
+                let _ = __r.send(());
             }
         }
     });
@@ -161,36 +947,123 @@ pub fn alictor(
     let dispatch = quote! {
         match cmd {
             #(#command_enum_variants)*
+            #batch_arm
+            #phantom_dispatch_arm
         }
     };
 
+    let mut worker_method = None;
     let spawned_task = if blocking {
-        quote! {
-            alictor::reexport::tokio::task::spawn_blocking(move || {
-                let mut rx = alictor::reexport::futures::executor::block_on_stream(rx);
-                while let Some(cmd) = rx.next() {
-                    #dispatch
+        // Async methods need a runtime to run on
+        let needs_runtime = methods.iter().any(|m| m.blocking == Some(false));
+        let runtime = if needs_runtime {
+            quote! { let __rt = alictor::reexport::tokio::runtime::Handle::current(); }
+        } else {
+            quote! {}
+        };
+
+        if let Some(workers) = &workers {
+            let (runtime_param, runtime_arg) = if needs_runtime {
+                (
+                    quote! { , __rt: alictor::reexport::tokio::runtime::Handle },
+                    quote! { , __rt.clone() },
+                )
+            } else {
+                (quote! {}, quote! {})
+            };
+
+            // Each worker owns a clone of the actor and takes turns
+            // waiting for the next command, so commands may complete
+            // in a different order than they were sent.
+            worker_method = Some(quote! {
+                #[doc(hidden)]
+                #[allow(unused_mut)]
+                fn __alictor_work(
+                    mut self,
+                    rx: std::sync::Arc<std::sync::Mutex<alictor::reexport::futures::executor::BlockingStream<alictor::reexport::futures::channel::mpsc::Receiver<#command_enum_name #ty_generics>>>>
+                    #runtime_param
+                ) {
+                    loop {
+                        let cmd = rx.lock().unwrap_or_else(std::sync::PoisonError::into_inner).next();
+                        let cmd = match cmd {
+                            Some(cmd) => cmd,
+                            None => break,
+                        };
+                        #dispatch
+                    }
                 }
-            })
+            });
+
+            quote! {
+                #runtime
+                let rx = std::sync::Arc::new(std::sync::Mutex::new(
+                    alictor::reexport::futures::executor::block_on_stream(rx),
+                ));
+                let workers: Vec<_> = (0..#workers)
+                    .map(|_| {
+                        let actor = std::clone::Clone::clone(&self);
+                        let rx = rx.clone();
+                        alictor::reexport::tokio::task::spawn_blocking(move || actor.__alictor_work(rx #runtime_arg))
+                    })
+                    .collect();
+
+                // The actor stops once every worker has
+                alictor::reexport::tokio::task::spawn(async move {
+                    #closed_guard
+                    for worker in workers {
+                        if let Err(e) = worker.await {
+                            if let Ok(panic) = e.try_into_panic() {
+                                std::panic::resume_unwind(panic);
+                            }
+                        }
+                    }
+                })
+            }
+        } else {
+            quote! {
+                #runtime
+                alictor::reexport::tokio::task::spawn_blocking(move || {
+                    #closed_guard
+                    let mut rx = alictor::reexport::futures::executor::block_on_stream(rx);
+                    while let Some(cmd) = rx.next() {
+                        #dispatch
+                    }
+                    #loop_exit
+                })
+            }
         }
     } else {
         quote! {
             alictor::reexport::tokio::task::spawn(async move {
+                #closed_guard
                 let mut rx = rx;
                 while let Some(cmd) = alictor::reexport::futures::StreamExt::next(&mut rx).await {
                     #dispatch
                 }
+                #loop_exit
             })
         }
     };
 
+    // Every worker needs its own copy of the actor
+    let clone_bound = workers.as_ref().map(|_| quote! { Self: Clone, });
+
     let inherent_impl_spawn = quote! {
-        impl #ty {
-            pub fn spawn(#[allow(unused_mut)] mut self) -> (#handle_name, alictor::reexport::tokio::task::JoinHandle<()>) {
-                let (tx, rx) = alictor::reexport::futures::channel::mpsc::channel(10);
-                let child = #spawned_task;
-                (#handle_name(tx), child)
+        impl #impl_generics #self_ty #where_clause {
+            pub fn spawn(#[allow(unused_mut)] mut self) -> (#handle_name #ty_generics, alictor::reexport::tokio::task::JoinHandle<#task_output>)
+            where
+                #(#spawn_bounds)*
+                #clone_bound
+                Self: Send,
+            {
+                let (tx, rx) = alictor::reexport::futures::channel::mpsc::channel(#capacity);
+                #closed_new
+                let handle = #handle_name(tx #closed_init);
+                let child = { #spawned_task };
+                (handle, child)
             }
+
+            #worker_method
         }
     };
 
@@ -203,13 +1076,113 @@ pub fn alictor(
         #command_enum
 
         #handle
+        #handle_trait_impl
+        #batch
+        #many
     })
     .into()
 }
 
+// Any type named `Result`, including aliases like `io::Result<T>`,
+// is assumed to be a `std::result::Result`.
+fn is_result(ty: &syn::Type) -> bool {
+    let path = match ty {
+        syn::Type::Path(p) if p.qself.is_none() => &p.path,
+        _ => return false,
+    };
+
+    path.segments.last().map_or(false, |s| {
+        s.ident == "Result" && matches!(s.arguments, syn::PathArguments::AngleBracketed(_))
+    })
+}
+
+fn result_error_ty(ty: &syn::Type) -> Option<&syn::Type> {
+    let path = match ty {
+        syn::Type::Path(p) if p.qself.is_none() => &p.path,
+        _ => return None,
+    };
+
+    let segment = path.segments.last().filter(|s| s.ident == "Result")?;
+    let args = match &segment.arguments {
+        syn::PathArguments::AngleBracketed(a) if a.args.len() == 2 => &a.args,
+        _ => return None,
+    };
+
+    match args.last()? {
+        syn::GenericArgument::Type(t) => Some(t),
+        _ => None,
+    }
+}
+
+// The generated types are named after the actor, so we need a name
+// to build on. Any generics come from the `impl` instead.
+fn actor_name(self_ty: &syn::Type) -> Result<&syn::Ident, syn::Error> {
+    let path = match self_ty {
+        syn::Type::Path(p) if p.qself.is_none() => &p.path,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                self_ty,
+                "The actor must be a named struct or enum",
+            ))
+        }
+    };
+
+    let segment = path
+        .segments
+        .last()
+        .ok_or_else(|| syn::Error::new_spanned(path, "The actor must have a name"))?;
+
+    if segment.ident == "Self" {
+        return Err(syn::Error::new_spanned(
+            segment,
+            "`Self` does not name the actor; use the type's name instead",
+        ));
+    }
+
+    Ok(&segment.ident)
+}
+
+// Accepts a whole number of milliseconds, seconds, or minutes, such
+// as "250ms", "5s", or "1m"
+fn parse_duration_ms(lit: &syn::LitStr) -> Result<u64, syn::Error> {
+    let value = lit.value();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+
+    let scale = match unit {
+        "ms" => Some(1),
+        "s" => Some(1_000),
+        "m" => Some(60_000),
+        _ => None,
+    };
+
+    amount
+        .parse::<u64>()
+        .ok()
+        .zip(scale)
+        .and_then(|(amount, scale)| amount.checked_mul(scale))
+        .ok_or_else(|| {
+            syn::Error::new_spanned(lit, "Expected a duration such as \"5s\" or \"250ms\"")
+        })
+}
+
 mod kw {
     syn::custom_keyword!(kind);
     syn::custom_keyword!(blocking);
+    syn::custom_keyword!(handle_trait);
+    syn::custom_keyword!(send_timeout_ms);
+    syn::custom_keyword!(timeout);
+    syn::custom_keyword!(batch_size);
+    syn::custom_keyword!(batch_window_ms);
+    syn::custom_keyword!(trace);
+    syn::custom_keyword!(debug_args);
+    syn::custom_keyword!(flatten_results);
+    syn::custom_keyword!(capacity);
+    syn::custom_keyword!(workers);
+    syn::custom_keyword!(fatal);
+    syn::custom_keyword!(batch);
 }
 
 struct RawOptions(syn::punctuated::Punctuated<RawOption, syn::token::Comma>);
@@ -228,6 +1201,80 @@ enum RawOption {
         eq_token: syn::token::Eq,
         kind: RawKind,
     },
+    HandleTrait {
+        #[allow(unused)]
+        handle_trait_token: kw::handle_trait,
+        #[allow(unused)]
+        eq_token: syn::token::Eq,
+        path: syn::Path,
+    },
+    SendTimeoutMs {
+        #[allow(unused)]
+        send_timeout_ms_token: kw::send_timeout_ms,
+        #[allow(unused)]
+        eq_token: syn::token::Eq,
+        value: syn::LitInt,
+    },
+    Timeout {
+        #[allow(unused)]
+        timeout_token: kw::timeout,
+        #[allow(unused)]
+        eq_token: syn::token::Eq,
+        millis: u64,
+    },
+    BatchSize {
+        #[allow(unused)]
+        batch_size_token: kw::batch_size,
+        #[allow(unused)]
+        eq_token: syn::token::Eq,
+        value: syn::LitInt,
+    },
+    BatchWindowMs {
+        #[allow(unused)]
+        batch_window_ms_token: kw::batch_window_ms,
+        #[allow(unused)]
+        eq_token: syn::token::Eq,
+        value: syn::LitInt,
+    },
+    Trace {
+        #[allow(unused)]
+        trace_token: kw::trace,
+    },
+    DebugArgs {
+        #[allow(unused)]
+        debug_args_token: kw::debug_args,
+        #[allow(unused)]
+        eq_token: syn::token::Eq,
+        value: syn::LitBool,
+    },
+    FlattenResults {
+        #[allow(unused)]
+        flatten_results_token: kw::flatten_results,
+        #[allow(unused)]
+        eq_token: syn::token::Eq,
+        value: syn::LitBool,
+    },
+    Capacity {
+        #[allow(unused)]
+        capacity_token: kw::capacity,
+        #[allow(unused)]
+        eq_token: syn::token::Eq,
+        value: syn::LitInt,
+    },
+    Workers {
+        #[allow(unused)]
+        workers_token: kw::workers,
+        #[allow(unused)]
+        eq_token: syn::token::Eq,
+        value: syn::Expr,
+    },
+    Batch {
+        #[allow(unused)]
+        batch_token: kw::batch,
+        #[allow(unused)]
+        eq_token: syn::token::Eq,
+        method: syn::Ident,
+    },
 }
 
 impl syn::parse::Parse for RawOption {
@@ -239,6 +1286,93 @@ impl syn::parse::Parse for RawOption {
                 eq_token: input.parse()?,
                 kind: input.parse()?,
             })
+        } else if lookahead.peek(kw::handle_trait) {
+            Ok(Self::HandleTrait {
+                handle_trait_token: input.parse()?,
+                eq_token: input.parse()?,
+                path: input.parse()?,
+            })
+        } else if lookahead.peek(kw::send_timeout_ms) {
+            Ok(Self::SendTimeoutMs {
+                send_timeout_ms_token: input.parse()?,
+                eq_token: input.parse()?,
+                value: input.parse()?,
+            })
+        } else if lookahead.peek(kw::timeout) {
+            Ok(Self::Timeout {
+                timeout_token: input.parse()?,
+                eq_token: input.parse()?,
+                millis: parse_duration_ms(&input.parse()?)?,
+            })
+        } else if lookahead.peek(kw::batch_size) {
+            Ok(Self::BatchSize {
+                batch_size_token: input.parse()?,
+                eq_token: input.parse()?,
+                value: input.parse()?,
+            })
+        } else if lookahead.peek(kw::batch_window_ms) {
+            Ok(Self::BatchWindowMs {
+                batch_window_ms_token: input.parse()?,
+                eq_token: input.parse()?,
+                value: input.parse()?,
+            })
+        } else if lookahead.peek(kw::trace) {
+            Ok(Self::Trace {
+                trace_token: input.parse()?,
+            })
+        } else if lookahead.peek(kw::debug_args) {
+            Ok(Self::DebugArgs {
+                debug_args_token: input.parse()?,
+                eq_token: input.parse()?,
+                value: input.parse()?,
+            })
+        } else if lookahead.peek(kw::flatten_results) {
+            Ok(Self::FlattenResults {
+                flatten_results_token: input.parse()?,
+                eq_token: input.parse()?,
+                value: input.parse()?,
+            })
+        } else if lookahead.peek(kw::batch) {
+            Ok(Self::Batch {
+                batch_token: input.parse()?,
+                eq_token: input.parse()?,
+                method: input.parse()?,
+            })
+        } else if lookahead.peek(kw::capacity) {
+            Ok(Self::Capacity {
+                capacity_token: input.parse()?,
+                eq_token: input.parse()?,
+                value: input.parse()?,
+            })
+        } else if lookahead.peek(kw::workers) {
+            Ok(Self::Workers {
+                workers_token: input.parse()?,
+                eq_token: input.parse()?,
+                value: input.parse()?,
+            })
+        } else {
+            Err(lookahead.error())
+        }
+    }
+}
+
+enum RawMethodOption {
+    Kind(RawKind),
+    Fatal {
+        #[allow(unused)]
+        fatal_token: kw::fatal,
+    },
+}
+
+impl syn::parse::Parse for RawMethodOption {
+    fn parse(input: syn::parse::ParseStream<'_>) -> Result<Self, syn::Error> {
+        let lookahead = input.lookahead1();
+        if lookahead.peek(syn::token::Async) || lookahead.peek(kw::blocking) {
+            input.parse().map(Self::Kind)
+        } else if lookahead.peek(kw::fatal) {
+            Ok(Self::Fatal {
+                fatal_token: input.parse()?,
+            })
         } else {
             Err(lookahead.error())
         }
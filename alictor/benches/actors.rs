@@ -0,0 +1,140 @@
+//! Run with `cargo bench`. The interesting comparisons are:
+//!
+//! - `round_trip`: one command and its reply, for async and blocking
+//!   actors.
+//! - `throughput`: many fire-and-forget commands, sent one at a time
+//!   or through a batch. A batch shares one mailbox slot and one reply
+//!   channel between all of its commands.
+//!
+//! The batched runs are repeated for several `batch_size` values so
+//! that the cost of a command can be read off per size. Most of the
+//! cost of an individual command is the reply channel and the wakeup
+//! of the actor, so the batched time per command should fall roughly
+//! as `1 / batch_size` until it reaches the cost of running the
+//! command itself. Once `batched/<n>` is close to `batched/<10 * n>`,
+//! a larger `batch_size` only delays commands for no gain; pick the
+//! smallest size that gets there. If the `individual` run is already
+//! close to that floor, the reply channel is not the bottleneck and
+//! batching (or reusing the reply channel) will not help.
+//!
+//! Criterion keeps the results of each run under
+//! `target/criterion/throughput`. Record the numbers next to the
+//! `batch_size` they were taken with when changing it for an actor.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::runtime::Runtime;
+
+const COMMANDS: u64 = 1000;
+
+struct AsyncEcho;
+
+#[alictor::alictor]
+impl AsyncEcho {
+    fn echo(&mut self, value: u64) -> u64 {
+        value
+    }
+}
+
+struct BlockingEcho;
+
+#[alictor::alictor(kind = blocking)]
+impl BlockingEcho {
+    fn echo(&mut self, value: u64) -> u64 {
+        value
+    }
+}
+
+// `batch_size` must be a literal, so each size needs its own actor
+macro_rules! recorder {
+    ($name:ident, $batch_size:tt) => {
+        #[derive(Default)]
+        struct $name(u64);
+
+        #[alictor::alictor(batch_size = $batch_size)]
+        impl $name {
+            fn record(&mut self, value: u64) {
+                self.0 = self.0.wrapping_add(value);
+            }
+        }
+    };
+}
+
+recorder!(Recorder10, 10);
+recorder!(Recorder100, 100);
+recorder!(Recorder1000, 1000);
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Unable to build the runtime")
+}
+
+fn round_trip(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("round_trip");
+
+    let (async_echo, _) = rt.block_on(async { AsyncEcho.spawn() });
+    group.bench_function("async", |b| {
+        b.to_async(&rt).iter(|| {
+            let mut handle = async_echo.clone();
+            async move { handle.echo(42).await }
+        })
+    });
+
+    let (blocking_echo, _) = rt.block_on(async { BlockingEcho.spawn() });
+    group.bench_function("blocking", |b| {
+        b.to_async(&rt).iter(|| {
+            let mut handle = blocking_echo.clone();
+            async move { handle.echo(42).await }
+        })
+    });
+
+    group.finish();
+}
+
+fn throughput(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("throughput");
+    group.throughput(Throughput::Elements(COMMANDS));
+
+    let (recorder, _) = rt.block_on(async { Recorder10::default().spawn() });
+
+    group.bench_function("individual", |b| {
+        b.to_async(&rt).iter(|| {
+            let mut handle = recorder.clone();
+            async move {
+                for i in 0..COMMANDS {
+                    handle.record(i).await;
+                }
+            }
+        })
+    });
+
+    macro_rules! batched {
+        ($recorder:ident, $batch_size:expr) => {
+            let (recorder, _) = rt.block_on(async { $recorder::default().spawn() });
+
+            group.bench_function(BenchmarkId::new("batched", $batch_size), |b| {
+                b.to_async(&rt).iter(|| {
+                    let mut batch = recorder.batch();
+                    async move {
+                        for i in 0..COMMANDS {
+                            batch.record(i).await.expect("Actor is no longer running");
+                        }
+                        batch.flush().await.expect("Actor is no longer running");
+                    }
+                })
+            });
+        };
+    }
+
+    batched!(Recorder10, 10);
+    batched!(Recorder100, 100);
+    batched!(Recorder1000, 1000);
+
+    group.finish();
+}
+
+criterion_group!(benches, round_trip, throughput);
+criterion_main!(benches);
@@ -1,31 +1,81 @@
-use futures_channel::oneshot;
+use futures_channel::{
+    mpsc::{SendError, TrySendError},
+    oneshot,
+};
 use snafu::Snafu;
+use std::time::Duration;
 
 pub use alictor_derive::alictor;
 
 #[derive(Debug, Snafu)]
 #[snafu(visibility(pub))] // TODO: eh... maybe wrap the oneshot to avoid leaking?
-pub struct ActorError {
-    source: oneshot::Canceled,
+pub enum ActorError {
+    #[snafu(display("The actor is no longer running"))]
+    Stopped { source: oneshot::Canceled },
+
+    #[snafu(display("The actor's mailbox remained full for {:?}", timeout))]
+    MailboxFull { timeout: Duration },
+
+    #[snafu(display("The actor's mailbox is full"))]
+    MailboxBusy,
+
+    #[snafu(display("The actor's mailbox is closed"))]
+    MailboxClosed,
+
+    #[snafu(display("The actor did not reply within {:?}", timeout))]
+    Timeout { timeout: Duration },
+}
+
+// Used by the generated `*_many` methods
+#[doc(hidden)]
+pub fn __send_error(e: SendError) -> ActorError {
+    if e.is_full() {
+        ActorError::MailboxBusy
+    } else {
+        ActorError::MailboxClosed
+    }
+}
+
+// Used by the generated `enqueue_*` methods, which never wait for
+// room in the mailbox
+#[doc(hidden)]
+pub fn __try_send_error<T>(e: TrySendError<T>) -> ActorError {
+    if e.is_full() {
+        ActorError::MailboxBusy
+    } else {
+        ActorError::MailboxClosed
+    }
 }
 
 #[doc(hidden)]
 pub mod reexport {
+    pub mod async_trait {
+        pub use async_trait::async_trait;
+    }
+
     pub mod futures {
         pub use futures_util::{sink::SinkExt, stream::StreamExt};
 
+        pub mod future {
+            pub use futures_util::future::poll_fn;
+        }
+
+        pub mod stream {
+            pub use futures_util::stream::iter;
+        }
+
         pub mod channel {
             pub mod mpsc {
-                pub use futures_channel::mpsc::{channel, Sender};
+                pub use futures_channel::mpsc::{channel, Receiver, Sender};
             }
 
             pub mod oneshot {
-                pub use futures_channel::oneshot::{channel, Sender};
+                pub use futures_channel::oneshot::{channel, Receiver, Sender};
             }
         }
 
         pub mod executor {
-            pub use futures_executor::block_on_stream;
+            pub use futures_executor::{block_on_stream, BlockingStream};
         }
     }
 
@@ -34,8 +84,106 @@ pub mod reexport {
     }
 
     pub mod tokio {
+        pub mod runtime {
+            pub use tokio::runtime::Handle;
+        }
+
         pub mod task {
-            pub use tokio::task::{spawn, spawn_blocking, JoinHandle};
+            pub use tokio::task::{block_in_place, spawn, spawn_blocking, JoinHandle};
+        }
+
+        pub mod time {
+            pub use tokio::time::{sleep, timeout};
         }
     }
+
+    #[cfg(feature = "tracing")]
+    pub mod tracing {
+        pub use tracing::{error, trace_span, Instrument};
+    }
+}
+
+// These are used by actors with `trace` enabled. Without the `tracing`
+// feature they compile to nothing.
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(feature = "tracing")]
+macro_rules! __stopped_unexpectedly {
+    ($actor:expr) => {
+        $crate::reexport::tracing::error!(actor = $actor, "The actor stopped unexpectedly")
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(not(feature = "tracing"))]
+macro_rules! __stopped_unexpectedly {
+    ($actor:expr) => {{
+        let _ = $actor;
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(feature = "tracing")]
+macro_rules! __traced {
+    ($actor:expr, $command:expr, $call:expr) => {
+        $crate::reexport::tracing::trace_span!("command", actor = $actor, command = $command)
+            .in_scope(|| $call)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(not(feature = "tracing"))]
+macro_rules! __traced {
+    ($actor:expr, $command:expr, $call:expr) => {
+        $call
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(feature = "tracing")]
+macro_rules! __traced_async {
+    ($actor:expr, $command:expr, $call:expr) => {
+        $crate::reexport::tracing::Instrument::instrument(
+            $call,
+            $crate::reexport::tracing::trace_span!("command", actor = $actor, command = $command),
+        )
+        .await
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(not(feature = "tracing"))]
+macro_rules! __traced_async {
+    ($actor:expr, $command:expr, $call:expr) => {
+        $call.await
+    };
+}
+
+// Named after the command so that each handle method is its own span
+#[doc(hidden)]
+#[macro_export]
+#[cfg(feature = "tracing")]
+macro_rules! __traced_call {
+    ($actor:expr, $command:expr, $call:expr) => {
+        $crate::reexport::tracing::Instrument::instrument(
+            $call,
+            $crate::reexport::tracing::trace_span!($command, actor = $actor),
+        )
+        .await
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(not(feature = "tracing"))]
+macro_rules! __traced_call {
+    ($actor:expr, $command:expr, $call:expr) => {
+        $call.await
+    };
 }
@@ -0,0 +1,27 @@
+use std::time::Duration;
+
+struct Doubler {
+    delay: Duration,
+}
+
+// No `#[alictor(async)]` is needed for an `async fn`
+#[alictor::alictor]
+impl Doubler {
+    async fn double(&self, value: u32) -> u32 {
+        tokio::time::sleep(self.delay).await;
+        value * 2
+    }
+
+    fn delay(&self) -> Duration {
+        self.delay
+    }
+}
+
+#[tokio::test]
+async fn async_handlers_are_awaited() {
+    let delay = Duration::from_millis(1);
+    let (mut handle, _child) = Doubler { delay }.spawn();
+
+    assert_eq!(handle.double(21).await, 42);
+    assert_eq!(handle.delay().await, delay);
+}
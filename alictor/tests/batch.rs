@@ -0,0 +1,103 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+type Recorded = Arc<Mutex<Vec<u32>>>;
+
+struct Recorder(Recorded);
+
+#[alictor::alictor(batch_size = 3)]
+impl Recorder {
+    fn record(&mut self, value: u32) {
+        self.0.lock().unwrap().push(value);
+    }
+
+    fn recorded(&mut self) -> Vec<u32> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+struct Windowed(Recorded);
+
+#[alictor::alictor(batch_size = 100, batch_window_ms = 10)]
+impl Windowed {
+    fn record(&mut self, value: u32) {
+        self.0.lock().unwrap().push(value);
+    }
+}
+
+#[tokio::test]
+async fn batch_is_sent_when_full() {
+    let (mut handle, _child) = Recorder(Recorded::default()).spawn();
+    let mut batch = handle.batch();
+
+    batch.record(1).await.unwrap();
+    batch.record(2).await.unwrap();
+    assert_eq!(batch.len(), 2);
+    assert!(handle.recorded().await.is_empty());
+
+    batch.record(3).await.unwrap();
+    assert!(batch.is_empty());
+    assert_eq!(handle.recorded().await, [1, 2, 3]);
+}
+
+#[tokio::test]
+async fn batch_is_sent_when_flushed() {
+    let (mut handle, _child) = Recorder(Recorded::default()).spawn();
+    let mut batch = handle.batch();
+
+    batch.record(1).await.unwrap();
+    batch.flush().await.unwrap();
+    assert_eq!(handle.recorded().await, [1]);
+
+    // Nothing to send
+    batch.flush().await.unwrap();
+}
+
+#[tokio::test]
+async fn batch_is_sent_when_dropped() {
+    let (mut handle, _child) = Recorder(Recorded::default()).spawn();
+    let mut batch = handle.batch();
+
+    batch.record(1).await.unwrap();
+    batch.record(2).await.unwrap();
+    drop(batch);
+
+    assert_eq!(handle.recorded().await, [1, 2]);
+}
+
+#[tokio::test]
+async fn batch_is_sent_when_the_window_ends() {
+    let recorded = Recorded::default();
+    let (handle, _child) = Windowed(Arc::clone(&recorded)).spawn();
+    let mut batch = handle.batch();
+
+    batch.record(1).await.unwrap();
+    batch.record(2).await.unwrap();
+    assert!(recorded.lock().unwrap().is_empty());
+
+    // Without any further commands or a flush
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(*recorded.lock().unwrap(), [1, 2]);
+    assert!(batch.is_empty());
+}
+
+#[tokio::test]
+async fn batch_keeps_commands_that_could_not_be_sent() {
+    let (mut handle, _child) = Recorder(Recorded::default()).spawn();
+    let mut batch = handle.batch();
+    handle.close();
+
+    batch.record(1).await.unwrap();
+    let e = batch.flush().await.unwrap_err();
+    assert!(matches!(e, alictor::ActorError::Stopped { .. }), "{:?}", e);
+
+    batch.record(2).await.unwrap();
+    assert_eq!(batch.len(), 2);
+
+    // A full batch is sent right away, and fails the same way
+    let e = batch.record(3).await.unwrap_err();
+    assert!(matches!(e, alictor::ActorError::Stopped { .. }), "{:?}", e);
+    assert_eq!(batch.len(), 3);
+}
@@ -0,0 +1,24 @@
+use futures_util::FutureExt;
+
+struct Sink;
+
+// A zero timeout fails as soon as the mailbox is full
+#[alictor::alictor(capacity = 64, send_timeout_ms = 0)]
+impl Sink {
+    fn record(&mut self, value: usize) {
+        let _ = value;
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn mailbox_holds_the_configured_capacity() {
+    let (mut handle, _child) = Sink.spawn();
+
+    // The actor's task can't run until we yield, so every command
+    // stays in the mailbox. A command that was sent is left waiting
+    // for its reply; one that couldn't be sent fails.
+    for i in 0..64 {
+        let r = handle.try_record(i).now_or_never();
+        assert!(r.is_none(), "Command {} did not fit in the mailbox", i);
+    }
+}
@@ -0,0 +1,35 @@
+struct Echo;
+
+#[alictor::alictor(capacity = 1)]
+impl Echo {
+    fn echo(&mut self, value: u8) -> u8 {
+        value
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn enqueue_fails_fast_when_the_mailbox_is_full() {
+    let (mut handle, _child) = Echo.spawn();
+
+    // The actor can't run until we yield. Each sender also gets one
+    // guaranteed slot on top of the capacity.
+    let a = handle.enqueue_echo(1).unwrap();
+    let b = handle.enqueue_echo(2).unwrap();
+    let full = handle.enqueue_echo(3).unwrap_err();
+    assert!(
+        matches!(full, alictor::ActorError::MailboxBusy),
+        "{:?}",
+        full
+    );
+
+    assert_eq!(a.await.unwrap(), 1);
+    assert_eq!(b.await.unwrap(), 2);
+
+    handle.close();
+    let closed = handle.enqueue_echo(4).unwrap_err();
+    assert!(
+        matches!(closed, alictor::ActorError::MailboxClosed),
+        "{:?}",
+        closed
+    );
+}
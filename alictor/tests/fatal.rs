@@ -0,0 +1,45 @@
+#[derive(Debug, PartialEq)]
+struct Broken;
+
+struct Machine(u32);
+
+#[alictor::alictor(flatten_results = false)]
+impl Machine {
+    fn bump(&mut self) -> u32 {
+        self.0 += 1;
+        self.0
+    }
+
+    #[alictor(fatal)]
+    fn check(&mut self, limit: u32) -> Result<u32, Broken> {
+        if self.0 > limit {
+            Err(Broken)
+        } else {
+            Ok(self.0)
+        }
+    }
+}
+
+#[tokio::test]
+async fn fatal_error_stops_the_actor() {
+    let (mut handle, child) = Machine(0).spawn();
+
+    assert_eq!(handle.bump().await, 1);
+    assert_eq!(handle.check(5).await, Ok(1));
+    assert_eq!(handle.bump().await, 2);
+
+    assert!(handle.try_check(1).await.is_err());
+    assert!(handle.try_bump().await.is_err());
+
+    assert_eq!(child.await.unwrap(), Err(Broken));
+}
+
+#[tokio::test]
+async fn closing_the_actor_is_not_fatal() {
+    let (mut handle, child) = Machine(0).spawn();
+
+    assert_eq!(handle.bump().await, 1);
+    handle.close();
+
+    assert_eq!(child.await.unwrap(), Ok(()));
+}
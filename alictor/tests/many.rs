@@ -0,0 +1,39 @@
+use std::sync::{Arc, Mutex};
+
+struct Spawner(Arc<Mutex<Vec<(u32, String)>>>);
+
+#[alictor::alictor(batch = start_polling)]
+impl Spawner {
+    fn start_polling(&mut self, id: u32, token: String) {
+        self.0.lock().unwrap().push((id, token));
+    }
+
+    fn count(&mut self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+}
+
+#[tokio::test]
+async fn many_sends_every_item_in_order() {
+    let started = Arc::default();
+    let (mut handle, _child) = Spawner(Arc::clone(&started)).spawn();
+
+    let items: Vec<_> = (0..25).map(|i| (i, format!("token-{}", i))).collect();
+    handle.start_polling_many(items.clone()).await.unwrap();
+
+    // Commands are processed in order, so this waits for the others
+    assert_eq!(handle.count().await, 25);
+    assert_eq!(*started.lock().unwrap(), items);
+}
+
+#[tokio::test]
+async fn many_fails_when_the_actor_is_closed() {
+    let (mut handle, _child) = Spawner(Arc::default()).spawn();
+    handle.close();
+
+    let e = handle
+        .start_polling_many(vec![(1, "token".into())])
+        .await
+        .unwrap_err();
+    assert!(matches!(e, alictor::ActorError::MailboxClosed), "{:?}", e);
+}
@@ -0,0 +1,27 @@
+use std::time::Duration;
+
+struct Sleeper;
+
+#[alictor::alictor(timeout = "10ms")]
+impl Sleeper {
+    #[alictor(async)]
+    async fn sleep(&self, duration: Duration) -> Duration {
+        tokio::time::sleep(duration).await;
+        duration
+    }
+}
+
+#[tokio::test]
+async fn slow_replies_time_out() {
+    let (mut handle, _child) = Sleeper.spawn();
+
+    let r = handle.try_sleep(Duration::ZERO).await;
+    assert_eq!(r.unwrap(), Duration::ZERO);
+
+    let r = handle.try_sleep(Duration::from_secs(1)).await;
+    assert!(
+        matches!(r, Err(alictor::ActorError::Timeout { .. })),
+        "{:?}",
+        r
+    );
+}
@@ -0,0 +1,105 @@
+#![cfg(feature = "tracing")]
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Metadata, Subscriber,
+};
+
+struct Counter(u32);
+
+#[alictor::alictor(trace)]
+impl Counter {
+    fn increment(&mut self) -> u32 {
+        self.0 += 1;
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Span {
+    name: &'static str,
+    actor: Option<String>,
+    command: Option<String>,
+}
+
+impl Visit for Span {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "actor" => self.actor = Some(value.into()),
+            "command" => self.command = Some(value.into()),
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value))
+    }
+}
+
+// Remembers every span that was entered
+#[derive(Debug, Clone, Default)]
+struct Recorder {
+    spans: Arc<Mutex<Vec<Span>>>,
+    entered: Arc<Mutex<Vec<Span>>>,
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, attrs: &span::Attributes<'_>) -> span::Id {
+        let mut span = Span {
+            name: attrs.metadata().name(),
+            ..Span::default()
+        };
+        attrs.record(&mut span);
+
+        let mut spans = self.spans.lock().unwrap();
+        spans.push(span);
+        span::Id::from_u64(spans.len() as u64)
+    }
+
+    fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+    fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+    fn event(&self, _: &Event<'_>) {}
+
+    fn enter(&self, id: &span::Id) {
+        let span = self.spans.lock().unwrap()[id.into_u64() as usize - 1].clone();
+        self.entered.lock().unwrap().push(span);
+    }
+
+    fn exit(&self, _: &span::Id) {}
+}
+
+#[tokio::test]
+async fn calls_are_traced_by_the_handle_and_the_actor() {
+    let recorder = Recorder::default();
+    // The actor's task runs on this thread too
+    let _guard = tracing::subscriber::set_default(recorder.clone());
+
+    let (mut handle, _child) = Counter(0).spawn();
+    assert_eq!(handle.increment().await, 1);
+
+    let entered = recorder.entered.lock().unwrap();
+
+    let caller = Span {
+        name: "increment",
+        actor: Some("Counter".into()),
+        command: None,
+    };
+    assert!(entered.contains(&caller), "{:?}", entered);
+
+    let actor = Span {
+        name: "command",
+        actor: Some("Counter".into()),
+        command: Some("increment".into()),
+    };
+    assert!(entered.contains(&actor), "{:?}", entered);
+}
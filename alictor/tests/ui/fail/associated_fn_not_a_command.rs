@@ -0,0 +1,24 @@
+struct Counter(u32);
+
+#[alictor::alictor]
+impl Counter {
+    fn make_default() -> Self {
+        Counter(0)
+    }
+
+    fn increment(&mut self) {
+        self.0 += 1;
+    }
+
+    fn value(&mut self) -> u32 {
+        self.0
+    }
+}
+
+async fn use_handle(mut handle: CounterHandle) {
+    handle.make_default().await;
+}
+
+fn main() {
+    let _ = use_handle;
+}
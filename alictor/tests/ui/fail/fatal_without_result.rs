@@ -0,0 +1,11 @@
+struct Machine;
+
+#[alictor::alictor]
+impl Machine {
+    #[alictor(fatal)]
+    fn check(&mut self) -> u32 {
+        0
+    }
+}
+
+fn main() {}
@@ -0,0 +1,16 @@
+struct Foo<T>(T);
+
+#[alictor::alictor]
+impl<T> Foo<T>
+where
+    T: Clone + Send + 'static,
+{
+    fn value(&mut self) -> T {
+        self.0.clone()
+    }
+}
+
+// The handle keeps the actor's bounds
+fn unbounded<T>(_: FooHandle<T>) {}
+
+fn main() {}
@@ -0,0 +1,19 @@
+use alictor::reexport::async_trait::async_trait;
+
+struct Counter(u32);
+
+#[async_trait]
+trait Count {
+    async fn increment(&mut self, by: u64) -> u32;
+}
+
+// The handle's methods must match the trait's
+#[alictor::alictor(handle_trait = Count)]
+impl Counter {
+    fn increment(&mut self, by: u32) -> u32 {
+        self.0 += by;
+        self.0
+    }
+}
+
+fn main() {}
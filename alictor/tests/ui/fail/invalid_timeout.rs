@@ -0,0 +1,10 @@
+struct Counter(u32);
+
+#[alictor::alictor(timeout = "5 minutes")]
+impl Counter {
+    fn count(&mut self) -> u32 {
+        self.0
+    }
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+struct Counter(u32);
+
+#[alictor::alictor]
+impl Counter {
+    fn count(&mut self, by: u32, self) -> u32 {
+        self.0 + by
+    }
+}
+
+fn main() {}
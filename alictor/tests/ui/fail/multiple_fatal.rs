@@ -0,0 +1,16 @@
+struct Machine;
+
+#[alictor::alictor]
+impl Machine {
+    #[alictor(fatal)]
+    fn first(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    #[alictor(fatal)]
+    fn second(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+fn main() {}
@@ -0,0 +1,6 @@
+#[alictor::alictor]
+impl Self {
+    fn count(&mut self) {}
+}
+
+fn main() {}
@@ -0,0 +1,6 @@
+#[alictor::alictor]
+impl (u8, u8) {
+    fn count(&mut self) {}
+}
+
+fn main() {}
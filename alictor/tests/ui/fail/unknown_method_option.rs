@@ -0,0 +1,11 @@
+struct Counter(u32);
+
+#[alictor::alictor]
+impl Counter {
+    #[alictor(sync)]
+    fn count(&mut self) -> u32 {
+        self.0
+    }
+}
+
+fn main() {}
@@ -0,0 +1,26 @@
+struct Counter(u32);
+
+#[alictor::alictor]
+impl Counter {
+    fn make_default() -> Self {
+        Counter(0)
+    }
+
+    fn increment(&mut self) {
+        self.0 += 1;
+    }
+
+    fn value(&mut self) -> u32 {
+        self.0
+    }
+}
+
+async fn use_handle(mut handle: CounterHandle) -> u32 {
+    handle.increment().await;
+    handle.value().await
+}
+
+fn main() {
+    let _ = Counter::make_default().spawn();
+    let _ = use_handle;
+}
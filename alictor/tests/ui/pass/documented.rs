@@ -0,0 +1,17 @@
+#![deny(missing_docs)]
+
+//! Handle methods carry the actor's documentation
+
+/// Counts things
+pub struct Counter(u32);
+
+#[alictor::alictor]
+impl Counter {
+    /// Adds one to the count
+    #[inline]
+    pub fn increment(&mut self) {
+        self.0 += 1;
+    }
+}
+
+fn main() {}
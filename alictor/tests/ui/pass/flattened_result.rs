@@ -0,0 +1,30 @@
+#[derive(Debug)]
+enum Error {
+    Actor(alictor::ActorError),
+}
+
+impl From<alictor::ActorError> for Error {
+    fn from(e: alictor::ActorError) -> Self {
+        Self::Actor(e)
+    }
+}
+
+struct Counter(u32);
+
+#[alictor::alictor]
+impl Counter {
+    fn increment(&mut self) -> Result<u32, Error> {
+        self.0 += 1;
+        Ok(self.0)
+    }
+}
+
+async fn use_handle(mut handle: CounterHandle) -> Result<u32, Error> {
+    let a = handle.try_increment().await?;
+    let b = handle.increment().await?;
+    Ok(a + b)
+}
+
+fn main() {
+    let _ = use_handle;
+}
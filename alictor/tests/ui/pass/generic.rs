@@ -0,0 +1,40 @@
+struct Foo<'a, T> {
+    label: &'a str,
+    value: T,
+}
+
+#[alictor::alictor]
+impl<'a, T> Foo<'a, T>
+where
+    T: Clone + Send + 'static,
+{
+    fn label(&mut self) -> String {
+        self.label.to_string()
+    }
+
+    fn value(&mut self) -> T {
+        self.value.clone()
+    }
+}
+
+struct Counter<T>(T);
+
+#[alictor::alictor]
+impl Counter<u8> {
+    fn count(&mut self) -> u8 {
+        self.0
+    }
+}
+
+async fn use_handle<'a, T>(mut handle: FooHandle<'a, T>) -> T
+where
+    T: Clone + Send + 'static,
+{
+    let _: String = handle.clone().label().await;
+    handle.value().await
+}
+
+fn main() {
+    let _ = use_handle::<u8>;
+    let _ = |h: CounterHandle| h;
+}
@@ -0,0 +1,24 @@
+use alictor::reexport::async_trait::async_trait;
+
+struct Counter(u32);
+
+#[async_trait]
+trait Count {
+    async fn increment(&mut self, by: u32) -> u32;
+}
+
+#[alictor::alictor(handle_trait = Count)]
+impl Counter {
+    fn increment(&mut self, by: u32) -> u32 {
+        self.0 += by;
+        self.0
+    }
+}
+
+async fn use_trait_object(mut count: Box<dyn Count + Send>) -> u32 {
+    count.increment(1).await
+}
+
+fn main() {
+    let _ = |h: CounterHandle| use_trait_object(Box::new(h));
+}
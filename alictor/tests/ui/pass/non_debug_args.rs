@@ -0,0 +1,12 @@
+struct NotDebug;
+
+struct Consumer;
+
+#[alictor::alictor(debug_args = false)]
+impl Consumer {
+    fn consume(&mut self, value: NotDebug) {
+        let _ = value;
+    }
+}
+
+fn main() {}
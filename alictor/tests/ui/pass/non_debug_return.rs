@@ -0,0 +1,13 @@
+struct NotDebug;
+
+struct Maker;
+
+#[alictor::alictor]
+impl Maker {
+    fn make(&mut self, label: String) -> NotDebug {
+        let _ = label;
+        NotDebug
+    }
+}
+
+fn main() {}
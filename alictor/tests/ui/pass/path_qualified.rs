@@ -0,0 +1,13 @@
+mod actors {
+    pub struct Counter(pub u64);
+}
+
+#[alictor::alictor]
+impl actors::Counter {
+    fn increment(&mut self) -> u64 {
+        self.0 += 1;
+        self.0
+    }
+}
+
+fn main() {}
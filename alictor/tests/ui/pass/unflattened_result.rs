@@ -0,0 +1,17 @@
+struct Counter(u32);
+
+#[alictor::alictor(flatten_results = false)]
+impl Counter {
+    fn increment(&mut self) -> Result<u32, std::num::TryFromIntError> {
+        self.0 += 1;
+        Ok(self.0)
+    }
+}
+
+async fn use_handle(mut handle: CounterHandle) -> u32 {
+    handle.try_increment().await.unwrap().unwrap()
+}
+
+fn main() {
+    let _ = use_handle;
+}
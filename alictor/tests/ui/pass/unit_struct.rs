@@ -0,0 +1,12 @@
+struct Pinger;
+
+#[alictor::alictor]
+impl Pinger {
+    const REPLY: &'static str = "pong";
+
+    fn ping(&mut self) -> &'static str {
+        Self::REPLY
+    }
+}
+
+fn main() {}
@@ -0,0 +1,30 @@
+use std::{
+    sync::{Arc, Barrier},
+    time::Duration,
+};
+
+#[derive(Clone)]
+struct Slow(Arc<Barrier>);
+
+// Both commands must be running at once for either to get past the
+// barrier
+#[alictor::alictor(kind = blocking, workers = 2)]
+impl Slow {
+    fn wait(&mut self) {
+        self.0.wait();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn workers_run_commands_concurrently() {
+    let (handle, child) = Slow(Arc::new(Barrier::new(2))).spawn();
+
+    let mut a = handle.clone();
+    let mut b = handle.clone();
+    let both = futures_util::future::join(a.wait(), b.wait());
+    tokio::time::timeout(Duration::from_secs(5), both)
+        .await
+        .expect("Commands were run one at a time");
+
+    handle.shutdown(child).await.unwrap();
+}
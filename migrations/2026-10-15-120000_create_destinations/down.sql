@@ -0,0 +1,13 @@
+CREATE TABLE pushover_users (
+  key TEXT PRIMARY KEY,
+  account_id INTEGER NOT NULL REFERENCES registrations (account_id) UNIQUE
+);
+
+INSERT INTO pushover_users (key, account_id)
+SELECT DISTINCT ON (account_id) config, account_id
+FROM destinations
+WHERE kind = 'pushover'
+ORDER BY account_id, enabled DESC, id DESC
+ON CONFLICT DO NOTHING;
+
+DROP TABLE destinations;
@@ -0,0 +1,14 @@
+CREATE TABLE destinations (
+  id SERIAL PRIMARY KEY,
+  account_id INTEGER NOT NULL REFERENCES registrations (account_id),
+  kind TEXT NOT NULL,
+  config TEXT NOT NULL,
+  enabled BOOLEAN NOT NULL DEFAULT TRUE,
+
+  UNIQUE (account_id, kind, config)
+);
+
+INSERT INTO destinations (account_id, kind, config)
+SELECT account_id, 'pushover', key FROM pushover_users;
+
+DROP TABLE pushover_users;
@@ -0,0 +1,3 @@
+ALTER TABLE notifications ADD COLUMN delivered BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE notifications SET delivered = TRUE;
@@ -0,0 +1 @@
+ALTER TABLE registrations DROP COLUMN last_polled_at;
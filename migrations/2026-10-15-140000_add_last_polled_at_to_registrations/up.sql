@@ -0,0 +1 @@
+ALTER TABLE registrations ADD COLUMN last_polled_at TIMESTAMPTZ;
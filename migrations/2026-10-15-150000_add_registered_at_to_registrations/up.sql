@@ -0,0 +1 @@
+ALTER TABLE registrations ADD COLUMN registered_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
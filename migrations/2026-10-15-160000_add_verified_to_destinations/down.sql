@@ -0,0 +1 @@
+ALTER TABLE destinations DROP COLUMN verified;
@@ -0,0 +1,4 @@
+ALTER TABLE destinations ADD COLUMN verified BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Destinations configured before verification existed were already in use
+UPDATE destinations SET verified = TRUE;
@@ -0,0 +1 @@
+ALTER TABLE registrations ADD COLUMN needs_reauth BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,5 @@
+CREATE TABLE sessions (
+  id BYTEA PRIMARY KEY,
+  oauth_state TEXT,
+  account_id INTEGER
+);
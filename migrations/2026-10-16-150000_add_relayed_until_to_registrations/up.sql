@@ -0,0 +1 @@
+ALTER TABLE registrations ADD COLUMN relayed_until TIMESTAMPTZ;
@@ -0,0 +1,11 @@
+ALTER TABLE notifications DROP CONSTRAINT notifications_account_id_notification_type_post_id_text_key;
+
+-- The narrower key no longer holds for notifications that only
+-- differed by type or post
+DELETE FROM notifications a
+  USING notifications b
+  WHERE a.account_id = b.account_id AND a.text = b.text AND a.id > b.id;
+ALTER TABLE notifications ADD CONSTRAINT notifications_account_id_text_key UNIQUE (account_id, text);
+
+ALTER TABLE notifications DROP COLUMN post_id;
+ALTER TABLE notifications DROP COLUMN notification_type;
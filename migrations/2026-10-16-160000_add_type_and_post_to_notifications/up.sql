@@ -0,0 +1,7 @@
+ALTER TABLE notifications ADD COLUMN notification_type TEXT NOT NULL DEFAULT '';
+-- Unique constraints never consider NULLs equal, so a missing post is 0
+ALTER TABLE notifications ADD COLUMN post_id BIGINT NOT NULL DEFAULT 0;
+
+ALTER TABLE notifications DROP CONSTRAINT notifications_account_id_text_key;
+ALTER TABLE notifications ADD CONSTRAINT notifications_account_id_notification_type_post_id_text_key
+  UNIQUE (account_id, notification_type, post_id, text);
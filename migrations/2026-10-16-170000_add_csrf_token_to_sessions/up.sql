@@ -0,0 +1 @@
+ALTER TABLE sessions ADD COLUMN csrf_token TEXT;
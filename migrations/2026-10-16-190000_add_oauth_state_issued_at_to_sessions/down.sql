@@ -0,0 +1 @@
+ALTER TABLE sessions DROP COLUMN oauth_state_issued_at;
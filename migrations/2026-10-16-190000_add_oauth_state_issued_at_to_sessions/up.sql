@@ -0,0 +1,2 @@
+ALTER TABLE sessions ADD COLUMN oauth_state_issued_at TIMESTAMPTZ;
+UPDATE sessions SET oauth_state_issued_at = last_seen_at WHERE oauth_state IS NOT NULL;
@@ -1,52 +1,309 @@
-use snafu::{ResultExt, Snafu};
+use crate::domain::{DeliveryPolicy, PollSources};
+use snafu::{ensure, ResultExt, Snafu};
 use std::{
     env,
     net::{IpAddr, SocketAddr},
     time::Duration,
 };
+use tracing::warn;
+use tracing_subscriber::EnvFilter;
 use url::Url;
 
+const DEFAULT_LOG_FILTER: &str = "info,stack_overflow_relay=debug";
+const DEFAULT_DATABASE_STATEMENT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_MAINTENANCE_STATEMENT_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+const DEFAULT_CONTENT_LANGUAGE: &str = "en";
+const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(60);
+// Polling any faster quickly uses up the daily API quota
+const MIN_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+pub fn log_filter() -> Result<EnvFilter> {
+    let filter = env::var("RUST_LOG")
+        .or_else(|_| env::var("LOG_FILTER"))
+        .unwrap_or_else(|_| DEFAULT_LOG_FILTER.into());
+
+    EnvFilter::try_new(&filter).context(InvalidLogFilterSnafu { filter })
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+pub fn log_format() -> Result<LogFormat> {
+    match env::var("LOG_FORMAT") {
+        Ok(format) => match &*format {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => InvalidLogFormatSnafu { format }.fail(),
+        },
+        Err(_) => Ok(LogFormat::Text),
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub database_url: String,
+    pub database_statement_timeout: Duration,
+    pub poll_interval: Duration,
+    pub poll_start_jitter: Duration,
     pub public_uri: Url,
     pub listen_address: SocketAddr,
+    pub internal_listen_address: Option<SocketAddr>,
     pub caffeine_interval: Option<Duration>,
+    pub maintenance_interval: Option<Duration>,
+    pub maintenance_statement_timeout: Duration,
+    pub behind_proxy: bool,
+    pub secure_cookies: bool,
+    pub session_ttl: Duration,
+    pub delivery_policy: DeliveryPolicy,
+    pub skip_backlog: bool,
+    pub poll_sources: PollSources,
+    pub single_session: bool,
+    pub notification_template: String,
+    pub content_language: String,
+    pub admin_token: Option<String>,
 }
 
 impl Config {
     pub fn from_environment() -> Result<Self> {
         let database_url = env::var("DATABASE_URL").context(UnknownDatabaseUrlSnafu)?;
+        let statement_timeout = env::var("DATABASE_STATEMENT_TIMEOUT").ok();
+        let poll_interval = env::var("POLL_INTERVAL_SECONDS").ok();
+        let poll_start_jitter = env::var("POLL_START_JITTER_SECONDS").ok();
         let uri = env::var("WEB_PUBLIC_URI").context(UnknownWebPublicUriSnafu)?;
         let address = env::var("WEB_LISTEN_ADDRESS").context(UnknownWebListenAddressSnafu)?;
         let port = env::var("WEB_LISTEN_PORT").or_else(|_| env::var("PORT"));
         let port = port.context(UnknownWebListenPortSnafu)?;
+        let internal_address = env::var("INTERNAL_LISTEN_ADDRESS").ok();
         let caffeine_interval = env::var("PREVENT_HEROKU_SLEEP").ok();
+        let maintenance_interval = env::var("DATABASE_MAINTENANCE_INTERVAL").ok();
+        let maintenance_timeout = env::var("DATABASE_MAINTENANCE_STATEMENT_TIMEOUT").ok();
+        let behind_proxy = env::var_os("WEB_BEHIND_PROXY").is_some();
+        // Browsers won't send `Secure` cookies to http://localhost
+        let secure_cookies = env::var_os("WEB_INSECURE_COOKIES").is_none();
+        let session_ttl = env::var("SESSION_TTL_SECONDS").ok();
+        let delivery_policy = env::var("DELIVERY_POLICY").ok();
+        let skip_backlog = env::var_os("SKIP_BACKLOG_ON_SIGNUP").is_some();
+        let poll_sources = PollSources {
+            notifications: toggle_from_environment("POLL_NOTIFICATIONS")?,
+            inbox: toggle_from_environment("POLL_INBOX")?,
+        };
+        ensure!(
+            poll_sources.notifications || poll_sources.inbox,
+            NothingToPollSnafu
+        );
+        let single_session = env::var_os("SINGLE_SESSION").is_some();
+        let notification_template = env::var("NOTIFICATION_TEMPLATE")
+            .unwrap_or_else(|_| crate::template::DEFAULT_TEMPLATE.into());
+        let content_language =
+            env::var("CONTENT_LANGUAGE").unwrap_or_else(|_| DEFAULT_CONTENT_LANGUAGE.into());
+        let admin_token = env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty());
 
+        let database_statement_timeout = statement_timeout
+            .map(|timeout| {
+                timeout
+                    .parse()
+                    .context(InvalidDatabaseStatementTimeoutSnafu { timeout })
+            })
+            .transpose()?
+            .map_or(DEFAULT_DATABASE_STATEMENT_TIMEOUT, Duration::from_secs);
+        let maintenance_statement_timeout = maintenance_timeout
+            .map(|timeout| {
+                timeout
+                    .parse()
+                    .context(InvalidMaintenanceStatementTimeoutSnafu { timeout })
+            })
+            .transpose()?
+            .map_or(DEFAULT_MAINTENANCE_STATEMENT_TIMEOUT, Duration::from_secs);
+        let session_ttl = session_ttl
+            .map(|ttl| ttl.parse().context(InvalidSessionTtlSnafu { ttl }))
+            .transpose()?
+            .map_or(DEFAULT_SESSION_TTL, Duration::from_secs);
+        let poll_interval = poll_interval
+            .map(|interval| {
+                interval
+                    .parse()
+                    .context(InvalidPollIntervalSnafu { interval })
+            })
+            .transpose()?
+            .map_or(DEFAULT_POLL_INTERVAL, Duration::from_secs);
+        let poll_interval = if poll_interval < MIN_POLL_INTERVAL {
+            warn!(
+                "POLL_INTERVAL_SECONDS of {:?} is too short; using {:?} instead",
+                poll_interval, MIN_POLL_INTERVAL,
+            );
+            MIN_POLL_INTERVAL
+        } else {
+            poll_interval
+        };
+        // Spread over a whole interval unless told otherwise
+        let poll_start_jitter = poll_start_jitter
+            .map(|jitter| {
+                jitter
+                    .parse()
+                    .context(InvalidPollStartJitterSnafu { jitter })
+            })
+            .transpose()?
+            .map_or(poll_interval, Duration::from_secs);
         let public_uri = Url::parse(&uri).context(InvalidWebPublicUriSnafu { uri })?;
         let address: IpAddr = address
             .parse()
             .context(InvalidWebListenAddressSnafu { address })?;
         let port = port.parse().context(InvalidWebListenPortSnafu { port })?;
         let listen_address = (address, port).into();
+        let internal_listen_address = internal_address
+            .map(|address| {
+                address
+                    .parse()
+                    .context(InvalidInternalListenAddressSnafu { address })
+            })
+            .transpose()?;
         let caffeine_interval = caffeine_interval
             .and_then(|i| i.parse().ok())
             .map(Duration::from_secs);
+        let maintenance_interval = maintenance_interval
+            .and_then(|i| i.parse().ok())
+            .map(Duration::from_secs);
+        let delivery_policy = match delivery_policy.as_deref() {
+            None | Some("any") => DeliveryPolicy::Any,
+            Some("all") => DeliveryPolicy::All,
+            Some(policy) => return InvalidDeliveryPolicySnafu { policy }.fail(),
+        };
+        // Language tags, optionally as a comma-separated list
+        ensure!(
+            !content_language.trim().is_empty()
+                && content_language
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | ',' | ' ')),
+            InvalidContentLanguageSnafu {
+                language: content_language,
+            }
+        );
 
         Ok(Self {
             database_url,
+            database_statement_timeout,
+            poll_interval,
+            poll_start_jitter,
             public_uri,
             listen_address,
+            internal_listen_address,
             caffeine_interval,
+            maintenance_interval,
+            maintenance_statement_timeout,
+            behind_proxy,
+            secure_cookies,
+            session_ttl,
+            delivery_policy,
+            skip_backlog,
+            poll_sources,
+            single_session,
+            notification_template,
+            content_language,
+            admin_token,
         })
     }
+
+    pub fn redacted_database_url(&self) -> String {
+        match Url::parse(&self.database_url) {
+            Ok(mut url) => {
+                if url.password().is_some() {
+                    // Only fails for URLs that cannot have a password
+                    let _ = url.set_password(Some("REDACTED"));
+                }
+                url.into()
+            }
+            Err(_) => String::from("REDACTED"),
+        }
+    }
+
+    pub fn warn_on_port_mismatch(&self) {
+        if self.behind_proxy {
+            return;
+        }
+
+        let public_port = match self.public_uri.port() {
+            Some(port) => port,
+            None => return,
+        };
+        let listen_port = self.listen_address.port();
+
+        if public_port != listen_port {
+            warn!(
+                "WEB_PUBLIC_URI uses port {} but the web server listens on port {}; \
+                 OAuth redirects will not reach this server. \
+                 Set WEB_BEHIND_PROXY if a proxy forwards between the two.",
+                public_port, listen_port,
+            );
+        }
+    }
+}
+
+// Enabled unless explicitly turned off
+fn toggle_from_environment(name: &'static str) -> Result<bool> {
+    match env::var(name) {
+        Ok(value) => value.parse().context(InvalidToggleSnafu { name, value }),
+        Err(_) => Ok(true),
+    }
 }
 
 #[derive(Debug, Snafu)]
 pub enum Error {
+    #[snafu(display("RUST_LOG / LOG_FILTER is invalid"))]
+    InvalidLogFilter {
+        source: tracing_subscriber::filter::ParseError,
+        filter: String,
+    },
+
+    #[snafu(display("LOG_FORMAT must be `text` or `json`, not {:?}", format))]
+    InvalidLogFormat { format: String },
+
+    #[snafu(display("{} must be `true` or `false`", name))]
+    InvalidToggle {
+        source: std::str::ParseBoolError,
+        name: &'static str,
+        value: String,
+    },
+
+    #[snafu(display("At least one of POLL_NOTIFICATIONS and POLL_INBOX must be enabled"))]
+    NothingToPoll,
+
     #[snafu(display("DATABASE_URL must be set"))]
     UnknownDatabaseUrl { source: env::VarError },
 
+    #[snafu(display("DATABASE_STATEMENT_TIMEOUT is invalid"))]
+    InvalidDatabaseStatementTimeout {
+        source: std::num::ParseIntError,
+        timeout: String,
+    },
+
+    #[snafu(display("DATABASE_MAINTENANCE_STATEMENT_TIMEOUT is invalid"))]
+    InvalidMaintenanceStatementTimeout {
+        source: std::num::ParseIntError,
+        timeout: String,
+    },
+
+    #[snafu(display("SESSION_TTL_SECONDS is invalid"))]
+    InvalidSessionTtl {
+        source: std::num::ParseIntError,
+        ttl: String,
+    },
+
+    #[snafu(display("POLL_INTERVAL_SECONDS is invalid"))]
+    InvalidPollInterval {
+        source: std::num::ParseIntError,
+        interval: String,
+    },
+
+    #[snafu(display("POLL_START_JITTER_SECONDS is invalid"))]
+    InvalidPollStartJitter {
+        source: std::num::ParseIntError,
+        jitter: String,
+    },
+
     #[snafu(display("WEB_LISTEN_ADDRESS must be set"))]
     UnknownWebListenAddress { source: env::VarError },
 
@@ -56,6 +313,12 @@ pub enum Error {
         address: String,
     },
 
+    #[snafu(display("INTERNAL_LISTEN_ADDRESS is invalid"))]
+    InvalidInternalListenAddress {
+        source: std::net::AddrParseError,
+        address: String,
+    },
+
     #[snafu(display("WEB_LISTEN_PORT must be set"))]
     UnknownWebListenPort { source: env::VarError },
 
@@ -73,6 +336,12 @@ pub enum Error {
         source: url::ParseError,
         uri: String,
     },
+
+    #[snafu(display("DELIVERY_POLICY must be `any` or `all`"))]
+    InvalidDeliveryPolicy { policy: String },
+
+    #[snafu(display("CONTENT_LANGUAGE must be a list of language tags"))]
+    InvalidContentLanguage { language: String },
 }
 
 type Result<T, E = Error> = std::result::Result<T, E>;
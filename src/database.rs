@@ -1,37 +1,88 @@
 use crate::{
-    domain::{IncomingNotification, OutgoingNotification, UserKey},
+    domain::{
+        ChatId, ConfiguredDestination, Destination, DestinationId, IncomingNotification,
+        NotificationId, OauthState, OutgoingNotification, RecentNotification, RegistrationChange,
+        RegistrationSummary, SessionData, SessionId, UserKey,
+    },
+    error::IsTransient,
     stack_overflow::{AccessToken, AccountId},
 };
 use diesel::{
     connection::{AnsiTransactionManager, TransactionManager},
     prelude::*,
-    upsert::excluded,
+    r2d2::{ConnectionManager, CustomizeConnection, PooledConnection},
 };
 use snafu::{ResultExt, Snafu};
+use std::{collections::HashSet, error::Error as _, time::Duration};
+use time::OffsetDateTime;
 use tracing::{trace, trace_span};
 
 mod models;
 mod schema;
 
+// Each worker holds at most one connection at a time
+pub const POOL_SIZE: u32 = 8;
+
+type Pool = diesel::r2d2::Pool<ConnectionManager<PgConnection>>;
+
+#[derive(Clone)]
 pub struct Db {
-    conn: diesel::PgConnection,
+    pool: Pool,
+    statement_timeout: Duration,
 }
 
 impl Db {
-    pub fn new(conn: diesel::PgConnection) -> Self {
-        Self { conn }
+    pub fn new(database_url: &str, statement_timeout: Duration) -> Result<Self> {
+        let pool = Pool::builder()
+            .max_size(POOL_SIZE)
+            .connection_customizer(Box::new(StatementTimeout(statement_timeout)))
+            .build(ConnectionManager::new(database_url))
+            .context(UnableToCreatePoolSnafu)?;
+
+        Ok(Self {
+            pool,
+            statement_timeout,
+        })
+    }
+
+    fn conn(&self) -> Result<PooledConnection<ConnectionManager<PgConnection>>> {
+        self.pool.get().context(UnableToGetConnectionSnafu)
+    }
+}
+
+// A slow query still ties up a worker and its connection
+#[derive(Debug)]
+struct StatementTimeout(Duration);
+
+impl CustomizeConnection<PgConnection, diesel::r2d2::Error> for StatementTimeout {
+    fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), diesel::r2d2::Error> {
+        set_statement_timeout(conn, self.0).map_err(diesel::r2d2::Error::QueryError)
     }
 }
 
-#[alictor::alictor(kind = blocking)]
+fn set_statement_timeout(conn: &mut PgConnection, timeout: Duration) -> QueryResult<()> {
+    diesel::sql_query(format!("SET statement_timeout = {}", timeout.as_millis()))
+        .execute(conn)
+        .map(drop)
+}
+
+#[alictor::alictor(
+    kind = blocking,
+    trace,
+    flatten_results = false,
+    capacity = 64,
+    workers = POOL_SIZE
+)]
 impl Db {
     fn registrations(&mut self) -> Result<Vec<(AccountId, AccessToken)>> {
         use schema::registrations;
 
-        let Self { conn } = self;
+        let conn = &mut self.conn()?;
 
+        // Polling with a rejected token would only fail again
         let r = registrations::table
             .select((registrations::account_id, registrations::access_token))
+            .filter(registrations::needs_reauth.eq(false))
             .load(conn)
             .context(UnableToQueryRegistrationsSnafu)?;
 
@@ -40,46 +91,439 @@ impl Db {
             .collect())
     }
 
-    fn register(&mut self, account_id: AccountId, access_token: AccessToken) -> Result<()> {
+    fn register(
+        &mut self,
+        account_id: AccountId,
+        access_token: AccessToken,
+    ) -> Result<RegistrationChange> {
+        use diesel::{dsl::sql, sql_types::Bool, upsert::excluded};
         use models::Registration;
         use schema::registrations::dsl;
 
-        let Self { conn } = self;
+        let conn = &mut self.conn()?;
 
         let registration = Registration {
             account_id: account_id.0,
             access_token: access_token.0,
         };
 
-        diesel::insert_into(dsl::registrations)
+        // A freshly inserted row has not been touched by any other
+        // transaction, so `xmax` is zero.
+        let created = diesel::insert_into(dsl::registrations)
             .values(&registration)
             .on_conflict(dsl::account_id)
             .do_update()
-            .set(dsl::access_token.eq(dsl::access_token)) // should this be `excluded`?
-            .execute(conn)
+            .set((
+                dsl::access_token.eq(excluded(dsl::access_token)),
+                dsl::needs_reauth.eq(false),
+            ))
+            .returning(sql::<Bool>("xmax = 0"))
+            .get_result(conn)
             .context(UnableToInsertRegistrationSnafu)?;
 
+        Ok(if created {
+            RegistrationChange::Created
+        } else {
+            RegistrationChange::Updated
+        })
+    }
+
+    fn registration_summaries(
+        &mut self,
+        search: Option<AccountId>,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<RegistrationSummary>> {
+        use schema::destinations as d;
+        use schema::registrations as r;
+
+        let conn = &mut self.conn()?;
+
+        let mut query = r::table
+            .select((r::account_id, r::last_polled_at))
+            .order(r::account_id)
+            .offset(offset)
+            .limit(limit)
+            .into_boxed();
+
+        if let Some(account_id) = search {
+            query = query.filter(r::account_id.eq(account_id.0));
+        }
+
+        let registrations = query
+            .load::<(i32, Option<OffsetDateTime>)>(conn)
+            .context(UnableToQueryRegistrationsSnafu)?;
+
+        let ids: Vec<_> = registrations.iter().map(|&(id, _)| id).collect();
+
+        let with_destination: HashSet<_> = d::table
+            .select(d::account_id)
+            .filter(d::account_id.eq_any(ids))
+            .distinct()
+            .load::<i32>(conn)
+            .context(UnableToQueryDestinationsSnafu)?
+            .into_iter()
+            .collect();
+
+        Ok(registrations
+            .into_iter()
+            .map(|(id, last_polled_at)| RegistrationSummary {
+                account_id: AccountId(id),
+                has_destination: with_destination.contains(&id),
+                last_polled_at,
+            })
+            .collect())
+    }
+
+    fn accounts_without_destination(&mut self) -> Result<Vec<AccountId>> {
+        use diesel::dsl::{exists, not};
+        use schema::destinations as d;
+        use schema::registrations as r;
+
+        let conn = &mut self.conn()?;
+
+        let has_destination = d::table.filter(d::account_id.eq(r::account_id));
+
+        let ids = r::table
+            .select(r::account_id)
+            .filter(not(exists(has_destination)))
+            .order(r::account_id)
+            .load::<i32>(conn)
+            .context(UnableToQueryRegistrationsSnafu)?;
+
+        Ok(ids.into_iter().map(AccountId).collect())
+    }
+
+    // Everything the account owns goes with it, including sessions
+    fn deregister(&mut self, account_id: AccountId) -> Result<()> {
+        use schema::{destinations as d, notifications as n, registrations as r, sessions as s};
+
+        let conn = &mut self.conn()?;
+        let id = account_id.0;
+
+        transaction(conn, |conn| {
+            diesel::delete(n::table.filter(n::account_id.eq(id)))
+                .execute(conn)
+                .context(UnableToDeregisterSnafu)?;
+            diesel::delete(d::table.filter(d::account_id.eq(id)))
+                .execute(conn)
+                .context(UnableToDeregisterSnafu)?;
+            diesel::delete(s::table.filter(s::account_id.eq(id)))
+                .execute(conn)
+                .context(UnableToDeregisterSnafu)?;
+            diesel::delete(r::table.find(id))
+                .execute(conn)
+                .context(UnableToDeregisterSnafu)?;
+            Ok(())
+        })
+    }
+
+    fn record_poll(&mut self, account_id: AccountId) -> Result<()> {
+        use schema::registrations::dsl;
+
+        let conn = &mut self.conn()?;
+
+        diesel::update(dsl::registrations.find(account_id.0))
+            .set(dsl::last_polled_at.eq(OffsetDateTime::now_utc()))
+            .execute(conn)
+            .context(UnableToRecordPollSnafu)?;
+
+        Ok(())
+    }
+
+    fn mark_needs_reauth(&mut self, account_id: AccountId) -> Result<()> {
+        use schema::registrations::dsl;
+
+        let conn = &mut self.conn()?;
+
+        diesel::update(dsl::registrations.find(account_id.0))
+            .set(dsl::needs_reauth.eq(true))
+            .execute(conn)
+            .context(UnableToMarkNeedsReauthSnafu)?;
+
         Ok(())
     }
 
-    fn set_pushover_user(&mut self, account_id: AccountId, user_key: UserKey) -> Result<()> {
-        use models::PushoverUser;
-        use schema::pushover_users::dsl;
+    fn last_polled_at(&mut self, account_id: AccountId) -> Result<Option<OffsetDateTime>> {
+        use schema::registrations::dsl;
+
+        let conn = &mut self.conn()?;
+
+        let last_polled_at = dsl::registrations
+            .find(account_id.0)
+            .select(dsl::last_polled_at)
+            .first(conn)
+            .optional()
+            .context(UnableToQueryRegistrationsSnafu)?;
 
-        let Self { conn } = self;
+        Ok(last_polled_at.flatten())
+    }
+
+    // When the newest notification already relayed was created
+    fn relayed_until(&mut self, account_id: AccountId) -> Result<Option<OffsetDateTime>> {
+        use schema::registrations::dsl;
 
-        let user = PushoverUser {
-            key: user_key.0,
+        let conn = &mut self.conn()?;
+
+        let relayed_until = dsl::registrations
+            .find(account_id.0)
+            .select(dsl::relayed_until)
+            .first(conn)
+            .optional()
+            .context(UnableToQueryRegistrationsSnafu)?;
+
+        Ok(relayed_until.flatten())
+    }
+
+    fn advance_relayed_until(
+        &mut self,
+        account_id: AccountId,
+        until: OffsetDateTime,
+    ) -> Result<()> {
+        use schema::registrations::dsl;
+
+        let conn = &mut self.conn()?;
+
+        // Never moves backwards, even if an older poll finishes last
+        diesel::update(dsl::registrations.find(account_id.0))
+            .filter(
+                dsl::relayed_until
+                    .is_null()
+                    .or(dsl::relayed_until.lt(until)),
+            )
+            .set(dsl::relayed_until.eq(until))
+            .execute(conn)
+            .context(UnableToRecordRelayedUntilSnafu)?;
+
+        Ok(())
+    }
+
+    fn registered_at(&mut self, account_id: AccountId) -> Result<Option<OffsetDateTime>> {
+        use schema::registrations::dsl;
+
+        let conn = &mut self.conn()?;
+
+        dsl::registrations
+            .find(account_id.0)
+            .select(dsl::registered_at)
+            .first(conn)
+            .optional()
+            .context(UnableToQueryRegistrationsSnafu)
+    }
+
+    fn destinations(&mut self, account_id: AccountId) -> Result<Vec<ConfiguredDestination>> {
+        use schema::destinations::dsl;
+
+        let conn = &mut self.conn()?;
+
+        let r = dsl::destinations
+            .filter(dsl::account_id.eq(account_id.0))
+            .order(dsl::id)
+            .select((dsl::id, dsl::kind, dsl::config, dsl::enabled, dsl::verified))
+            .load::<(i32, String, String, bool, bool)>(conn)
+            .context(UnableToQueryDestinationsSnafu)?;
+
+        r.into_iter()
+            .map(|(id, kind, config, enabled, verified)| {
+                destination_from_parts(kind, config).map(|destination| ConfiguredDestination {
+                    id: DestinationId(id),
+                    destination,
+                    enabled,
+                    verified,
+                })
+            })
+            .collect()
+    }
+
+    fn load_session(&mut self, id: SessionId) -> Result<Option<SessionData>> {
+        use models::Session;
+        use schema::sessions::dsl;
+
+        let conn = &mut self.conn()?;
+
+        let session = dsl::sessions
+            .find(&id.0[..])
+            .first::<Session>(conn)
+            .optional()
+            .context(UnableToQuerySessionsSnafu)?;
+
+        Ok(session.map(|s| SessionData {
+            oauth_state: s
+                .oauth_state
+                .zip(s.oauth_state_issued_at)
+                .map(|(state, issued_at)| OauthState { state, issued_at }),
+            account_id: s.account_id.map(AccountId),
+            csrf_token: s.csrf_token,
+            created_at: s.created_at,
+            last_seen_at: s.last_seen_at,
+        }))
+    }
+
+    fn save_session(&mut self, id: SessionId, data: SessionData) -> Result<()> {
+        use models::Session;
+        use schema::sessions::dsl;
+
+        let conn = &mut self.conn()?;
+
+        let (oauth_state, oauth_state_issued_at) = match data.oauth_state {
+            Some(OauthState { state, issued_at }) => (Some(state), Some(issued_at)),
+            None => (None, None),
+        };
+
+        let session = Session {
+            id: id.0.to_vec(),
+            oauth_state,
+            account_id: data.account_id.map(|a| a.0),
+            csrf_token: data.csrf_token,
+            created_at: data.created_at,
+            last_seen_at: data.last_seen_at,
+            oauth_state_issued_at,
+        };
+
+        diesel::insert_into(dsl::sessions)
+            .values(&session)
+            .on_conflict(dsl::id)
+            .do_update()
+            .set(&session)
+            .execute(conn)
+            .context(UnableToSaveSessionSnafu)?;
+
+        Ok(())
+    }
+
+    fn remove_session(&mut self, id: SessionId) -> Result<()> {
+        use schema::sessions::dsl;
+
+        let conn = &mut self.conn()?;
+
+        diesel::delete(dsl::sessions.find(&id.0[..]))
+            .execute(conn)
+            .context(UnableToDeleteSessionsSnafu)?;
+
+        Ok(())
+    }
+
+    fn remove_other_sessions(&mut self, id: SessionId, account_id: AccountId) -> Result<()> {
+        use schema::sessions::dsl;
+
+        let conn = &mut self.conn()?;
+
+        let others = dsl::sessions
+            .filter(dsl::account_id.eq(account_id.0))
+            .filter(dsl::id.ne(&id.0[..]));
+
+        diesel::delete(others)
+            .execute(conn)
+            .context(UnableToDeleteSessionsSnafu)?;
+
+        Ok(())
+    }
+
+    // Sessions with a login in progress use `login_cutoff` instead
+    fn remove_expired_sessions(
+        &mut self,
+        cutoff: OffsetDateTime,
+        login_cutoff: OffsetDateTime,
+    ) -> Result<usize> {
+        use schema::sessions::dsl;
+
+        let conn = &mut self.conn()?;
+
+        let expired = dsl::sessions.filter(dsl::last_seen_at.lt(cutoff)).filter(
+            dsl::oauth_state
+                .is_null()
+                .or(dsl::last_seen_at.lt(login_cutoff)),
+        );
+
+        diesel::delete(expired)
+            .execute(conn)
+            .context(UnableToDeleteSessionsSnafu)
+    }
+
+    fn add_destination(
+        &mut self,
+        account_id: AccountId,
+        destination: Destination,
+    ) -> Result<DestinationId> {
+        use models::NewDestination;
+        use schema::destinations::dsl;
+
+        let conn = &mut self.conn()?;
+
+        let (kind, config) = destination_into_parts(destination);
+        let destination = NewDestination {
             account_id: account_id.0,
+            kind,
+            config,
         };
 
-        diesel::insert_into(dsl::pushover_users)
-            .values(&user)
-            .on_conflict(dsl::account_id)
+        let id = diesel::insert_into(dsl::destinations)
+            .values(&destination)
+            .on_conflict((dsl::account_id, dsl::kind, dsl::config))
             .do_update()
-            .set(dsl::key.eq(excluded(dsl::key)))
+            .set(dsl::enabled.eq(true))
+            .returning(dsl::id)
+            .get_result::<i32>(conn)
+            .context(UnableToInsertDestinationSnafu)?;
+
+        Ok(DestinationId(id))
+    }
+
+    fn set_destination_enabled(
+        &mut self,
+        account_id: AccountId,
+        id: DestinationId,
+        enabled: bool,
+    ) -> Result<()> {
+        use schema::destinations::dsl;
+
+        let conn = &mut self.conn()?;
+
+        let destination = dsl::destinations
+            .filter(dsl::id.eq(id.0))
+            .filter(dsl::account_id.eq(account_id.0));
+
+        diesel::update(destination)
+            .set(dsl::enabled.eq(enabled))
+            .execute(conn)
+            .context(UnableToUpdateDestinationSnafu)?;
+
+        Ok(())
+    }
+
+    fn mark_destination_verified(
+        &mut self,
+        account_id: AccountId,
+        id: DestinationId,
+    ) -> Result<()> {
+        use schema::destinations::dsl;
+
+        let conn = &mut self.conn()?;
+
+        let destination = dsl::destinations
+            .filter(dsl::id.eq(id.0))
+            .filter(dsl::account_id.eq(account_id.0));
+
+        diesel::update(destination)
+            .set(dsl::verified.eq(true))
+            .execute(conn)
+            .context(UnableToUpdateDestinationSnafu)?;
+
+        Ok(())
+    }
+
+    fn remove_destination(&mut self, account_id: AccountId, id: DestinationId) -> Result<()> {
+        use schema::destinations::dsl;
+
+        let conn = &mut self.conn()?;
+
+        let destination = dsl::destinations
+            .filter(dsl::id.eq(id.0))
+            .filter(dsl::account_id.eq(account_id.0));
+
+        diesel::delete(destination)
             .execute(conn)
-            .context(UnableToInsertPushoverUserSnafu)?;
+            .context(UnableToDeleteDestinationSnafu)?;
 
         Ok(())
     }
@@ -89,52 +533,217 @@ impl Db {
         notifications: Vec<IncomingNotification>,
     ) -> Result<Vec<OutgoingNotification>> {
         use models::NewNotification;
+        use schema::destinations as d;
         use schema::notifications as n;
-        use schema::pushover_users as p;
 
         let s = trace_span!("add_new_notifications");
         let _s = s.enter();
-        let Self { conn } = self;
+        let conn = &mut self.conn()?;
 
         trace!("Checking {} notifications", notifications.len());
 
+        let account_ids: Vec<_> = notifications.iter().map(|n| n.account_id.0).collect();
+        let texts: Vec<_> = notifications.iter().map(|n| n.text.clone()).collect();
+
         let notifications: Vec<_> = notifications
             .into_iter()
             .map(|n| NewNotification {
                 account_id: n.account_id.0,
                 text: n.text,
+                url: n.url,
+                created_at: n.created_at,
+                notification_type: n.notification_type,
+                post_id: n.post_id.map_or(0, |p| p.0),
             })
             .collect();
 
-        let raw_notifications: Vec<(String, String)> = transaction(conn, |conn| {
-            let ids = diesel::insert_into(n::table)
+        #[allow(clippy::type_complexity)]
+        let raw_notifications: Vec<(
+            i32,
+            i32,
+            String,
+            String,
+            String,
+            Option<String>,
+            OffsetDateTime,
+        )> = transaction(conn, |conn| {
+            let count = diesel::insert_into(n::table)
                 .values(notifications)
-                .on_conflict((n::account_id, n::text))
+                .on_conflict((n::account_id, n::notification_type, n::post_id, n::text))
                 .do_nothing()
-                .returning(n::id)
                 .log_query()
-                .get_results::<i32>(conn)
+                .execute(conn)
                 .context(UnableToInsertNotificationsSnafu)?;
 
-            trace!("Inserted {} new notifications", ids.len());
-
-            p::table
-                .inner_join(n::table.on(n::account_id.eq(p::account_id)))
-                .select((p::key, n::text))
-                .filter(n::id.eq_any(ids))
+            trace!("Inserted {} new notifications", count);
+
+            // Notifications that previously failed to be delivered
+            // are retried for as long as they remain unread.
+            d::table
+                .inner_join(n::table.on(n::account_id.eq(d::account_id)))
+                .select((
+                    n::id,
+                    n::account_id,
+                    d::kind,
+                    d::config,
+                    n::text,
+                    n::url,
+                    n::created_at,
+                ))
+                .filter(n::account_id.eq_any(account_ids))
+                .filter(n::text.eq_any(texts))
+                .filter(n::delivered.eq(false))
+                .filter(d::enabled.eq(true))
+                .filter(d::verified.eq(true))
                 .log_query()
                 .load(conn)
                 .context(UnableToQueryNotificationsSnafu)
         })?;
 
-        Ok(raw_notifications
+        raw_notifications
+            .into_iter()
+            .map(|(id, account_id, kind, config, text, url, created_at)| {
+                destination_from_parts(kind, config).map(|destination| OutgoingNotification {
+                    id: NotificationId(id),
+                    account_id: AccountId(account_id),
+                    destination,
+                    text,
+                    url,
+                    created_at,
+                })
+            })
+            .collect()
+    }
+
+    fn notification_dedupe_index_present(&mut self) -> Result<bool> {
+        use models::UniqueIndex;
+
+        let conn = &mut self.conn()?;
+
+        let indexes = diesel::sql_query(
+            "SELECT array_agg(a.attname::text) AS columns \
+             FROM pg_index i \
+             JOIN pg_class c ON c.oid = i.indrelid \
+             JOIN pg_attribute a ON a.attrelid = c.oid AND a.attnum = ANY (i.indkey) \
+             WHERE c.relname = 'notifications' AND i.indisunique \
+             GROUP BY i.indexrelid",
+        )
+        .load::<UniqueIndex>(conn)
+        .context(UnableToQueryIndexesSnafu)?;
+
+        let indexes: Vec<_> = indexes.into_iter().map(|i| i.columns).collect();
+        Ok(is_dedupe_index(&indexes))
+    }
+
+    fn recent_notifications(
+        &mut self,
+        account_id: AccountId,
+        limit: i64,
+    ) -> Result<Vec<RecentNotification>> {
+        use schema::notifications::dsl;
+
+        let conn = &mut self.conn()?;
+
+        let notifications = dsl::notifications
+            .filter(dsl::account_id.eq(account_id.0))
+            .order((dsl::created_at.desc(), dsl::id.desc()))
+            .limit(limit)
+            .load::<models::Notification>(conn)
+            .context(UnableToQueryNotificationsSnafu)?;
+
+        Ok(notifications
             .into_iter()
-            .map(|(key, text)| OutgoingNotification {
-                user: UserKey(key),
-                text,
+            .map(|n| RecentNotification {
+                text: n.text,
+                url: n.url,
+                created_at: n.created_at,
+                delivered: n.delivered,
             })
             .collect())
     }
+
+    fn mark_delivered(&mut self, ids: Vec<NotificationId>) -> Result<()> {
+        use schema::notifications::dsl;
+
+        let conn = &mut self.conn()?;
+
+        let ids: Vec<_> = ids.into_iter().map(|id| id.0).collect();
+
+        diesel::update(dsl::notifications.filter(dsl::id.eq_any(ids)))
+            .set(dsl::delivered.eq(true))
+            .execute(conn)
+            .context(UnableToMarkNotificationsDeliveredSnafu)?;
+
+        Ok(())
+    }
+
+    fn health_check(&mut self) -> Result<()> {
+        let conn = &mut self.conn()?;
+
+        diesel::sql_query("SELECT 1")
+            .execute(conn)
+            .context(UnableToCheckHealthSnafu)?;
+
+        Ok(())
+    }
+
+    // A large table takes longer than the usual statement timeout.
+    // VACUUM can't run in a transaction, so `SET LOCAL` isn't available;
+    // the usual timeout is put back before the connection is reused.
+    fn vacuum_notifications(&mut self, timeout: Duration) -> Result<()> {
+        let conn = &mut self.conn()?;
+
+        set_statement_timeout(conn, timeout).context(UnableToVacuumSnafu)?;
+        let vacuum = diesel::sql_query("VACUUM ANALYZE notifications").execute(conn);
+        let restore = set_statement_timeout(conn, self.statement_timeout);
+
+        vacuum.context(UnableToVacuumSnafu)?;
+        restore.context(UnableToVacuumSnafu)?;
+
+        Ok(())
+    }
+}
+
+// Must match the `ON CONFLICT` target in `add_new_notifications`
+pub(crate) const NOTIFICATION_DEDUPE_KEY: &[&str] =
+    &["account_id", "notification_type", "post_id", "text"];
+
+fn is_dedupe_index(unique_indexes: &[Vec<String>]) -> bool {
+    let mut key = NOTIFICATION_DEDUPE_KEY.to_vec();
+    key.sort_unstable();
+
+    unique_indexes.iter().any(|columns| {
+        let mut columns: Vec<_> = columns.iter().map(String::as_str).collect();
+        columns.sort_unstable();
+        columns == key
+    })
+}
+
+const KIND_PUSHOVER: &str = "pushover";
+const KIND_TELEGRAM: &str = "telegram";
+const KIND_WEBHOOK: &str = "webhook";
+
+fn destination_into_parts(destination: Destination) -> (String, String) {
+    match destination {
+        Destination::Pushover(UserKey(key)) => (KIND_PUSHOVER.into(), key),
+        Destination::Telegram(ChatId(id)) => (KIND_TELEGRAM.into(), id.to_string()),
+        Destination::Webhook(url) => (KIND_WEBHOOK.into(), url.into()),
+    }
+}
+
+fn destination_from_parts(kind: String, config: String) -> Result<Destination> {
+    match &*kind {
+        KIND_PUSHOVER => Ok(Destination::Pushover(UserKey(config))),
+        KIND_TELEGRAM => match config.parse() {
+            Ok(id) => Ok(Destination::Telegram(ChatId(id))),
+            Err(_) => InvalidDestinationConfigSnafu { kind, config }.fail(),
+        },
+        KIND_WEBHOOK => match config.parse() {
+            Ok(url) => Ok(Destination::Webhook(url)),
+            Err(_) => InvalidDestinationConfigSnafu { kind, config }.fail(),
+        },
+        _ => UnknownDestinationKindSnafu { kind }.fail(),
+    }
 }
 
 trait LogQuery {
@@ -174,13 +783,102 @@ pub enum Error {
 
     UnableToInsertRegistration { source: diesel::result::Error },
 
-    UnableToInsertPushoverUser { source: diesel::result::Error },
+    UnableToRecordPoll { source: diesel::result::Error },
+
+    UnableToRecordRelayedUntil { source: diesel::result::Error },
+
+    UnableToMarkNeedsReauth { source: diesel::result::Error },
+
+    UnableToDeregister { source: diesel::result::Error },
+
+    UnableToQuerySessions { source: diesel::result::Error },
+
+    UnableToSaveSession { source: diesel::result::Error },
+
+    UnableToDeleteSessions { source: diesel::result::Error },
+
+    UnableToQueryDestinations { source: diesel::result::Error },
+
+    UnableToInsertDestination { source: diesel::result::Error },
+
+    UnableToUpdateDestination { source: diesel::result::Error },
+
+    UnableToDeleteDestination { source: diesel::result::Error },
+
+    UnknownDestinationKind { kind: String },
+
+    InvalidDestinationConfig { kind: String, config: String },
 
     UnableToInsertNotifications { source: diesel::result::Error },
 
     UnableToQueryNotifications { source: diesel::result::Error },
 
+    UnableToMarkNotificationsDelivered { source: diesel::result::Error },
+
+    UnableToQueryIndexes { source: diesel::result::Error },
+
+    UnableToVacuum { source: diesel::result::Error },
+
+    UnableToCheckHealth { source: diesel::result::Error },
+
     TransactionFailed { source: diesel::result::Error },
+
+    UnableToCreatePool { source: diesel::r2d2::PoolError },
+
+    UnableToGetConnection { source: diesel::r2d2::PoolError },
+}
+
+impl IsTransient for Error {
+    fn is_transient(&self) -> bool {
+        // Every connection may just be busy
+        if let Self::UnableToGetConnection { .. } = self {
+            return true;
+        }
+
+        self.source()
+            .and_then(|s| s.downcast_ref::<diesel::result::Error>())
+            .map_or(false, IsTransient::is_transient)
+    }
 }
 
 type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn indexes(indexes: &[&[&str]]) -> Vec<Vec<String>> {
+        indexes
+            .iter()
+            .map(|columns| columns.iter().map(|&c| c.to_owned()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn dedupe_index_present() {
+        let unique = indexes(&[
+            &["id"],
+            &["account_id", "notification_type", "post_id", "text"],
+        ]);
+        assert!(is_dedupe_index(&unique));
+    }
+
+    #[test]
+    fn dedupe_index_column_order_is_irrelevant() {
+        let unique = indexes(&[&["text", "post_id", "account_id", "notification_type"]]);
+        assert!(is_dedupe_index(&unique));
+    }
+
+    #[test]
+    fn dedupe_index_missing() {
+        let unique = indexes(&[&["id"]]);
+        assert!(!is_dedupe_index(&unique));
+    }
+
+    #[test]
+    fn dedupe_index_must_match_exactly() {
+        // The key from before types and posts were distinguished
+        let unique = indexes(&[&["account_id"], &["account_id", "text"]]);
+        assert!(!is_dedupe_index(&unique));
+    }
+}
@@ -6,10 +6,12 @@ pub struct Registration {
     pub access_token: String,
 }
 
-#[derive(Debug, Queryable, Insertable)]
-pub struct PushoverUser {
-    pub key: String,
+#[derive(Debug, Insertable)]
+#[diesel(table_name = destinations)]
+pub struct NewDestination {
     pub account_id: i32,
+    pub kind: String,
+    pub config: String,
 }
 
 #[derive(Debug, Insertable)]
@@ -17,6 +19,11 @@ pub struct PushoverUser {
 pub struct NewNotification {
     pub account_id: i32,
     pub text: String,
+    pub url: Option<String>,
+    pub created_at: time::OffsetDateTime,
+    pub notification_type: String,
+    // Zero when the notification isn't about a post
+    pub post_id: i64,
 }
 
 #[derive(Debug, Queryable)]
@@ -24,4 +31,27 @@ pub struct Notification {
     pub id: i32,
     pub account_id: i32,
     pub text: String,
+    pub created_at: time::OffsetDateTime,
+    pub delivered: bool,
+    pub url: Option<String>,
+    pub notification_type: String,
+    pub post_id: i64,
+}
+
+#[derive(Debug, Queryable, Insertable, AsChangeset)]
+#[diesel(table_name = sessions, treat_none_as_null = true)]
+pub struct Session {
+    pub id: Vec<u8>,
+    pub oauth_state: Option<String>,
+    pub account_id: Option<i32>,
+    pub csrf_token: Option<String>,
+    pub created_at: time::OffsetDateTime,
+    pub last_seen_at: time::OffsetDateTime,
+    pub oauth_state_issued_at: Option<time::OffsetDateTime>,
+}
+
+#[derive(Debug, QueryableByName)]
+pub struct UniqueIndex {
+    #[diesel(sql_type = diesel::sql_types::Array<diesel::sql_types::Text>)]
+    pub columns: Vec<String>,
 }
@@ -1,16 +1,24 @@
 table! {
-    notifications (id) {
+    destinations (id) {
         id -> Int4,
         account_id -> Int4,
-        text -> Text,
-        created_at -> Timestamptz,
+        kind -> Text,
+        config -> Text,
+        enabled -> Bool,
+        verified -> Bool,
     }
 }
 
 table! {
-    pushover_users (key) {
-        key -> Text,
+    notifications (id) {
+        id -> Int4,
         account_id -> Int4,
+        text -> Text,
+        created_at -> Timestamptz,
+        delivered -> Bool,
+        url -> Nullable<Text>,
+        notification_type -> Text,
+        post_id -> Int8,
     }
 }
 
@@ -18,10 +26,26 @@ table! {
     registrations (account_id) {
         account_id -> Int4,
         access_token -> Text,
+        last_polled_at -> Nullable<Timestamptz>,
+        registered_at -> Timestamptz,
+        needs_reauth -> Bool,
+        relayed_until -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Bytea,
+        oauth_state -> Nullable<Text>,
+        account_id -> Nullable<Int4>,
+        csrf_token -> Nullable<Text>,
+        created_at -> Timestamptz,
+        last_seen_at -> Timestamptz,
+        oauth_state_issued_at -> Nullable<Timestamptz>,
     }
 }
 
+joinable!(destinations -> registrations (account_id));
 joinable!(notifications -> registrations (account_id));
-joinable!(pushover_users -> registrations (account_id));
 
-allow_tables_to_appear_in_same_query!(notifications, pushover_users, registrations,);
+allow_tables_to_appear_in_same_query!(destinations, notifications, registrations, sessions,);
@@ -1,14 +1,170 @@
 pub use crate::pushover::UserKey;
-pub use crate::stack_overflow::AccountId;
+pub use crate::stack_overflow::{AccountId, PostId};
+pub use crate::telegram::ChatId;
+use std::{convert::TryInto, time::Duration};
+use time::OffsetDateTime;
 
 #[derive(Debug)]
 pub struct IncomingNotification {
     pub account_id: AccountId,
+    pub notification_type: String,
+    pub post_id: Option<PostId>,
     pub text: String,
+    pub url: Option<String>,
+    pub created_at: OffsetDateTime,
 }
 
+#[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq)]
+pub struct SessionId(pub [u8; 32]);
+
+impl rand::distributions::Distribution<SessionId> for rand::distributions::Standard {
+    fn sample<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> SessionId {
+        SessionId(self.sample(rng))
+    }
+}
+
+impl SessionId {
+    pub fn from_cookie(s: &str) -> Option<Self> {
+        let bytes = hex::decode(s).ok()?;
+        let bytes = bytes.try_into().ok()?;
+        Some(Self(bytes))
+    }
+
+    pub fn to_cookie(&self) -> String {
+        hex::encode(self.0)
+    }
+}
+
+// Proves that a returning OAuth callback was started by this session
+#[derive(Debug, Clone)]
+pub struct OauthState {
+    pub state: String,
+    pub issued_at: OffsetDateTime,
+}
+
+impl OauthState {
+    // Long enough to log in to Stack Overflow, short enough to limit
+    // how long a leaked `state` is useful
+    pub const TTL: Duration = Duration::from_secs(10 * 60);
+
+    pub fn is_expired(&self, now: OffsetDateTime) -> bool {
+        self.issued_at + Self::TTL < now
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionData {
+    pub oauth_state: Option<OauthState>,
+    pub account_id: Option<AccountId>,
+    pub csrf_token: Option<String>,
+    pub created_at: OffsetDateTime,
+    pub last_seen_at: OffsetDateTime,
+}
+
+impl SessionData {
+    // Someone partway through logging in has this long to return from
+    // Stack Overflow, however short the TTL is
+    pub const LOGIN_GRACE: Duration = Duration::from_secs(60 * 60);
+
+    pub fn new(now: OffsetDateTime) -> Self {
+        Self {
+            oauth_state: None,
+            account_id: None,
+            csrf_token: None,
+            created_at: now,
+            last_seen_at: now,
+        }
+    }
+
+    pub fn is_expired(&self, ttl: Duration, now: OffsetDateTime) -> bool {
+        let ttl = if self.oauth_state.is_some() {
+            ttl.max(Self::LOGIN_GRACE)
+        } else {
+            ttl
+        };
+        self.last_seen_at + ttl < now
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct DestinationId(pub i32);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Destination {
+    Pushover(UserKey),
+    Telegram(ChatId),
+    Webhook(url::Url),
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfiguredDestination {
+    pub id: DestinationId,
+    pub destination: Destination,
+    pub enabled: bool,
+    pub verified: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub text: String,
+    // Shown as a tappable link below the text
+    pub url: Option<String>,
+}
+
+impl From<String> for Message {
+    fn from(text: String) -> Self {
+        Self { text, url: None }
+    }
+}
+
+impl From<&str> for Message {
+    fn from(text: &str) -> Self {
+        text.to_owned().into()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct NotificationId(pub i32);
+
 #[derive(Debug)]
 pub struct OutgoingNotification {
-    pub user: UserKey,
+    pub id: NotificationId,
+    pub account_id: AccountId,
+    pub destination: Destination,
     pub text: String,
+    pub url: Option<String>,
+    pub created_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecentNotification {
+    pub text: String,
+    pub url: Option<String>,
+    pub created_at: OffsetDateTime,
+    pub delivered: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RegistrationChange {
+    Created,
+    Updated,
+}
+
+#[derive(Debug, Clone)]
+pub struct RegistrationSummary {
+    pub account_id: AccountId,
+    pub has_destination: bool,
+    pub last_polled_at: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PollSources {
+    pub notifications: bool,
+    pub inbox: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeliveryPolicy {
+    Any,
+    All,
 }
@@ -1,9 +1,33 @@
-use snafu::{ensure, Snafu};
-use std::{error::Error, future::Future};
-use tracing::warn;
+use snafu::Snafu;
+use std::{error::Error, fmt, future::Future, time::Duration};
+use tokio::time::Instant;
+use tracing::{trace, warn};
 
 pub(crate) trait IsTransient {
     fn is_transient(&self) -> bool;
+
+    // How long the other side asked us to wait before trying again
+    fn retry_after(&self) -> Option<Duration> {
+        None
+    }
+}
+
+// Every error in the chain, like `main` reports them. `Debug` is
+// avoided as it can include request URLs and the secrets in them.
+pub(crate) struct Chain<'a>(pub &'a dyn Error);
+
+impl fmt::Display for Chain<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+
+        let mut e = self.0;
+        while let Some(source) = e.source() {
+            write!(f, ": {}", source)?;
+            e = source;
+        }
+
+        Ok(())
+    }
 }
 
 impl IsTransient for reqwest::Error {
@@ -16,17 +40,99 @@ impl IsTransient for reqwest::Error {
     }
 }
 
-#[derive(Debug, Default)]
+impl IsTransient for diesel::result::Error {
+    fn is_transient(&self) -> bool {
+        // Postgres reports this as a generic error; the message is all we have
+        match self {
+            Self::DatabaseError(_, info) => info
+                .message()
+                .contains("canceling statement due to statement timeout"),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum BreakerState {
+    Closed,
+    Open { until: Instant },
+    // A single trial request decides between open and closed
+    HalfOpen,
+}
+
+#[derive(Debug)]
 pub(crate) struct Breaker {
+    state: BreakerState,
     failure_count: usize,
+    threshold: usize,
+    cooldown: Duration,
+    retry_after: Option<Duration>,
+}
+
+impl Default for Breaker {
+    fn default() -> Self {
+        Self::with_threshold(10)
+    }
 }
 
 impl Breaker {
+    const DEFAULT_COOLDOWN: Duration = Duration::from_secs(5 * 60);
+
+    // Trips on the `threshold`th sequential transient failure
+    pub(crate) fn with_threshold(threshold: usize) -> Self {
+        Self {
+            state: BreakerState::Closed,
+            failure_count: 0,
+            threshold,
+            cooldown: Self::DEFAULT_COOLDOWN,
+            retry_after: None,
+        }
+    }
+
+    pub(crate) fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    // Sequential transient failures since the last success
+    pub(crate) fn failure_count(&self) -> usize {
+        self.failure_count
+    }
+
+    // Requested by the most recent transient failure
+    pub(crate) fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
+    // How long until the half-open trial, while the breaker is open
+    pub(crate) fn cooldown_remaining(&self) -> Option<Duration> {
+        match self.state {
+            BreakerState::Open { until } => until.checked_duration_since(Instant::now()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_open(&self) -> bool {
+        match self.state {
+            BreakerState::Open { until } => Instant::now() < until,
+            _ => false,
+        }
+    }
+
+    // `f` isn't run at all while the breaker is open
     pub(crate) async fn run<F, T, E>(&mut self, f: F) -> Result<Option<Result<T, E>>, BreakerError>
     where
         F: Future<Output = Result<T, E>>,
         E: Error + IsTransient,
     {
+        if let BreakerState::Open { until } = self.state {
+            if Instant::now() < until {
+                return Ok(None);
+            }
+            trace!("Breaker cooldown elapsed, trying again");
+            self.state = BreakerState::HalfOpen;
+        }
+
         self.check(f.await)
     }
 
@@ -40,21 +146,150 @@ impl Breaker {
         match r {
             Ok(v) => {
                 self.failure_count = 0;
+                self.state = BreakerState::Closed;
+                self.retry_after = None;
                 Ok(Some(Ok(v)))
             }
             Err(e) if e.is_transient() => {
                 self.failure_count += 1;
-                ensure!(self.failure_count < 10, BreakerSnafu);
+                self.retry_after = e.retry_after();
+                if self.state == BreakerState::HalfOpen || self.failure_count >= self.threshold {
+                    let cooldown = self
+                        .retry_after
+                        .map_or(self.cooldown, |r| r.max(self.cooldown));
+                    self.state = BreakerState::Open {
+                        until: Instant::now() + cooldown,
+                    };
+                    return BreakerSnafu {
+                        failure_count: self.failure_count,
+                        last_error: e.to_string(),
+                    }
+                    .fail();
+                }
                 warn!(
                     "{} sequential transient errors occurred, ignoring: {}",
                     self.failure_count, e,
                 );
                 Ok(None)
             }
-            Err(e) => Ok(Some(Err(e))),
+            Err(e) => {
+                self.retry_after = None;
+                Ok(Some(Err(e)))
+            }
         }
     }
 }
 
 #[derive(Debug, Snafu)]
-pub(crate) struct BreakerError {}
+#[snafu(display(
+    "{} sequential transient errors occurred, the last was: {}",
+    failure_count,
+    last_error
+))]
+pub(crate) struct BreakerError {
+    pub(crate) failure_count: usize,
+    pub(crate) last_error: String,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, Snafu)]
+    struct Flaky;
+
+    impl IsTransient for Flaky {
+        fn is_transient(&self) -> bool {
+            true
+        }
+    }
+
+    #[derive(Debug, Snafu)]
+    struct Throttled;
+
+    impl IsTransient for Throttled {
+        fn is_transient(&self) -> bool {
+            true
+        }
+
+        fn retry_after(&self) -> Option<Duration> {
+            Some(Duration::from_secs(90))
+        }
+    }
+
+    #[test]
+    fn breaker_trips_at_its_threshold() {
+        let mut breaker = Breaker::with_threshold(3);
+
+        assert!(matches!(breaker.check::<(), _>(Err(Flaky)), Ok(None)));
+        assert!(matches!(breaker.check::<(), _>(Err(Flaky)), Ok(None)));
+
+        let e = breaker.check::<(), _>(Err(Flaky)).unwrap_err();
+        assert_eq!(e.failure_count, 3);
+    }
+
+    #[test]
+    fn breaker_resets_on_success() {
+        let mut breaker = Breaker::with_threshold(3);
+
+        for _ in 0..5 {
+            assert!(breaker.check::<(), _>(Err(Flaky)).is_ok());
+            assert!(breaker.check::<(), _>(Err(Flaky)).is_ok());
+            assert!(breaker.check::<_, Flaky>(Ok(())).is_ok());
+        }
+    }
+
+    #[test]
+    fn breaker_remembers_the_requested_delay() {
+        let mut breaker = Breaker::with_threshold(3);
+
+        assert!(breaker.check::<(), _>(Err(Throttled)).is_ok());
+        assert_eq!(breaker.retry_after(), Some(Duration::from_secs(90)));
+
+        assert!(breaker.check::<(), _>(Err(Flaky)).is_ok());
+        assert_eq!(breaker.retry_after(), None);
+    }
+
+    #[tokio::test]
+    async fn open_breaker_skips_work() {
+        let mut breaker = Breaker::with_threshold(1);
+        assert!(breaker.check::<(), _>(Err(Flaky)).is_err());
+        assert!(breaker.is_open());
+
+        let r = breaker
+            .run(async { panic!("Should not run while open") as Result<(), Flaky> })
+            .await;
+        assert!(matches!(r, Ok(None)));
+    }
+
+    #[test]
+    fn open_breaker_reports_its_cooldown() {
+        let cooldown = Duration::from_secs(5 * 60);
+        let mut breaker = Breaker::with_threshold(10).with_cooldown(cooldown);
+
+        for _ in 0..9 {
+            assert!(breaker.check::<(), _>(Err(Flaky)).is_ok());
+            assert_eq!(breaker.cooldown_remaining(), None);
+        }
+
+        assert!(breaker.check::<(), _>(Err(Flaky)).is_err());
+        let remaining = breaker.cooldown_remaining().unwrap();
+        assert!(remaining <= cooldown, "{:?}", remaining);
+        assert!(remaining > cooldown / 2, "{:?}", remaining);
+    }
+
+    #[tokio::test]
+    async fn half_open_breaker_recovers_or_reopens() {
+        let mut breaker = Breaker::with_threshold(1).with_cooldown(Duration::ZERO);
+        assert!(breaker.check::<(), _>(Err(Flaky)).is_err());
+
+        // The trial request failing opens the breaker again
+        assert!(breaker.run(async { Err::<(), _>(Flaky) }).await.is_err());
+        assert!(matches!(breaker.state, BreakerState::Open { .. }));
+
+        let r = breaker.run(async { Ok::<_, Flaky>(()) }).await;
+        assert!(matches!(r, Ok(Some(Ok(())))));
+        assert_eq!(breaker.state, BreakerState::Closed);
+        assert_eq!(breaker.failure_count, 0);
+    }
+}
@@ -1,12 +1,28 @@
 use crate::{
-    database::DbHandle,
-    domain::{AccountId, IncomingNotification, UserKey},
+    database::{DbHandle, NOTIFICATION_DEDUPE_KEY},
+    domain::{
+        AccountId, ConfiguredDestination, DeliveryPolicy, Destination, DestinationId,
+        IncomingNotification, Message, NotificationId, PollSources, RecentNotification,
+        RegistrationSummary, UserKey,
+    },
     error::IsTransient,
-    poll_spawner::PollSpawnerHandle,
-    pushover, GlobalStackOverflowConfig,
+    poll_spawner::{PollSpawnerHandle, POLL_MAX_PAGES},
+    pushover,
+    sink::{Backends, NotificationSink, SinkError},
+    stack_overflow::{Date, Pages},
+    template::Template,
+    GlobalStackOverflowConfig,
+};
+use parking_lot::Mutex;
+use serde::Serialize;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use snafu::{ResultExt, Snafu};
-use tracing::{trace, trace_span, Instrument};
+use time::OffsetDateTime;
+use tracing::{error, info, trace, trace_span, warn, Instrument};
 
 #[derive(Debug, Clone)]
 pub struct BootFlow {
@@ -22,12 +38,26 @@ impl BootFlow {
     pub async fn boot(&mut self) -> Result<()> {
         let Self { db, poll_spawner } = self;
 
+        let dedupe_index_present = db
+            .notification_dedupe_index_present()
+            .await
+            .context(UnableToCheckSchemaSnafu)?;
+        if !dedupe_index_present {
+            error!(
+                "The unique index on notifications ({}) is missing; \
+                 notifications cannot be deduplicated. Run the database migrations.",
+                NOTIFICATION_DEDUPE_KEY.join(", "),
+            );
+        }
+
         let registrations = db
             .registrations()
             .await
             .context(UnableToLoadRegistrationsSnafu)?;
-        poll_spawner.start_many(registrations).await;
-        Ok(())
+        poll_spawner
+            .start_polling_many(registrations)
+            .await
+            .context(UnableToStartPollingSnafu)
     }
 }
 
@@ -71,9 +101,16 @@ impl RegisterFlow {
         let account_id = resp.account_id;
         let access_token = so_client.access_token().clone();
 
-        db.register(account_id, access_token.clone())
+        let change = db
+            .register(account_id, access_token.clone())
             .await
             .context(UnableToPersistRegistrationSnafu)?;
+
+        trace!(change = ?change, "Saved the registration");
+
+        // Re-registering usually means the old token was revoked, so
+        // a poller still using it has to be replaced. The spawner
+        // leaves one that already has this token alone.
         poll_spawner.start_polling(account_id, access_token).await;
 
         Ok(account_id)
@@ -83,19 +120,270 @@ impl RegisterFlow {
 #[derive(Debug, Clone)]
 pub struct SetPushoverUserFlow {
     db: DbHandle,
+    pushover: pushover::Client,
 }
 
 impl SetPushoverUserFlow {
+    pub fn new(db: DbHandle, pushover: pushover::Client) -> Self {
+        Self { db, pushover }
+    }
+
+    // A mistyped key would otherwise silently swallow every notification
+    pub async fn set_pushover_user(
+        &mut self,
+        account_id: AccountId,
+        key: &str,
+    ) -> Result<DestinationId> {
+        let Self { db, pushover } = self;
+
+        let user = UserKey::parse(key).context(InvalidPushoverKeySnafu)?;
+
+        match pushover.validate_user(&user).await {
+            Ok(()) => {}
+            Err(e @ pushover::Error::UserKeyRejected { .. }) => {
+                return Err(e).context(InvalidPushoverKeySnafu)
+            }
+            Err(e) => return Err(e).context(UnableToValidatePushoverKeySnafu),
+        }
+
+        db.add_destination(account_id, Destination::Pushover(user))
+            .await
+            .context(UnableToPersistPushoverUserSnafu)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DestinationsFlow {
+    db: DbHandle,
+    backends: Backends,
+}
+
+impl DestinationsFlow {
+    const VERIFICATION_MESSAGE: &'static str =
+        "This destination will now receive your Stack Overflow notifications";
+
+    pub fn new(db: DbHandle, backends: Backends) -> Self {
+        Self { db, backends }
+    }
+
+    pub fn backends(&self) -> &Backends {
+        &self.backends
+    }
+
+    pub async fn add(
+        &mut self,
+        account_id: AccountId,
+        destination: Destination,
+    ) -> Result<DestinationId> {
+        let Self { db, .. } = self;
+
+        db.add_destination(account_id, destination)
+            .await
+            .context(UnableToAddDestinationSnafu)
+    }
+
+    pub async fn list(&mut self, account_id: AccountId) -> Result<Vec<ConfiguredDestination>> {
+        let Self { db, .. } = self;
+
+        db.destinations(account_id)
+            .await
+            .context(UnableToLoadDestinationsSnafu)
+    }
+
+    pub async fn set_enabled(
+        &mut self,
+        account_id: AccountId,
+        id: DestinationId,
+        enabled: bool,
+    ) -> Result<()> {
+        let Self { db, .. } = self;
+
+        db.set_destination_enabled(account_id, id, enabled)
+            .await
+            .context(UnableToUpdateDestinationSnafu)
+    }
+
+    pub async fn remove(&mut self, account_id: AccountId, id: DestinationId) -> Result<()> {
+        let Self { db, .. } = self;
+
+        db.remove_destination(account_id, id)
+            .await
+            .context(UnableToRemoveDestinationSnafu)
+    }
+
+    // Only destinations that have successfully received a test
+    // notification are sent real ones.
+    pub async fn verify(&mut self, account_id: AccountId, id: DestinationId) -> Result<()> {
+        let Self { db, backends } = self;
+
+        let destinations = db
+            .destinations(account_id)
+            .await
+            .context(UnableToLoadDestinationsSnafu)?;
+        let destination = destinations
+            .into_iter()
+            .find(|d| d.id == id)
+            .context(UnknownDestinationSnafu)?;
+
+        backends
+            .notify(
+                account_id,
+                &destination.destination,
+                vec![Self::VERIFICATION_MESSAGE.into()],
+            )
+            .await
+            .context(UnableToVerifyDestinationSnafu)?;
+
+        db.mark_destination_verified(account_id, id)
+            .await
+            .context(UnableToUpdateDestinationSnafu)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HistoryFlow {
+    db: DbHandle,
+}
+
+impl HistoryFlow {
+    // Keeps the page (and the query behind it) small
+    const LIMIT: i64 = 50;
+
     pub fn new(db: DbHandle) -> Self {
         Self { db }
     }
 
-    pub async fn set_pushover_user(&mut self, account_id: AccountId, user: UserKey) -> Result<()> {
+    pub async fn recent(&mut self, account_id: AccountId) -> Result<Vec<RecentNotification>> {
         let Self { db } = self;
 
-        db.set_pushover_user(account_id, user)
+        db.recent_notifications(account_id, Self::LIMIT)
             .await
-            .context(UnableToPersistPushoverUserSnafu)?;
+            .context(UnableToLoadRecentNotificationsSnafu)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DeregisterFlow {
+    db: DbHandle,
+    poll_spawner: PollSpawnerHandle,
+}
+
+impl DeregisterFlow {
+    pub fn new(db: DbHandle, poll_spawner: PollSpawnerHandle) -> Self {
+        Self { db, poll_spawner }
+    }
+
+    pub async fn deregister(&mut self, account_id: AccountId) -> Result<()> {
+        let Self { db, poll_spawner } = self;
+
+        // Stop first so the poller is unlikely to write anything new
+        // for the account
+        poll_spawner.stop_polling(account_id).await;
+
+        db.deregister(account_id)
+            .await
+            .context(UnableToDeregisterSnafu)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AdminFlow {
+    db: DbHandle,
+    poll_spawner: PollSpawnerHandle,
+}
+
+#[derive(Debug)]
+pub struct UserStatus {
+    pub registration: RegistrationSummary,
+    pub polling: bool,
+}
+
+#[derive(Debug)]
+pub struct UserPage {
+    pub users: Vec<UserStatus>,
+    pub has_more: bool,
+    pub total_polling: usize,
+}
+
+impl AdminFlow {
+    const PAGE_SIZE: i64 = 50;
+
+    pub fn new(db: DbHandle, poll_spawner: PollSpawnerHandle) -> Self {
+        Self { db, poll_spawner }
+    }
+
+    pub async fn users(&mut self, search: Option<AccountId>, page: u32) -> Result<UserPage> {
+        let Self { db, poll_spawner } = self;
+
+        let offset = i64::from(page) * Self::PAGE_SIZE;
+        // Ask for one extra to know if there's another page
+        let mut users = db
+            .registration_summaries(search, offset, Self::PAGE_SIZE + 1)
+            .await
+            .context(UnableToLoadUsersSnafu)?;
+
+        let has_more = users.len() as i64 > Self::PAGE_SIZE;
+        users.truncate(Self::PAGE_SIZE as usize);
+
+        let polling: HashSet<_> = poll_spawner.list_polling().await.into_iter().collect();
+
+        let users = users
+            .into_iter()
+            .map(|registration| UserStatus {
+                polling: polling.contains(&registration.account_id),
+                registration,
+            })
+            .collect();
+        let total_polling = polling.len();
+
+        Ok(UserPage {
+            users,
+            has_more,
+            total_polling,
+        })
+    }
+
+    // Linked their account but never told us where to deliver
+    pub async fn incomplete_setups(&mut self) -> Result<Vec<AccountId>> {
+        let Self { db, .. } = self;
+
+        db.accounts_without_destination()
+            .await
+            .context(UnableToLoadUsersSnafu)
+    }
+
+    pub async fn stop_polling(&mut self, account_id: AccountId) -> bool {
+        let Self { poll_spawner, .. } = self;
+
+        poll_spawner.stop_polling(account_id).await
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MaintenanceFlow {
+    db: DbHandle,
+    statement_timeout: Duration,
+}
+
+impl MaintenanceFlow {
+    pub fn new(db: DbHandle, statement_timeout: Duration) -> Self {
+        Self {
+            db,
+            statement_timeout,
+        }
+    }
+
+    pub async fn vacuum(&mut self) -> Result<()> {
+        let Self {
+            db,
+            statement_timeout,
+        } = self;
+
+        let start = Instant::now();
+        db.vacuum_notifications(*statement_timeout)
+            .await
+            .context(UnableToVacuumSnafu)?;
+        info!(elapsed = ?start.elapsed(), "Vacuumed notifications");
 
         Ok(())
     }
@@ -105,19 +393,31 @@ impl SetPushoverUserFlow {
 pub struct ProxyNotificationsFlow {
     so_config: GlobalStackOverflowConfig,
     db: DbHandle,
-    pushover: pushover::Client,
+    sink: Arc<dyn NotificationSink>,
+    delivery_policy: DeliveryPolicy,
+    skip_backlog: bool,
+    poll_sources: PollSources,
+    template: Template,
 }
 
 impl ProxyNotificationsFlow {
     pub fn new(
         so_config: GlobalStackOverflowConfig,
         db: DbHandle,
-        pushover: pushover::Client,
+        sink: Arc<dyn NotificationSink>,
+        delivery_policy: DeliveryPolicy,
+        skip_backlog: bool,
+        poll_sources: PollSources,
+        template: Template,
     ) -> Self {
         Self {
             so_config,
             db,
-            pushover,
+            sink,
+            delivery_policy,
+            skip_backlog,
+            poll_sources,
+            template,
         }
     }
 
@@ -129,7 +429,11 @@ impl ProxyNotificationsFlow {
         let Self {
             so_config,
             db,
-            pushover,
+            sink,
+            delivery_policy,
+            skip_backlog,
+            poll_sources,
+            template,
         } = self;
 
         let so_client = crate::stack_overflow::AuthClient::new(so_config.clone(), access_token);
@@ -137,7 +441,12 @@ impl ProxyNotificationsFlow {
         ProxyNotificationsAuthFlow {
             so_client,
             db,
-            pushover,
+            sink,
+            delivery_policy,
+            skip_backlog,
+            poll_sources,
+            template,
+            registered_at: None,
             account_id,
         }
     }
@@ -147,65 +456,377 @@ impl ProxyNotificationsFlow {
 pub struct ProxyNotificationsAuthFlow {
     so_client: crate::stack_overflow::AuthClient,
     db: DbHandle,
-    pushover: pushover::Client,
+    sink: Arc<dyn NotificationSink>,
+    delivery_policy: DeliveryPolicy,
+    skip_backlog: bool,
+    poll_sources: PollSources,
+    template: Template,
+    registered_at: Option<OffsetDateTime>,
     account_id: AccountId,
 }
 
+#[derive(Debug)]
+pub struct DeliveryOutcome {
+    pub destination: Destination,
+    pub notifications: Vec<NotificationId>,
+    pub result: Result<(), SinkError>,
+}
+
 impl ProxyNotificationsAuthFlow {
-    pub async fn proxy(&mut self) -> Result<()> {
+    pub fn take_backoff(&self) -> Option<std::time::Duration> {
+        self.so_client.take_backoff()
+    }
+
+    pub fn quota_status(&self) -> crate::stack_overflow::QuotaStatus {
+        self.so_client.quota_status()
+    }
+
+    pub async fn last_polled_at(&self) -> Result<Option<OffsetDateTime>> {
+        let Self { db, account_id, .. } = self;
+
+        db.last_polled_at(*account_id)
+            .await
+            .context(UnableToLoadRegistrationSnafu)
+    }
+
+    // Stops the account from being polled at boot until the user
+    // registers again
+    pub async fn mark_needs_reauth(&self) -> Result<()> {
+        let Self { db, account_id, .. } = self;
+
+        db.mark_needs_reauth(*account_id)
+            .await
+            .context(UnableToMarkNeedsReauthSnafu)
+    }
+
+    pub async fn proxy(&mut self) -> Result<Vec<DeliveryOutcome>> {
         let s = trace_span!("notify");
         let Self {
             so_client,
             db,
-            pushover,
+            sink,
+            delivery_policy,
+            skip_backlog,
+            poll_sources,
+            template,
+            registered_at,
             account_id,
         } = self;
         let account_id = *account_id;
+        let delivery_policy = *delivery_policy;
 
         async {
-            let (a, b) = futures::join!(so_client.unread_notifications(), so_client.unread_inbox());
+            // Items from before the user signed up are their backlog
+            let cutoff = match (*skip_backlog, *registered_at) {
+                (false, _) => None,
+                (true, Some(t)) => Some(t),
+                (true, None) => {
+                    let t = db
+                        .registered_at(account_id)
+                        .await
+                        .context(UnableToLoadRegistrationSnafu)?;
+                    *registered_at = t;
+                    t
+                }
+            };
+            let is_new = |d: Date| match (cutoff, d.to_datetime()) {
+                (Some(cutoff), Some(d)) => d >= cutoff,
+                _ => true,
+            };
 
-            let a = a?.into_iter().map(|n| IncomingNotification {
-                account_id,
-                text: n.body,
+            // Anything older has already been relayed. Items from the
+            // same second come back again and are caught by the
+            // database's dedupe.
+            let since = db
+                .relayed_until(account_id)
+                .await
+                .context(UnableToLoadRegistrationSnafu)?
+                .map(|t| Date(t.unix_timestamp()));
+
+            // Skipping a source saves its share of the API quota
+            let notifications = async {
+                if poll_sources.notifications {
+                    so_client
+                        .unread_notifications_since(POLL_MAX_PAGES, since)
+                        .await
+                } else {
+                    Ok(Pages::default())
+                }
+            };
+            let inbox = async {
+                if poll_sources.inbox {
+                    so_client.unread_inbox_since(POLL_MAX_PAGES, since).await
+                } else {
+                    Ok(Pages::default())
+                }
+            };
+            let (a, b) = futures::join!(notifications, inbox);
+            let (a, b) = (a?, b?);
+            let complete = a.complete && b.complete;
+            let (a, b) = (a.items, b.items);
+
+            let now = OffsetDateTime::now_utc();
+
+            // Includes the backlog, which is skipped rather than relayed
+            let newest = a
+                .iter()
+                .map(|n| n.creation_date)
+                .chain(b.iter().map(|i| i.creation_date))
+                .max()
+                .map(|d| d.to_datetime_or(now));
+
+            let so_client = &*so_client;
+
+            let a = a.into_iter().filter(|n| is_new(n.creation_date)).map(|n| {
+                let text = template.render(&n.notification_type, &n.body, None);
+                let kind = type_name(&n.notification_type);
+                let url = n.post_id.map(|p| so_client.post_url(p));
+                (text, kind, n.post_id, url, n.creation_date)
             });
 
-            let b = b?.into_iter().map(|i| IncomingNotification {
-                account_id,
-                text: i.body,
+            // Inbox items link to exactly where the reply is
+            let b = b.into_iter().filter(|i| is_new(i.creation_date)).map(|i| {
+                let text = template.render(&i.item_type, &i.body, i.link.as_deref());
+                let kind = type_name(&i.item_type);
+                let url = i.link.or_else(|| i.post_id.map(|p| so_client.post_url(p)));
+                (text, kind, i.post_id, url, i.creation_date)
             });
 
-            let notifications: Vec<_> = a.chain(b).collect();
+            let notifications = a
+                .chain(b)
+                .map(|(text, notification_type, post_id, url, creation_date)| {
+                    text.map(|text| IncomingNotification {
+                        account_id,
+                        notification_type,
+                        post_id,
+                        text,
+                        url,
+                        created_at: creation_date.to_datetime_or(now),
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .context(UnableToRenderNotificationSnafu)?;
+
+            db.record_poll(account_id)
+                .await
+                .context(UnableToRecordPollSnafu)?;
 
-            if notifications.is_empty() {
+            let new_notifications = if notifications.is_empty() {
                 trace!("No notifications present");
-                return Ok(());
+                vec![]
+            } else {
+                db.add_new_notifications(notifications)
+                    .await
+                    .context(UnableToPersistNotificationsSnafu)?
             };
 
-            let new_notifications = db
-                .add_new_notifications(notifications)
-                .await
-                .context(UnableToPersistNotificationsSnafu)?;
             if new_notifications.is_empty() {
-                trace!("All notifications have been seen");
-                return Ok(());
+                trace!("All notifications have been delivered");
+
+                if let Some(until) = relayed_until(complete, newest, None) {
+                    db.advance_relayed_until(account_id, until)
+                        .await
+                        .context(UnableToRecordRelayedUntilSnafu)?;
+                }
+                return Ok(vec![]);
+            }
+
+            // Whatever isn't delivered needs to be fetched again
+            let mut undelivered: HashMap<_, _> = new_notifications
+                .iter()
+                .map(|n| (n.id, n.created_at))
+                .collect();
+
+            let mut by_destination = HashMap::<_, Vec<_>>::new();
+            for n in new_notifications {
+                by_destination
+                    .entry(n.destination.clone())
+                    .or_default()
+                    .push(n);
+            }
+
+            let mut outcomes = Vec::with_capacity(by_destination.len());
+            for (destination, group) in by_destination {
+                let notifications = group.iter().map(|n| n.id).collect();
+
+                let result = sink.deliver(group).await;
+
+                outcomes.push(DeliveryOutcome {
+                    destination,
+                    notifications,
+                    result,
+                });
             }
 
-            pushover
-                .notify(new_notifications)
+            let delivered = delivered_notifications(delivery_policy, &outcomes);
+            for id in &delivered {
+                undelivered.remove(id);
+            }
+            db.mark_delivered(delivered)
                 .await
-                .context(UnableToDeliverNotificationsSnafu)?;
+                .context(UnableToPersistDeliverySnafu)?;
+
+            if let Some(until) = relayed_until(complete, newest, undelivered.into_values()) {
+                db.advance_relayed_until(account_id, until)
+                    .await
+                    .context(UnableToRecordRelayedUntilSnafu)?;
+            }
 
-            Ok(())
+            // When nothing could be delivered, let the caller decide
+            // if the failure is worth retrying.
+            if outcomes.iter().all(|o| o.result.is_err()) {
+                if let Some(e) = outcomes.into_iter().find_map(|o| o.result.err()) {
+                    return Err(e).context(UnableToDeliverNotificationsSnafu);
+                }
+                return Ok(vec![]);
+            }
+
+            for o in &outcomes {
+                if let Err(e) = &o.result {
+                    warn!("Unable to deliver to {:?}: {}", o.destination, e);
+                }
+            }
+
+            Ok(outcomes)
         }
         .instrument(s)
         .await
     }
 }
 
+// The next poll only fetches items from here on, so it can't move
+// past anything that still needs to be relayed
+fn relayed_until(
+    complete: bool,
+    newest: Option<OffsetDateTime>,
+    undelivered: impl IntoIterator<Item = OffsetDateTime>,
+) -> Option<OffsetDateTime> {
+    // A truncated fetch may have left older items behind
+    if !complete {
+        return None;
+    }
+
+    let newest = newest?;
+    Some(undelivered.into_iter().fold(newest, |a, b| a.min(b)))
+}
+
+// The name Stack Overflow uses, including for types we don't know
+fn type_name<T: Serialize>(kind: &T) -> String {
+    match serde_json::to_value(kind) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ResendFlow {
+    db: DbHandle,
+    backends: Backends,
+    last_resend: Arc<Mutex<HashMap<AccountId, Instant>>>,
+}
+
+impl ResendFlow {
+    const COUNT: i64 = 5;
+    const INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+    pub fn new(db: DbHandle, backends: Backends) -> Self {
+        Self {
+            db,
+            backends,
+            last_resend: Default::default(),
+        }
+    }
+
+    pub async fn resend(&mut self, account_id: AccountId) -> Result<()> {
+        let Self {
+            db,
+            backends,
+            last_resend,
+        } = self;
+
+        Self::allow_resend(&mut last_resend.lock(), account_id, Instant::now())?;
+
+        let notifications = db
+            .recent_notifications(account_id, Self::COUNT)
+            .await
+            .context(UnableToLoadRecentNotificationsSnafu)?;
+        let destinations = db
+            .destinations(account_id)
+            .await
+            .context(UnableToLoadDestinationsSnafu)?;
+
+        // Oldest first, as they were originally delivered
+        let messages: Vec<_> = notifications
+            .into_iter()
+            .rev()
+            .map(|n| Message {
+                text: format!("[Resent] {}", n.text),
+                url: n.url,
+            })
+            .collect();
+
+        // Resending must not reach destinations that were never verified
+        for d in destinations.into_iter().filter(|d| d.enabled && d.verified) {
+            backends
+                .notify(account_id, &d.destination, messages.clone())
+                .await
+                .context(UnableToResendNotificationsSnafu)?;
+        }
+
+        Ok(())
+    }
+
+    // Entries that no longer limit anything are dropped, so the map
+    // only holds accounts that resent recently
+    fn allow_resend(
+        last_resend: &mut HashMap<AccountId, Instant>,
+        account_id: AccountId,
+        now: Instant,
+    ) -> Result<()> {
+        last_resend.retain(|_, previous| now.duration_since(*previous) < Self::INTERVAL);
+        ensure!(
+            !last_resend.contains_key(&account_id),
+            ResendRateLimitedSnafu
+        );
+        last_resend.insert(account_id, now);
+        Ok(())
+    }
+}
+
+fn delivered_notifications(
+    policy: DeliveryPolicy,
+    outcomes: &[DeliveryOutcome],
+) -> Vec<NotificationId> {
+    // (successful deliveries, attempted deliveries)
+    let mut tally = HashMap::<_, (usize, usize)>::new();
+
+    for o in outcomes {
+        for &id in &o.notifications {
+            let (succeeded, attempted) = tally.entry(id).or_default();
+            *attempted += 1;
+            if o.result.is_ok() {
+                *succeeded += 1;
+            }
+        }
+    }
+
+    tally
+        .into_iter()
+        .filter(|&(_, (succeeded, attempted))| match policy {
+            DeliveryPolicy::Any => succeeded > 0,
+            DeliveryPolicy::All => succeeded == attempted,
+        })
+        .map(|(id, _)| id)
+        .collect()
+}
+
 #[derive(Debug, Snafu)]
 #[allow(clippy::enum_variant_names)]
 pub enum Error {
+    UnableToCheckSchema {
+        source: crate::database::Error,
+    },
+
     UnableToLoadRegistrations {
         source: crate::database::Error,
     },
@@ -223,10 +844,45 @@ pub enum Error {
         source: crate::database::Error,
     },
 
+    UnableToStartPolling {
+        source: alictor::ActorError,
+    },
+
+    #[snafu(display("The Pushover user key is invalid: {}", source))]
+    InvalidPushoverKey {
+        source: pushover::Error,
+    },
+
+    UnableToValidatePushoverKey {
+        source: pushover::Error,
+    },
+
     UnableToPersistPushoverUser {
         source: crate::database::Error,
     },
 
+    UnableToLoadDestinations {
+        source: crate::database::Error,
+    },
+
+    UnableToAddDestination {
+        source: crate::database::Error,
+    },
+
+    UnableToUpdateDestination {
+        source: crate::database::Error,
+    },
+
+    UnknownDestination,
+
+    UnableToVerifyDestination {
+        source: SinkError,
+    },
+
+    UnableToRemoveDestination {
+        source: crate::database::Error,
+    },
+
     #[snafu(context(false))]
     UnableToGetUnreadNotifications {
         source: crate::stack_overflow::UnreadNotificationsError,
@@ -237,12 +893,58 @@ pub enum Error {
         source: crate::stack_overflow::UnreadInboxError,
     },
 
+    UnableToRecordPoll {
+        source: crate::database::Error,
+    },
+
+    UnableToRecordRelayedUntil {
+        source: crate::database::Error,
+    },
+
+    UnableToMarkNeedsReauth {
+        source: crate::database::Error,
+    },
+
+    UnableToDeregister {
+        source: crate::database::Error,
+    },
+
+    UnableToLoadRegistration {
+        source: crate::database::Error,
+    },
+
+    UnableToVacuum {
+        source: crate::database::Error,
+    },
+
+    UnableToRenderNotification {
+        source: crate::template::Error,
+    },
+
     UnableToPersistNotifications {
         source: crate::database::Error,
     },
 
+    UnableToLoadUsers {
+        source: crate::database::Error,
+    },
+
+    ResendRateLimited,
+
+    UnableToLoadRecentNotifications {
+        source: crate::database::Error,
+    },
+
+    UnableToResendNotifications {
+        source: SinkError,
+    },
+
+    UnableToPersistDelivery {
+        source: crate::database::Error,
+    },
+
     UnableToDeliverNotifications {
-        source: crate::pushover::Error,
+        source: SinkError,
     },
 }
 
@@ -252,9 +954,121 @@ impl IsTransient for Error {
             Self::UnableToGetUnreadNotifications { source } => source.is_transient(),
             Self::UnableToGetUnreadInbox { source } => source.is_transient(),
             Self::UnableToDeliverNotifications { source } => source.is_transient(),
+            Self::UnableToLoadRegistration { source }
+            | Self::UnableToRecordPoll { source }
+            | Self::UnableToRecordRelayedUntil { source }
+            | Self::UnableToPersistNotifications { source }
+            | Self::UnableToPersistDelivery { source } => source.is_transient(),
+            _ => false,
+        }
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::UnableToDeliverNotifications { source } => source.retry_after(),
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    pub fn is_auth_failure(&self) -> bool {
+        match self {
+            Self::UnableToGetUnreadNotifications { source } => source.is_auth_failure(),
+            Self::UnableToGetUnreadInbox { source } => source.is_auth_failure(),
             _ => false,
         }
     }
 }
 
 type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use time::macros::datetime;
+
+    fn outcome(id: NotificationId, result: Result<(), SinkError>) -> DeliveryOutcome {
+        DeliveryOutcome {
+            destination: Destination::Webhook("http://example.com/".parse().unwrap()),
+            notifications: vec![id],
+            result,
+        }
+    }
+
+    fn undelivered(
+        fetched: &[(NotificationId, OffsetDateTime)],
+        outcomes: &[DeliveryOutcome],
+    ) -> Vec<OffsetDateTime> {
+        let delivered = delivered_notifications(DeliveryPolicy::Any, outcomes);
+        fetched
+            .iter()
+            .filter(|(id, _)| !delivered.contains(id))
+            .map(|&(_, created_at)| created_at)
+            .collect()
+    }
+
+    #[test]
+    fn failed_delivery_is_fetched_again_until_it_succeeds() {
+        let older = (NotificationId(1), datetime!(2024-06-01 12:00 UTC));
+        let newer = (NotificationId(2), datetime!(2024-06-01 12:05 UTC));
+        let fetched = [older, newer];
+
+        // The first poll delivers the newer item but not the older one
+        let failure = SinkError::new(pushover::Error::RateLimited {
+            retry_after: Duration::from_secs(60),
+        });
+        let outcomes = [outcome(older.0, Err(failure)), outcome(newer.0, Ok(()))];
+        let until = relayed_until(true, Some(newer.1), undelivered(&fetched, &outcomes));
+        assert_eq!(until, Some(older.1));
+
+        // Starting from there, the next poll fetches the older item again
+        let fetched = [older];
+        let outcomes = [outcome(older.0, Ok(()))];
+        let until = relayed_until(true, Some(newer.1), undelivered(&fetched, &outcomes));
+        assert_eq!(until, Some(newer.1));
+    }
+
+    #[test]
+    fn resending_again_within_the_interval_is_rejected() {
+        let mut last_resend = HashMap::new();
+        let account_id = AccountId(42);
+        let start = Instant::now();
+
+        assert!(ResendFlow::allow_resend(&mut last_resend, account_id, start).is_ok());
+
+        let soon = start + ResendFlow::INTERVAL / 2;
+        let e = ResendFlow::allow_resend(&mut last_resend, account_id, soon).unwrap_err();
+        assert!(matches!(e, Error::ResendRateLimited), "{:?}", e);
+
+        // Other accounts aren't limited by it
+        assert!(ResendFlow::allow_resend(&mut last_resend, AccountId(7), soon).is_ok());
+
+        let later = start + ResendFlow::INTERVAL;
+        assert!(ResendFlow::allow_resend(&mut last_resend, account_id, later).is_ok());
+    }
+
+    #[test]
+    fn expired_resend_limits_are_pruned() {
+        let mut last_resend = HashMap::new();
+        let start = Instant::now();
+
+        for id in 0..10 {
+            ResendFlow::allow_resend(&mut last_resend, AccountId(id), start).unwrap();
+        }
+        assert_eq!(last_resend.len(), 10);
+
+        let later = start + ResendFlow::INTERVAL;
+        ResendFlow::allow_resend(&mut last_resend, AccountId(42), later).unwrap();
+        assert_eq!(last_resend.len(), 1);
+    }
+
+    #[test]
+    fn truncated_fetch_does_not_advance() {
+        let newest = datetime!(2024-06-01 12:05 UTC);
+
+        assert_eq!(relayed_until(false, Some(newest), None), None);
+        assert_eq!(relayed_until(true, None, None), None);
+        assert_eq!(relayed_until(true, Some(newest), None), Some(newest));
+    }
+}
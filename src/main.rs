@@ -3,8 +3,9 @@
 #[macro_use]
 extern crate diesel;
 
-use diesel::{pg::PgConnection, prelude::*};
+use futures::{future::FusedFuture, FutureExt};
 use snafu::{ResultExt, Snafu};
+use tracing::{info, warn};
 
 pub use config::Config;
 
@@ -15,8 +16,13 @@ mod error;
 mod flow;
 mod poll_spawner;
 mod pushover;
+mod self_test;
+mod sink;
 mod stack_overflow;
+mod telegram;
+mod template;
 mod web_ui;
+mod webhook;
 
 type GlobalConfig = &'static Config;
 type GlobalStackOverflowConfig = &'static stack_overflow::Config;
@@ -36,42 +42,144 @@ fn main() {
 
 #[tokio::main]
 async fn core() -> Result<()> {
-    tracing_subscriber::fmt::init();
     dotenv::dotenv().ok();
 
+    let log_filter = config::log_filter().context(UnableToConfigureLoggingSnafu)?;
+    let log_format = config::log_format().context(UnableToConfigureLoggingSnafu)?;
+    let subscriber = tracing_subscriber::fmt().with_env_filter(log_filter);
+    match log_format {
+        config::LogFormat::Text => subscriber.init(),
+        // Span fields such as `account_id` become structured fields
+        config::LogFormat::Json => subscriber
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
+    }
+
     let config = Config::from_environment().context(UnableToConfigureSnafu)?;
+    config.warn_on_port_mismatch();
     let config = &*Box::leak(Box::new(config));
 
     let so_config =
         stack_overflow::Config::from_environment().context(UnableToConfigureStackOverflowSnafu)?;
     let so_config = &*Box::leak(Box::new(so_config));
 
+    let template = template::Template::new(&config.notification_template)
+        .context(InvalidNotificationTemplateSnafu)?;
+
     let pushover_config =
         pushover::Config::from_environment().context(UnableToConfigurePushoverSnafu)?;
-
-    let database_url = &config.database_url;
-    let conn =
-        PgConnection::establish(database_url).context(UnableToConnectSnafu { database_url })?;
-
-    let (db, db_task) = database::Db::new(conn).spawn();
+    let telegram_config =
+        telegram::Config::from_environment().context(UnableToConfigureTelegramSnafu)?;
+    let webhook_config =
+        webhook::Config::from_environment().context(UnableToConfigureWebhookSnafu)?;
+
+    if std::env::args().any(|a| a == "--self-test") {
+        return self_test::run(so_config, &pushover_config)
+            .await
+            .context(SelfTestFailedSnafu);
+    }
 
     let pushover = pushover_config.into_client();
-    let notify_flow = flow::ProxyNotificationsFlow::new(so_config, db.clone(), pushover);
+    let backends = sink::Backends::new(
+        pushover.clone(),
+        telegram_config.map(telegram::Config::into_client),
+        webhook_config.map(webhook::Config::into_client),
+    );
+
+    info!(
+        git_sha = env!("VERGEN_GIT_SHA"),
+        listen_address = %config.listen_address,
+        internal_listen_address = ?config.internal_listen_address,
+        public_uri = %config.public_uri,
+        stack_exchange_site = so_config.site(),
+        behind_proxy = config.behind_proxy,
+        secure_cookies = config.secure_cookies,
+        session_ttl = ?config.session_ttl,
+        database_url = %config.redacted_database_url(),
+        database_statement_timeout = ?config.database_statement_timeout,
+        database_pool_size = database::POOL_SIZE,
+        poll_interval = ?config.poll_interval,
+        poll_start_jitter = ?config.poll_start_jitter,
+        poll_max_pages = poll_spawner::POLL_MAX_PAGES,
+        backends = ?backends.names(),
+        delivery_policy = ?config.delivery_policy,
+        skip_backlog = config.skip_backlog,
+        poll_sources = ?config.poll_sources,
+        single_session = config.single_session,
+        admin_enabled = config.admin_token.is_some(),
+        caffeine_interval = ?config.caffeine_interval,
+        maintenance_interval = ?config.maintenance_interval,
+        maintenance_statement_timeout = ?config.maintenance_statement_timeout,
+        "Starting with resolved configuration",
+    );
 
-    let (poll_spawner, poll_spawner_task) = poll_spawner::PollSpawner::new(notify_flow).spawn();
+    let database_url = &config.database_url;
+    let (db, mut db_task) = database::Db::new(database_url, config.database_statement_timeout)
+        .context(UnableToConnectSnafu { database_url })?
+        .spawn();
+
+    let resend_flow = flow::ResendFlow::new(db.clone(), backends.clone());
+    let destinations_flow = flow::DestinationsFlow::new(db.clone(), backends.clone());
+    let notify_flow = flow::ProxyNotificationsFlow::new(
+        so_config,
+        db.clone(),
+        std::sync::Arc::new(backends),
+        config.delivery_policy,
+        config.skip_backlog,
+        config.poll_sources,
+        template,
+    );
+
+    let (mut poll_spawner, mut poll_spawner_task) =
+        poll_spawner::PollSpawner::new(notify_flow, config.poll_interval, config.poll_start_jitter)
+            .spawn();
 
     let mut boot_flow = flow::BootFlow::new(db.clone(), poll_spawner.clone());
     boot_flow.boot().await.context(UnableToBootSnafu)?;
 
     let register_flow = flow::RegisterFlow::new(so_config, db.clone(), poll_spawner.clone());
-    let set_pushover_user_flow = flow::SetPushoverUserFlow::new(db);
-
-    let web_ui = tokio::spawn(web_ui::serve(
+    let set_pushover_user_flow = flow::SetPushoverUserFlow::new(db.clone(), pushover.clone());
+    let mut maintenance_flow =
+        flow::MaintenanceFlow::new(db.clone(), config.maintenance_statement_timeout);
+    let admin_flow = flow::AdminFlow::new(db.clone(), poll_spawner.clone());
+    let deregister_flow = flow::DeregisterFlow::new(db.clone(), poll_spawner.clone());
+    let history_flow = flow::HistoryFlow::new(db.clone());
+
+    // Both web servers stop accepting connections when this is sent
+    let (shutdown_tx, shutdown_rx) = futures::channel::oneshot::channel::<()>();
+    let shutdown = shutdown_rx.map(drop).shared();
+
+    let mut web_ui = tokio::spawn(web_ui::serve(
         config,
         so_config,
         register_flow,
         set_pushover_user_flow,
-    ));
+        destinations_flow,
+        deregister_flow,
+        history_flow,
+        admin_flow,
+        resend_flow,
+        poll_spawner.clone(),
+        db.clone(),
+        shutdown.clone(),
+    ))
+    .fuse();
+
+    let internal_web_ui = async {
+        match config.internal_listen_address {
+            Some(address) => {
+                tokio::spawn(web_ui::serve_internal(
+                    address,
+                    poll_spawner.clone(),
+                    shutdown,
+                ))
+                .await
+            }
+            None => futures::future::pending().await,
+        }
+    };
 
     let caffeine_task = async {
         match config.caffeine_interval {
@@ -91,23 +199,86 @@ async fn core() -> Result<()> {
         }
     };
 
-    tokio::select! {
-        web_ui = web_ui => {
+    let maintenance_task = async move {
+        match config.maintenance_interval {
+            Some(interval) => {
+                tokio::spawn(async move {
+                    loop {
+                        tokio::time::sleep(interval).await;
+                        if let Err(e) = maintenance_flow.vacuum().await {
+                            warn!("Database maintenance failed: {}", e);
+                        }
+                    }
+                })
+                .await
+            }
+            None => futures::future::pending().await,
+        }
+    };
+
+    let result = tokio::select! {
+        signal = shutdown_signal() => {
+            info!("Shutting down");
+            signal.context(UnableToListenForShutdownSnafu)
+        }
+        web_ui = &mut web_ui => {
             web_ui.context(WebUiFailedSnafu)
         }
+        internal_web_ui = internal_web_ui => {
+            internal_web_ui.context(InternalWebUiFailedSnafu)
+        }
+        maintenance_task = maintenance_task => {
+            maintenance_task
+                .context(MaintenanceFailedSnafu)
+                .and_then(|_| MaintenanceExitedSnafu.fail())
+        }
         caffeine_task = caffeine_task => {
-            caffeine_task.context(CaffeineFailedSnafu)?;
-            CaffeineExitedSnafu.fail()
+            caffeine_task
+                .context(CaffeineFailedSnafu)
+                .and_then(|_| CaffeineExitedSnafu.fail())
         }
-        poll_spawner_task = poll_spawner_task => {
-            poll_spawner_task.context(PollSpawnerFailedSnafu)?.context(PollSpawnerErroredSnafu)?;
-            PollSpawnerExitedSnafu.fail()
+        poll_spawner_task = &mut poll_spawner_task => {
+            poll_spawner_task
+                .context(PollSpawnerFailedSnafu)
+                .and_then(|_| PollSpawnerExitedSnafu.fail())
         }
-        db_task = db_task => {
+        db_task = &mut db_task => {
             db_task.context(DatabaseFailedSnafu)?;
-            DatabaseExitedSnafu.fail()
+            return DatabaseExitedSnafu.fail();
+        }
+    };
+
+    // Nothing new should reach the database while it drains
+    shutdown_tx.send(()).ok();
+    // The spawner may be the reason we are exiting
+    poll_spawner.try_stop_all().await.ok();
+    poll_spawner_task.abort();
+
+    if !web_ui.is_terminated() {
+        web_ui.await.context(WebUiFailedSnafu)?;
+    }
+
+    // Let any in-flight database work finish instead of aborting it
+    db.shutdown(db_task).await.context(DatabaseFailedSnafu)?;
+
+    result
+}
+
+// Heroku sends SIGTERM before killing the dyno
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            ctrl_c = tokio::signal::ctrl_c() => ctrl_c,
+            _ = terminate.recv() => Ok(()),
         }
     }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
 }
 
 const USER_AGENT: &str = concat!(
@@ -117,14 +288,20 @@ const USER_AGENT: &str = concat!(
 );
 
 fn reqwest_client() -> reqwest::Client {
+    // The Stack Exchange API always compresses its responses
     reqwest::Client::builder()
         .user_agent(USER_AGENT)
+        .gzip(true)
+        .deflate(true)
         .build()
         .expect("Unable to configure reqwest::Client")
 }
 
 #[derive(Debug, Snafu)]
 enum Error {
+    #[snafu(display("Unable to configure logging"))]
+    UnableToConfigureLogging { source: config::Error },
+
     #[snafu(display("Unable to configure application"))]
     UnableToConfigure { source: config::Error },
 
@@ -134,33 +311,54 @@ enum Error {
     #[snafu(display("Unable to configure Pushover integration"))]
     UnableToConfigurePushover { source: pushover::Error },
 
+    #[snafu(display("Unable to configure Telegram integration"))]
+    UnableToConfigureTelegram { source: telegram::Error },
+
+    #[snafu(display("Unable to configure webhook integration"))]
+    UnableToConfigureWebhook { source: webhook::Error },
+
+    #[snafu(display("The self-test did not pass"))]
+    SelfTestFailed { source: self_test::Error },
+
+    #[snafu(display("Unable to configure the notification template"))]
+    InvalidNotificationTemplate { source: template::Error },
+
     #[snafu(display("Error connecting to {}", database_url))]
     UnableToConnect {
-        source: diesel::ConnectionError,
+        source: database::Error,
         database_url: String,
     },
 
     #[snafu(display("Unable to boot background workers"))]
     UnableToBoot { source: flow::Error },
 
+    #[snafu(display("Unable to listen for the shutdown signal"))]
+    UnableToListenForShutdown { source: std::io::Error },
+
     #[snafu(display("The web UI failed"))]
     WebUiFailed { source: tokio::task::JoinError },
 
+    #[snafu(display("The internal web UI failed"))]
+    InternalWebUiFailed { source: tokio::task::JoinError },
+
     #[snafu(display("The poll spawner exited and never should"))]
     PollSpawnerExited,
 
     #[snafu(display("The poll spawner failed and never should"))]
     PollSpawnerFailed { source: tokio::task::JoinError },
 
-    #[snafu(display("The poll spawner errored and never should"))]
-    PollSpawnerErrored { source: poll_spawner::Error },
-
     #[snafu(display("The database exited and never should"))]
     DatabaseExited,
 
     #[snafu(display("The database failed and never should"))]
     DatabaseFailed { source: tokio::task::JoinError },
 
+    #[snafu(display("The maintenance task exited and never should"))]
+    MaintenanceExited,
+
+    #[snafu(display("The maintenance task failed and never should"))]
+    MaintenanceFailed { source: tokio::task::JoinError },
+
     #[snafu(display("The caffeine task exited and never should"))]
     CaffeineExited,
 
@@ -1,125 +1,364 @@
 use crate::{
-    error::Breaker,
+    error::{Breaker, Chain},
     flow::{ProxyNotificationsAuthFlow, ProxyNotificationsFlow},
-    stack_overflow::{AccessToken, AccountId},
-};
-use futures::{
-    channel::mpsc,
-    future, select,
-    stream::{self, FuturesUnordered},
-    SinkExt, StreamExt,
+    stack_overflow::{self, AccessToken, AccountId, QuotaStatus},
 };
+use ::time::OffsetDateTime;
+use futures::future::{self, AbortHandle, Aborted};
+use parking_lot::Mutex;
 use snafu::{ResultExt, Snafu};
-use std::{collections::HashMap, time::Duration};
-use tokio::{task::JoinHandle, time};
-use tracing::{trace, trace_span, warn, Instrument};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::time;
+use tracing::{debug, error, trace, trace_span, warn, Instrument};
+
+// Every poll re-reads all unread items, so keep each one's share of
+// the API quota small
+pub(crate) const POLL_MAX_PAGES: u32 = 3;
+
+// Upper bounds, in seconds
+pub(crate) const POLL_DURATION_BUCKETS: [f64; 8] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+#[derive(Debug, Clone, Default)]
+pub struct PollDurations {
+    // Cumulative, as each bucket counts everything at or below its bound
+    pub buckets: [u64; POLL_DURATION_BUCKETS.len()],
+    pub count: u64,
+    pub sum: Duration,
+}
+
+impl PollDurations {
+    fn record(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bucket, &bound) in self.buckets.iter_mut().zip(&POLL_DURATION_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += duration;
+    }
+}
 
 #[derive(Debug)]
 pub struct PollSpawner {
     flow: ProxyNotificationsFlow,
+    poll_interval: Duration,
+    start_jitter: Duration,
+    pollers: Pollers,
+    durations: Arc<Mutex<PollDurations>>,
+    next_generation: u64,
 }
 
 impl PollSpawner {
-    pub fn new(flow: ProxyNotificationsFlow) -> Self {
-        Self { flow }
+    pub fn new(
+        flow: ProxyNotificationsFlow,
+        poll_interval: Duration,
+        start_jitter: Duration,
+    ) -> Self {
+        Self {
+            flow,
+            poll_interval,
+            start_jitter,
+            pollers: Default::default(),
+            durations: Default::default(),
+            next_generation: 0,
+        }
     }
+}
 
-    pub(crate) fn spawn(self) -> (PollSpawnerHandle, JoinHandle<Result<()>>) {
-        let Self { flow } = self;
-
-        let (tx, mut rx) = mpsc::channel(10);
-
-        let task = tokio::task::spawn(async move {
-            let mut pollers = HashMap::new();
-            let mut children = FuturesUnordered::new();
+#[alictor::alictor(batch = start_polling)]
+impl PollSpawner {
+    fn start_polling(&mut self, account_id: AccountId, access_token: AccessToken) {
+        // Only a poller that already has this token can be kept.
+        // One with an older token may be about to have it rejected,
+        // which would disable the account again.
+        let unchanged = self
+            .pollers
+            .lock()
+            .get(&account_id)
+            .map_or(false, |p| p.access_token == access_token);
+        if unchanged {
+            trace!(account_id = account_id.0, "Already polling with this token");
+            return;
+        }
 
-            loop {
-                select! {
-                    (account_id, access_token) = rx.select_next_some() => {
-                        trace!("Starting new polling task");
+        trace!("Starting new polling task");
 
-                        let flow = flow.clone().auth(account_id, access_token);
+        let flow = self.flow.clone().auth(account_id, access_token.clone());
 
-                        let work = poll_one_account(flow, account_id);
-                        let (work, abort_handle) = future::abortable(work);
+        let work = poll_one_account(
+            flow,
+            account_id,
+            self.poll_interval,
+            self.start_jitter,
+            self.durations.clone(),
+        );
+        let (work, abort_handle) = future::abortable(work);
 
-                        children.push(tokio::spawn(work));
+        let generation = self.next_generation;
+        self.next_generation += 1;
 
-                        let old_handle = pollers.insert(account_id, abort_handle);
-                        if let Some(old_handle) = old_handle {
-                            old_handle.abort();
-                        }
-                    }
+        // Recorded before the child starts, so it can't finish first
+        // and find nothing to remove
+        let poller = Poller {
+            generation,
+            access_token,
+            abort_handle,
+        };
+        let old_poller = self.pollers.lock().insert(account_id, poller);
+        if let Some(old_poller) = old_poller {
+            old_poller.abort_handle.abort();
+        }
 
-                    child = children.select_next_some() => {
-                        match child.context(ChildFailedSnafu)? {
-                            Ok(v) => v?,
-                            Err(_) => warn!("Second worker started"),
-                        }
-                    }
-                }
+        let child = tokio::spawn(work);
+        let pollers = self.pollers.clone();
+        tokio::spawn(async move {
+            if let Err(e) = child_finished(&pollers, account_id, generation, child.await) {
+                error!(
+                    account_id = account_id.0,
+                    "Polling task did not finish: {}",
+                    Chain(&e)
+                );
             }
         });
+    }
+
+    // Reports if the account was being polled
+    fn stop_polling(&mut self, account_id: AccountId) -> bool {
+        trace!(account_id = account_id.0, "Stopping polling task");
+        match self.pollers.lock().remove(&account_id) {
+            Some(poller) => {
+                poller.abort_handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Used when the process is exiting
+    fn stop_all(&mut self) {
+        for (_, poller) in self.pollers.lock().drain() {
+            poller.abort_handle.abort();
+        }
+    }
+
+    fn list_polling(&mut self) -> Vec<AccountId> {
+        self.pollers.lock().keys().copied().collect()
+    }
 
-        (PollSpawnerHandle(tx), task)
+    fn poll_durations(&mut self) -> PollDurations {
+        self.durations.lock().clone()
+    }
+}
+
+type ChildResult = Result<Result<Result<()>, Aborted>, tokio::task::JoinError>;
+
+fn child_finished(
+    pollers: &Pollers,
+    account_id: AccountId,
+    generation: u64,
+    child: ChildResult,
+) -> Result<()> {
+    match child {
+        Ok(Ok(v)) => {
+            // The poller gave up on its own, such as for a rejected token
+            remove_poller(pollers, account_id, generation);
+
+            // One account's failure shouldn't stop everyone else's polling
+            if let Err(e) = v {
+                error!(
+                    account_id = account_id.0,
+                    "Polling task failed: {}",
+                    Chain(&e)
+                );
+            }
+            Ok(())
+        }
+        // Stopping and replacing pollers is routine
+        Ok(Err(Aborted)) => {
+            debug!(account_id = account_id.0, "Polling task was stopped");
+            Ok(())
+        }
+        // A bug in one account's poller shouldn't stop everyone else's
+        Err(e) if e.is_panic() => {
+            error!(account_id = account_id.0, "Polling task panicked");
+            remove_poller(pollers, account_id, generation);
+            Ok(())
+        }
+        Err(e) => Err(e).context(ChildFailedSnafu),
     }
 }
 
 async fn poll_one_account(
     mut flow: ProxyNotificationsAuthFlow,
     account_id: AccountId,
+    poll_interval: Duration,
+    start_jitter: Duration,
+    durations: Arc<Mutex<PollDurations>>,
 ) -> Result<()> {
     let s = trace_span!("poll_one_account", account_id = account_id.0);
     async {
         trace!("Starting polling");
 
+        // Restarting shouldn't poll everyone at once, or sooner than usual
+        let delay = match flow.last_polled_at().await {
+            Ok(last_polled_at) => {
+                initial_delay(poll_interval, last_polled_at, OffsetDateTime::now_utc())
+            }
+            Err(e) => {
+                warn!("Unable to find when the account was last polled: {}", e);
+                Duration::ZERO
+            }
+        };
+        let delay = delay.max(jitter(account_id, start_jitter));
+        if delay > Duration::ZERO {
+            trace!(delay = ?delay, "Delaying the first poll");
+            time::sleep(delay).await;
+        }
+
         let mut breaker = Breaker::default();
 
         loop {
-            let attempt = breaker.run(flow.proxy());
+            let skipped = breaker.is_open();
+            let start = Instant::now();
+            let attempt = breaker.run(flow.proxy()).await;
 
-            if let Some(attempt) = attempt.await.context(TooManyTransientFailuresSnafu)? {
-                attempt.context(UnableToProxyNotificationsSnafu)?;
+            // Skipped polls would skew the durations towards zero
+            if !skipped {
+                let elapsed = start.elapsed();
+                trace!(elapsed = ?elapsed, "Poll finished");
+                durations.lock().record(elapsed);
             }
 
-            time::sleep(Duration::from_secs(60)).await;
+            // The breaker lets polling resume once its cooldown passes
+            let attempt = attempt.unwrap_or_else(|e| {
+                warn!("Pausing polling: {}", e);
+                None
+            });
+
+            if let Some(attempt) = attempt {
+                match attempt {
+                    Err(e) if e.is_auth_failure() => {
+                        warn!("Stopping polling as the access token was rejected: {}", e);
+                        flow.mark_needs_reauth()
+                            .await
+                            .context(UnableToMarkNeedsReauthSnafu)?;
+                        return Ok(());
+                    }
+                    attempt => {
+                        attempt.context(UnableToProxyNotificationsSnafu)?;
+                    }
+                }
+            }
+
+            let interval = match breaker.cooldown_remaining() {
+                // The backoff would put the half-open trial off for far
+                // longer than the cooldown intends
+                Some(cooldown) => poll_delay(poll_interval, Some(cooldown)),
+                None => {
+                    // A rate-limited backend says when it will accept more
+                    let interval = retry_delay(poll_interval, breaker.failure_count());
+                    poll_delay(interval, breaker.retry_after())
+                }
+            };
+            let interval = poll_delay(interval, flow.take_backoff());
+
+            // Polling with an exhausted quota would only trip the breaker
+            let interval = match flow.quota_status() {
+                QuotaStatus::Low { remaining } => {
+                    warn!(
+                        remaining,
+                        "Stack Exchange quota is low; waiting for it to reset"
+                    );
+                    let reset = stack_overflow::until_quota_reset(OffsetDateTime::now_utc());
+                    poll_delay(interval, Some(reset))
+                }
+                QuotaStatus::Available => interval,
+            };
+            time::sleep(interval).await;
         }
     }
     .instrument(s)
     .await
 }
 
-type Pair = (AccountId, AccessToken);
-
-#[derive(Debug, Clone)]
-pub struct PollSpawnerHandle(mpsc::Sender<Pair>);
+// The same account always gets the same offset, keeping the accounts
+// evenly spread across restarts
+fn jitter(account_id: AccountId, range: Duration) -> Duration {
+    use rand::{Rng, SeedableRng};
 
-impl PollSpawnerHandle {
-    pub async fn try_start_many(&mut self, registrations: Vec<Pair>) -> Option<()> {
-        self.0
-            .send_all(&mut stream::iter(registrations).map(Ok))
-            .await
-            .ok()
+    if range == Duration::ZERO {
+        return Duration::ZERO;
     }
 
-    pub async fn start_many(&mut self, registrations: Vec<Pair>) {
-        self.try_start_many(registrations)
-            .await
-            .expect("The actor is no longer running")
-    }
+    let mut rng = rand::rngs::StdRng::seed_from_u64(account_id.0 as u64);
+    rng.gen_range(Duration::ZERO..range)
+}
 
-    pub async fn try_start_polling(
-        &mut self,
-        account_id: AccountId,
-        access_token: AccessToken,
-    ) -> Option<()> {
-        self.0.send((account_id, access_token)).await.ok()
+// Retries during an outage get further apart, up to a limit
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30 * 60);
+
+fn retry_delay(poll_interval: Duration, failures: usize) -> Duration {
+    use rand::Rng;
+
+    if failures == 0 {
+        return poll_interval;
     }
 
-    pub async fn start_polling(&mut self, account_id: AccountId, access_token: AccessToken) {
-        self.try_start_polling(account_id, access_token)
-            .await
-            .expect("The actor is no longer running")
+    let exponent = u32::try_from(failures).unwrap_or(u32::MAX).min(16);
+    let delay = poll_interval
+        .saturating_mul(2u32.pow(exponent))
+        .min(MAX_RETRY_DELAY)
+        .max(poll_interval);
+
+    let jitter = rand::thread_rng().gen_range(Duration::ZERO..=poll_interval);
+    delay + jitter
+}
+
+// The rest of the interval that started with the previous poll
+fn initial_delay(
+    poll_interval: Duration,
+    last_polled_at: Option<OffsetDateTime>,
+    now: OffsetDateTime,
+) -> Duration {
+    let elapsed = match last_polled_at {
+        Some(last_polled_at) => now - last_polled_at,
+        None => return Duration::ZERO,
+    };
+
+    // A last poll in the future means the clock moved; wait a full interval
+    let elapsed = Duration::try_from(elapsed).unwrap_or(Duration::ZERO);
+    poll_interval.saturating_sub(elapsed)
+}
+
+// Stack Exchange asks us to wait when we request too often
+pub(crate) fn poll_delay(poll_interval: Duration, backoff: Option<Duration>) -> Duration {
+    backoff.map_or(poll_interval, |b| b.max(poll_interval))
+}
+
+#[derive(Debug)]
+struct Poller {
+    generation: u64,
+    access_token: AccessToken,
+    abort_handle: AbortHandle,
+}
+
+type Pollers = Arc<Mutex<HashMap<AccountId, Poller>>>;
+
+// A replaced or stopped poller can finish after a newer one has
+// started for the same account; that one must stay
+fn remove_poller(pollers: &Pollers, account_id: AccountId, generation: u64) {
+    let mut pollers = pollers.lock();
+    if pollers
+        .get(&account_id)
+        .map_or(false, |p| p.generation == generation)
+    {
+        pollers.remove(&account_id);
     }
 }
 
@@ -129,7 +368,140 @@ pub(crate) enum Error {
 
     UnableToProxyNotifications { source: crate::flow::Error },
 
-    TooManyTransientFailures { source: crate::error::BreakerError },
+    UnableToMarkNeedsReauth { source: crate::flow::Error },
 }
 
 type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn insert_poller(pollers: &Pollers, account_id: AccountId, generation: u64) {
+        let (abort_handle, _) = AbortHandle::new_pair();
+        let poller = Poller {
+            generation,
+            access_token: AccessToken(String::from("token")),
+            abort_handle,
+        };
+        pollers.lock().insert(account_id, poller);
+    }
+
+    #[tokio::test]
+    async fn panicking_child_does_not_stop_the_spawner() {
+        let pollers = Pollers::default();
+        let account_id = AccountId(42);
+        insert_poller(&pollers, account_id, 0);
+
+        let child = tokio::spawn(async {
+            if true {
+                panic!("The poller failed");
+            }
+            Ok(Ok(()))
+        })
+        .await;
+
+        assert!(child_finished(&pollers, account_id, 0, child).is_ok());
+        assert!(!pollers.lock().contains_key(&account_id));
+    }
+
+    #[tokio::test]
+    async fn failing_child_does_not_stop_the_spawner() {
+        let pollers = Pollers::default();
+        let account_id = AccountId(42);
+        insert_poller(&pollers, account_id, 0);
+
+        let child = tokio::spawn(async {
+            let e = crate::pushover::Error::RateLimited {
+                retry_after: Duration::from_secs(60),
+            };
+            Ok(Err(Error::UnableToProxyNotifications {
+                source: crate::flow::Error::UnableToDeliverNotifications {
+                    source: crate::sink::SinkError::new(e),
+                },
+            }))
+        })
+        .await;
+
+        assert!(child_finished(&pollers, account_id, 0, child).is_ok());
+        assert!(!pollers.lock().contains_key(&account_id));
+    }
+
+    #[tokio::test]
+    async fn replaced_child_does_not_remove_its_replacement() {
+        let pollers = Pollers::default();
+        let account_id = AccountId(42);
+        insert_poller(&pollers, account_id, 1);
+
+        // The replaced poller finished before its abort was seen
+        let child = tokio::spawn(async { Ok(Ok(())) }).await;
+        assert!(child_finished(&pollers, account_id, 0, child).is_ok());
+        assert!(pollers.lock().contains_key(&account_id));
+
+        let child = tokio::spawn(async {
+            if true {
+                panic!("The poller failed");
+            }
+            Ok(Ok(()))
+        })
+        .await;
+        assert!(child_finished(&pollers, account_id, 0, child).is_ok());
+        assert!(pollers.lock().contains_key(&account_id));
+
+        let child = tokio::spawn(async { Ok(Ok(())) }).await;
+        assert!(child_finished(&pollers, account_id, 1, child).is_ok());
+        assert!(!pollers.lock().contains_key(&account_id));
+    }
+
+    #[test]
+    fn initial_delay_waits_out_the_rest_of_the_interval() {
+        let interval = Duration::from_secs(60);
+        let now = OffsetDateTime::now_utc();
+
+        assert_eq!(initial_delay(interval, None, now), Duration::ZERO);
+
+        let recently = now - Duration::from_secs(15);
+        assert_eq!(
+            initial_delay(interval, Some(recently), now),
+            Duration::from_secs(45)
+        );
+
+        let long_ago = now - Duration::from_secs(600);
+        assert_eq!(initial_delay(interval, Some(long_ago), now), Duration::ZERO);
+
+        let future = now + Duration::from_secs(30);
+        assert_eq!(initial_delay(interval, Some(future), now), interval);
+    }
+
+    #[test]
+    fn retry_delay_grows_to_a_limit() {
+        let interval = Duration::from_secs(60);
+
+        assert_eq!(retry_delay(interval, 0), interval);
+
+        let first = retry_delay(interval, 1);
+        assert!(
+            first >= 2 * interval && first <= 3 * interval,
+            "{:?}",
+            first
+        );
+
+        let many = retry_delay(interval, 1000);
+        assert!(many >= MAX_RETRY_DELAY, "{:?}", many);
+        assert!(many <= MAX_RETRY_DELAY + interval, "{:?}", many);
+    }
+
+    #[test]
+    fn jitter_is_bounded_and_stable_per_account() {
+        let range = Duration::from_secs(60);
+
+        for id in 0..100 {
+            let account_id = AccountId(id);
+            let j = jitter(account_id, range);
+            assert!(j < range);
+            assert_eq!(j, jitter(account_id, range));
+        }
+
+        assert_eq!(jitter(AccountId(42), Duration::ZERO), Duration::ZERO);
+    }
+}
@@ -1,27 +1,228 @@
-use crate::{domain::OutgoingNotification, error::IsTransient};
+use crate::{
+    domain::{Destination, Message, OutgoingNotification},
+    error::IsTransient,
+    sink::{NotificationSink, SinkError},
+};
+use futures::{future::BoxFuture, stream, FutureExt, StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
-use snafu::{ResultExt, Snafu};
-use std::env;
-use tracing::{trace, trace_span, Instrument};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::{collections::HashMap, env, num::NonZeroUsize, time::Duration};
+use tokio::time;
+use tracing::{trace, trace_span, warn, Instrument};
 use url::Url;
 
+const DEFAULT_API_BASE: &str = "https://api.pushover.net/";
+
+// https://pushover.net/api#limits
+const MESSAGE_LIMIT: usize = 1024;
+
+// Give Pushover some breathing room between the parts of split messages
+const SPLIT_DELAY: Duration = Duration::from_secs(1);
+
+// Smaller limits can't fit the part numbers and a useful amount of text
+const MIN_SPLIT_LIMIT: usize = 32;
+
+// Pushover limits how many messages an application may send
+const DEFAULT_CONCURRENCY: usize = 4;
+
+// Used when a rate-limited response doesn't say how long to wait
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+const APP_REMAINING_HEADER: &str = "X-Limit-App-Remaining";
+
+// https://pushover.net/api#identifiers
+const USER_KEY_LENGTH: usize = 30;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct UserKey(pub String);
 
+impl UserKey {
+    pub fn parse(key: &str) -> Result<Self> {
+        // Copying the key out of the Pushover dashboard often brings whitespace along
+        let key = key.trim();
+
+        let length = key.chars().count();
+        ensure!(
+            length == USER_KEY_LENGTH,
+            InvalidUserKeyLengthSnafu { length }
+        );
+        ensure!(
+            key.chars().all(|c| c.is_ascii_alphanumeric()),
+            InvalidUserKeyCharactersSnafu
+        );
+
+        Ok(Self(key.into()))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     token: String,
     notify_url: Url,
+    validate_url: Url,
+    overflow: Overflow,
+    max_message_len: usize,
+    split_delay: Duration,
+    concurrency: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Overflow {
+    Truncate,
+    Split,
+}
+
+impl Overflow {
+    fn apply(self, message: &str, limit: usize) -> Vec<String> {
+        if message.chars().count() <= limit {
+            return vec![message.to_owned()];
+        }
+
+        match self {
+            Self::Split if limit >= MIN_SPLIT_LIMIT => split(message, limit),
+            Self::Truncate | Self::Split => vec![truncate(message, limit)],
+        }
+    }
+}
+
+// Pushover counts characters, so a multi-byte character is never cut in half
+fn truncate(message: &str, limit: usize) -> String {
+    let mut message: String = message.chars().take(limit.saturating_sub(1)).collect();
+    message.push('…');
+    message
+}
+
+// Each part is prefixed with "n/total ", so the room left depends on
+// how many parts there end up being
+fn split(message: &str, limit: usize) -> Vec<String> {
+    let chars: Vec<_> = message.chars().collect();
+
+    let mut digits = 1;
+    loop {
+        let prefix_len = 2 * digits + "/ ".len();
+        let chunks = chunks(&chars, limit.saturating_sub(prefix_len).max(1));
+        let total = chunks.len();
+
+        if total.to_string().len() > digits {
+            digits += 1;
+            continue;
+        }
+
+        return chunks
+            .into_iter()
+            .enumerate()
+            .map(|(i, chunk)| format!("{}/{} {}", i + 1, total, chunk))
+            .collect();
+    }
+}
+
+fn chunks(mut chars: &[char], chunk_len: usize) -> Vec<String> {
+    let mut chunks = vec![];
+
+    while !chars.is_empty() {
+        let len = if chars.len() <= chunk_len {
+            chars.len()
+        } else {
+            split_point(&chars[..=chunk_len])
+        };
+        let (chunk, rest) = chars.split_at(len);
+        chars = rest;
+
+        let chunk: String = chunk.iter().collect();
+        let chunk = chunk.trim();
+        if !chunk.is_empty() {
+            chunks.push(chunk.to_owned());
+        }
+    }
+
+    chunks
+}
+
+// The message is HTML, so prefer to end a part at whitespace and never
+// in the middle of a tag or entity. `window` is one longer than the
+// part may be, to see if the part could end just before it.
+fn split_point(window: &[char]) -> usize {
+    let max = window.len() - 1;
+    let mut in_tag = false;
+    let mut in_entity = false;
+    let mut last_space = None;
+    let mut last_boundary = None;
+
+    for (i, &c) in window.iter().enumerate() {
+        if !in_tag && !in_entity && i != 0 {
+            last_boundary = Some(i);
+        }
+        if i == max {
+            break;
+        }
+
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            '&' if !in_tag => in_entity = true,
+            ';' => in_entity = false,
+            c if c.is_whitespace() => {
+                in_entity = false;
+                if !in_tag {
+                    last_space = Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    last_space.or(last_boundary).unwrap_or(max)
 }
 
 impl Config {
     pub fn from_environment() -> Result<Self> {
         let token = env::var("PUSHOVER_API_TOKEN").context(UnknownApiTokenSnafu)?;
+        let overflow = env::var("PUSHOVER_MESSAGE_OVERFLOW").ok();
+        let api_base = env::var("PUSHOVER_API_BASE").unwrap_or_else(|_| DEFAULT_API_BASE.into());
+        let concurrency = env::var("PUSHOVER_CONCURRENCY").ok();
+
+        let overflow = match overflow.as_deref() {
+            None | Some("truncate") => Overflow::Truncate,
+            Some("split") => Overflow::Split,
+            Some(overflow) => return InvalidMessageOverflowSnafu { overflow }.fail(),
+        };
+
+        let api_base = Url::parse(&api_base).context(InvalidApiBaseSnafu { api_base })?;
 
-        let notify_url = Url::parse("https://api.pushover.net/1/messages.json")
+        let concurrency = match concurrency {
+            Some(concurrency) => concurrency
+                .parse::<NonZeroUsize>()
+                .ok()
+                .context(InvalidConcurrencySnafu { concurrency })?
+                .get(),
+            None => DEFAULT_CONCURRENCY,
+        };
+
+        let config = Self::new(token, &api_base, overflow)?;
+        Ok(Self {
+            concurrency,
+            ..config
+        })
+    }
+
+    fn new(token: impl Into<String>, api_base: &Url, overflow: Overflow) -> Result<Self> {
+        let token = token.into();
+        let notify_url = api_base
+            .join("1/messages.json")
+            .context(UnableToConfigureNotifyUrlSnafu)?;
+        let validate_url = api_base
+            .join("1/users/validate.json")
             .context(UnableToConfigureNotifyUrlSnafu)?;
 
-        Ok(Self { token, notify_url })
+        Ok(Self {
+            token,
+            notify_url,
+            validate_url,
+            overflow,
+            max_message_len: MESSAGE_LIMIT,
+            split_delay: SPLIT_DELAY,
+            concurrency: DEFAULT_CONCURRENCY,
+        })
     }
 
     pub fn into_client(self) -> Client {
@@ -39,9 +240,48 @@ pub struct Client {
 }
 
 impl Client {
-    pub async fn notify(&self, notifications: Vec<OutgoingNotification>) -> Result<()> {
+    pub async fn notify(&self, user: &UserKey, messages: Vec<Message>) -> Result<()> {
+        let Self { config, .. } = self;
+        let s = trace_span!("notify", count = messages.len());
+
+        async {
+            trace!("Performing notifications");
+
+            let (whole, in_parts): (Vec<_>, Vec<_>) = messages
+                .iter()
+                .map(|m| {
+                    let parts = config.overflow.apply(&m.text, config.max_message_len);
+                    (m, parts)
+                })
+                .partition(|(_, parts)| parts.len() == 1);
+
+            // Separate messages don't need to wait for each other
+            stream::iter(&whole)
+                .map(|(message, parts)| self.notify_one(user, message, &parts[0]))
+                .buffer_unordered(config.concurrency)
+                .try_collect::<()>()
+                .await?;
+
+            // The parts of split messages arrive in order, one message
+            // after another, and spaced out to avoid Pushover's limits
+            let parts = in_parts
+                .iter()
+                .flat_map(|(message, parts)| parts.iter().map(move |part| (*message, part)));
+            for (i, (message, part)) in parts.enumerate() {
+                if i != 0 {
+                    time::sleep(config.split_delay).await;
+                }
+                self.notify_one(user, message, part).await?;
+            }
+
+            Ok(())
+        }
+        .instrument(s)
+        .await
+    }
+
+    async fn notify_one(&self, user: &UserKey, message: &Message, part: &str) -> Result<()> {
         let Self { client, config } = self;
-        let s = trace_span!("notify", count = notifications.len());
 
         #[derive(Debug, Serialize)]
         struct NotifyParams<'a> {
@@ -50,35 +290,140 @@ impl Client {
             title: &'a str,
             message: &'a str,
             html: u8,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            url: Option<&'a str>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            url_title: Option<&'a str>,
         }
 
-        async {
-            trace!("Performing notifications");
+        let url = message.url.as_deref();
+
+        let params = NotifyParams {
+            token: &config.token,
+            user,
+            title: "Stack Overflow notification",
+            message: part,
+            html: 1,
+            url,
+            url_title: url.map(|_| "View on Stack Overflow"),
+        };
+
+        let res = client
+            .post(config.notify_url.clone())
+            .query(&params)
+            .send()
+            .await
+            .context(UnableToSendNotificationSnafu)?;
 
-            let notifications = notifications.iter().map(|n| NotifyParams {
-                token: &config.token,
-                user: &n.user,
-                title: "Stack Overflow notification",
-                message: &n.text,
-                html: 1,
-            });
+        ensure_delivered(res).await
+    }
+}
 
+impl Client {
+    // Asks Pushover if the user key belongs to an account with a device
+    pub async fn validate_user(&self, user: &UserKey) -> Result<()> {
+        let Self { client, config } = self;
+
+        #[derive(Debug, Serialize)]
+        struct ValidateParams<'a> {
+            token: &'a str,
+            user: &'a UserKey,
+        }
+
+        let params = ValidateParams {
+            token: &config.token,
+            user,
+        };
+
+        let res = client
+            .post(config.validate_url.clone())
+            .query(&params)
+            .send()
+            .await
+            .context(UnableToValidateUserSnafu)?;
+
+        match ensure_delivered(res).await {
+            Err(Error::NotificationRejected { status, errors }) if status.is_client_error() => {
+                UserKeyRejectedSnafu { errors }.fail()
+            }
+            r => r,
+        }
+    }
+}
+
+impl NotificationSink for Client {
+    fn deliver(
+        &self,
+        notifications: Vec<OutgoingNotification>,
+    ) -> BoxFuture<'_, Result<(), SinkError>> {
+        async move {
+            let mut by_user = HashMap::<_, Vec<_>>::new();
             for n in notifications {
-                client
-                    .post(config.notify_url.clone())
-                    .query(&n)
-                    .send()
-                    .await
-                    .context(UnableToSendNotificationSnafu)?;
+                match n.destination {
+                    Destination::Pushover(user) => by_user.entry(user).or_default().push(Message {
+                        text: n.text,
+                        url: n.url,
+                    }),
+                    destination => warn!("Pushover cannot deliver to {:?}", destination),
+                }
+            }
+
+            for (user, messages) in by_user {
+                self.notify(&user, messages).await.map_err(SinkError::new)?;
             }
 
             Ok(())
         }
-        .instrument(s)
-        .await
+        .boxed()
+    }
+}
+
+// https://pushover.net/api#response
+#[derive(Debug, Deserialize)]
+struct NotifyResponse {
+    status: i32,
+    #[serde(default)]
+    errors: Vec<String>,
+}
+
+async fn ensure_delivered(res: reqwest::Response) -> Result<()> {
+    let status = res.status();
+
+    // https://pushover.net/api#limits
+    if let Some(remaining) = res.headers().get(APP_REMAINING_HEADER) {
+        trace!(?remaining, "Pushover messages remaining this month");
+    }
+
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = retry_after(&res).unwrap_or(DEFAULT_RETRY_AFTER);
+        return RateLimitedSnafu { retry_after }.fail();
+    }
+
+    let body = res.json::<NotifyResponse>().await;
+
+    match body {
+        Ok(body) if body.status == 1 => Ok(()),
+        Ok(body) => NotificationRejectedSnafu {
+            status,
+            errors: body.errors,
+        }
+        .fail(),
+        // Outages and proxies don't always respond with Pushover's JSON
+        Err(_) if !status.is_success() => NotificationRejectedSnafu {
+            status,
+            errors: Vec::new(),
+        }
+        .fail(),
+        Err(e) => Err(e).context(UnableToDeserializeResponseSnafu),
     }
 }
 
+fn retry_after(res: &reqwest::Response) -> Option<Duration> {
+    let value = res.headers().get(reqwest::header::RETRY_AFTER)?;
+    let seconds = value.to_str().ok()?.trim().parse().ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
 #[derive(Debug, Snafu)]
 pub enum Error {
     #[snafu(display("PUSHOVER_API_TOKEN must be set"))]
@@ -86,6 +431,37 @@ pub enum Error {
         source: env::VarError,
     },
 
+    #[snafu(display("PUSHOVER_MESSAGE_OVERFLOW must be `truncate` or `split`"))]
+    InvalidMessageOverflow {
+        overflow: String,
+    },
+
+    #[snafu(display(
+        "A Pushover user key is {} characters long, but {} were provided",
+        USER_KEY_LENGTH,
+        length
+    ))]
+    InvalidUserKeyLength {
+        length: usize,
+    },
+
+    #[snafu(display("A Pushover user key may only contain letters and numbers"))]
+    InvalidUserKeyCharacters,
+
+    #[snafu(display(
+        "PUSHOVER_CONCURRENCY must be a positive number, not {:?}",
+        concurrency
+    ))]
+    InvalidConcurrency {
+        concurrency: String,
+    },
+
+    #[snafu(display("PUSHOVER_API_BASE is invalid"))]
+    InvalidApiBase {
+        source: url::ParseError,
+        api_base: String,
+    },
+
     UnableToConfigureNotifyUrl {
         source: url::ParseError,
     },
@@ -93,15 +469,349 @@ pub enum Error {
     UnableToSendNotification {
         source: reqwest::Error,
     },
+
+    UnableToDeserializeResponse {
+        source: reqwest::Error,
+    },
+
+    UnableToValidateUser {
+        source: reqwest::Error,
+    },
+
+    #[snafu(display("Pushover does not recognize the user key: {}", errors.join(", ")))]
+    UserKeyRejected {
+        errors: Vec<String>,
+    },
+
+    #[snafu(display(
+        "Pushover rejected the notification ({}): {}",
+        status,
+        errors.join(", ")
+    ))]
+    NotificationRejected {
+        status: reqwest::StatusCode,
+        errors: Vec<String>,
+    },
+
+    #[snafu(display("Pushover is rate limiting us; retry after {:?}", retry_after))]
+    RateLimited {
+        retry_after: Duration,
+    },
 }
 
 impl IsTransient for Error {
     fn is_transient(&self) -> bool {
         match self {
-            Self::UnableToSendNotification { source } => source.is_transient(),
+            Self::UnableToSendNotification { source } | Self::UnableToValidateUser { source } => {
+                source.is_transient()
+            }
+            Self::NotificationRejected { status, .. } => {
+                status.is_server_error() || *status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }
+            Self::RateLimited { .. } => true,
             _ => false,
         }
     }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::RateLimited { retry_after } => Some(*retry_after),
+            _ => None,
+        }
+    }
 }
 
 type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parking_lot::Mutex;
+    use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+    use warp::Filter;
+
+    type Requests = Arc<Mutex<Vec<HashMap<String, String>>>>;
+
+    // Records the query parameters of every request
+    fn mock_server() -> (Url, Requests) {
+        let requests = Requests::default();
+
+        let route = {
+            let requests = requests.clone();
+            warp::post()
+                .and(warp::path!("1" / "messages.json"))
+                .and(warp::query::<HashMap<String, String>>())
+                .map(move |params| {
+                    requests.lock().push(params);
+                    r#"{"status":1}"#
+                })
+        };
+        let (address, server) =
+            warp::serve(route).bind_ephemeral(SocketAddr::from(([127, 0, 0, 1], 0)));
+        tokio::spawn(server);
+
+        let url = Url::parse(&format!("http://{address}/")).expect("Mock server URL is invalid");
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn notify_uses_the_api_base() {
+        let (api_base, requests) = mock_server();
+        let client = Config::new("api-token", &api_base, Overflow::Truncate)
+            .unwrap()
+            .into_client();
+        let user = UserKey("user-key".into());
+
+        client
+            .notify(&user, vec!["one".into(), "two".into()])
+            .await
+            .unwrap();
+
+        // The messages are sent concurrently, so may arrive in any order
+        let requests = requests.lock();
+        let mut messages: Vec<_> = requests.iter().map(|r| &*r["message"]).collect();
+        messages.sort_unstable();
+        assert_eq!(messages, ["one", "two"]);
+        assert!(requests.iter().all(|r| r["token"] == "api-token"));
+        assert!(requests.iter().all(|r| r["user"] == "user-key"));
+        assert!(requests.iter().all(|r| !r.contains_key("url")));
+        assert!(requests.iter().all(|r| !r.contains_key("url_title")));
+    }
+
+    #[tokio::test]
+    async fn notify_links_to_the_post() {
+        let (api_base, requests) = mock_server();
+        let client = Config::new("api-token", &api_base, Overflow::Truncate)
+            .unwrap()
+            .into_client();
+        let user = UserKey("user-key".into());
+        let message = Message {
+            text: "one".into(),
+            url: Some("https://stackoverflow.com/q/42".into()),
+        };
+
+        client.notify(&user, vec![message]).await.unwrap();
+
+        let requests = requests.lock();
+        assert_eq!(requests[0]["url"], "https://stackoverflow.com/q/42");
+        assert!(requests[0].contains_key("url_title"));
+    }
+
+    #[tokio::test]
+    async fn notify_truncates_long_messages() {
+        let (api_base, requests) = mock_server();
+        let mut config = Config::new("api-token", &api_base, Overflow::Truncate).unwrap();
+        config.max_message_len = 100;
+        let client = config.into_client();
+        let user = UserKey("user-key".into());
+
+        let message: String = "é".repeat(2000);
+        client
+            .notify(&user, vec![message.clone().into()])
+            .await
+            .unwrap();
+
+        let requests = requests.lock();
+        let sent = &requests[0]["message"];
+        assert_eq!(sent.chars().count(), 100);
+        assert!(sent.ends_with('…'));
+        assert!(message.starts_with(sent.trim_end_matches('…')));
+    }
+
+    // The text of every part, without the part numbers
+    fn contents(parts: &[String]) -> Vec<&str> {
+        parts
+            .iter()
+            .map(|p| p.split_once(' ').map_or("", |(_, text)| text))
+            .collect()
+    }
+
+    fn without_whitespace(s: &str) -> String {
+        s.chars().filter(|c| !c.is_whitespace()).collect()
+    }
+
+    #[test]
+    fn split_numbers_the_parts() {
+        let message = "word ".repeat(100);
+        let parts = split(&message, 40);
+
+        let total = parts.len();
+        assert!(total > 10, "{:?}", parts);
+        for (i, part) in parts.iter().enumerate() {
+            assert!(
+                part.starts_with(&format!("{}/{} ", i + 1, total)),
+                "{}",
+                part
+            );
+        }
+    }
+
+    #[test]
+    fn split_parts_never_exceed_the_limit() {
+        let messages = [
+            "x".repeat(5000),
+            "é".repeat(5000),
+            "日本語 ".repeat(1000),
+            "🦀 crab ".repeat(700),
+        ];
+
+        for message in &messages {
+            for &limit in &[MIN_SPLIT_LIMIT, 100, MESSAGE_LIMIT] {
+                let parts = split(message, limit);
+                for part in &parts {
+                    assert!(part.chars().count() <= limit, "{}", part);
+                }
+
+                // No characters are lost or cut in half
+                let rejoined = contents(&parts).concat();
+                assert_eq!(without_whitespace(&rejoined), without_whitespace(message));
+            }
+        }
+    }
+
+    #[test]
+    fn split_prefers_whitespace_and_keeps_markup_whole() {
+        let message = r#"<a href="https://stackoverflow.com/q/42">A question</a> &amp; an answer "#
+            .repeat(20);
+        let parts = split(&message, 64);
+
+        for text in contents(&parts) {
+            assert_eq!(
+                text.matches('<').count(),
+                text.matches('>').count(),
+                "{}",
+                text
+            );
+            assert!(!text.ends_with('&') && !text.ends_with("&amp"), "{}", text);
+            assert!(!text.starts_with("amp;"), "{}", text);
+        }
+
+        let parts = split(&"word ".repeat(100), 40);
+        for text in contents(&parts) {
+            assert!(text.split(' ').all(|w| w == "word"), "{}", text);
+        }
+    }
+
+    #[test]
+    fn split_falls_back_to_truncating_for_tiny_limits() {
+        let parts = Overflow::Split.apply(&"x".repeat(100), MIN_SPLIT_LIMIT - 1);
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].chars().count(), MIN_SPLIT_LIMIT - 1);
+    }
+
+    #[tokio::test]
+    async fn notify_sends_split_messages_one_after_another() {
+        let (api_base, requests) = mock_server();
+        let mut config = Config::new("api-token", &api_base, Overflow::Split).unwrap();
+        config.max_message_len = 40;
+        config.split_delay = Duration::ZERO;
+        let client = config.into_client();
+        let user = UserKey("user-key".into());
+
+        let a = "alpha ".repeat(20);
+        let b = "bravo ".repeat(20);
+        client
+            .notify(&user, vec![a.into(), "short".into(), b.into()])
+            .await
+            .unwrap();
+
+        let requests = requests.lock();
+        let messages: Vec<_> = requests.iter().map(|r| &*r["message"]).collect();
+        let parts: Vec<_> = messages.iter().filter(|m| **m != "short").collect();
+
+        assert!(messages.contains(&"short"));
+        let a_parts = parts.iter().take_while(|m| m.contains("alpha")).count();
+        assert!(a_parts > 1, "{:?}", messages);
+        assert!(
+            parts[a_parts..].iter().all(|m| m.contains("bravo")),
+            "{:?}",
+            messages
+        );
+        assert!(parts[0].starts_with("1/"), "{:?}", messages);
+        assert!(parts[a_parts].starts_with("1/"), "{:?}", messages);
+    }
+
+    fn rejecting_client(status: u16, body: &'static str) -> Client {
+        let status = warp::http::StatusCode::from_u16(status).unwrap();
+        let route = warp::any().map(move || warp::reply::with_status(body, status));
+        let (address, server) =
+            warp::serve(route).bind_ephemeral(SocketAddr::from(([127, 0, 0, 1], 0)));
+        tokio::spawn(server);
+
+        let api_base = Url::parse(&format!("http://{address}/")).unwrap();
+        Config::new("api-token", &api_base, Overflow::Truncate)
+            .unwrap()
+            .into_client()
+    }
+
+    async fn notify_error(status: u16, body: &'static str) -> Error {
+        let user = UserKey("user-key".into());
+        rejecting_client(status, body)
+            .notify(&user, vec!["one".into()])
+            .await
+            .unwrap_err()
+    }
+
+    #[tokio::test]
+    async fn notify_reports_rejections() {
+        let e = notify_error(
+            400,
+            r#"{"status":0,"errors":["user identifier is invalid"]}"#,
+        )
+        .await;
+        assert!(
+            matches!(e, Error::NotificationRejected { ref errors, .. } if errors == &["user identifier is invalid"]),
+            "{:?}",
+            e,
+        );
+        assert!(!e.is_transient());
+    }
+
+    #[tokio::test]
+    async fn validate_user_reports_unknown_keys() {
+        let user = UserKey("user-key".into());
+        let e = rejecting_client(400, r#"{"status":0,"errors":["user key is invalid"]}"#)
+            .validate_user(&user)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(e, Error::UserKeyRejected { ref errors } if errors == &["user key is invalid"]),
+            "{:?}",
+            e,
+        );
+    }
+
+    #[tokio::test]
+    async fn notify_retries_outages_and_rate_limits() {
+        let e = notify_error(500, "Internal Server Error").await;
+        assert!(e.is_transient(), "{:?}", e);
+
+        let e = notify_error(429, r#"{"status":0,"errors":["too many requests"]}"#).await;
+        assert!(e.is_transient(), "{:?}", e);
+        assert_eq!(e.retry_after(), Some(DEFAULT_RETRY_AFTER));
+    }
+
+    #[tokio::test]
+    async fn notify_reports_how_long_to_wait() {
+        let route = warp::any().map(|| {
+            let reply = warp::reply::with_status("", warp::http::StatusCode::TOO_MANY_REQUESTS);
+            warp::reply::with_header(reply, "retry-after", "120")
+        });
+        let (address, server) =
+            warp::serve(route).bind_ephemeral(SocketAddr::from(([127, 0, 0, 1], 0)));
+        tokio::spawn(server);
+
+        let api_base = Url::parse(&format!("http://{address}/")).unwrap();
+        let client = Config::new("api-token", &api_base, Overflow::Truncate)
+            .unwrap()
+            .into_client();
+        let user = UserKey("user-key".into());
+
+        let e = client.notify(&user, vec!["one".into()]).await.unwrap_err();
+        assert!(
+            matches!(e, Error::RateLimited { retry_after } if retry_after == Duration::from_secs(120)),
+            "{:?}",
+            e,
+        );
+    }
+}
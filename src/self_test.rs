@@ -0,0 +1,61 @@
+use crate::{domain::UserKey, pushover, stack_overflow};
+use snafu::{ensure, Snafu};
+use std::env;
+
+const MESSAGE: &str = "Test notification from the stack-overflow-relay self-test";
+
+pub async fn run(
+    so_config: &stack_overflow::Config,
+    pushover_config: &pushover::Config,
+) -> Result<()> {
+    let mut failures = 0;
+
+    let so_client = so_config.clone().into_unauth_client();
+    report(
+        "Stack Exchange key",
+        so_client.check_key().await,
+        &mut failures,
+    );
+
+    match env::var("SELF_TEST_PUSHOVER_USER") {
+        Ok(user) => match UserKey::parse(&user) {
+            Ok(user) => {
+                let client = pushover_config.clone().into_client();
+                let r = client.notify(&user, vec![MESSAGE.into()]).await;
+                report("Pushover notification", r, &mut failures);
+            }
+            Err(e) => report("Pushover notification", Err(e), &mut failures),
+        },
+        Err(_) => println!("Pushover notification: skipped; set SELF_TEST_PUSHOVER_USER"),
+    }
+
+    ensure!(failures == 0, FailedSnafu { failures });
+    Ok(())
+}
+
+fn report<E>(name: &str, r: Result<(), E>, failures: &mut usize)
+where
+    E: std::error::Error,
+{
+    match r {
+        Ok(()) => println!("{name}: ok"),
+        Err(e) => {
+            *failures += 1;
+
+            println!("{name}: failed: {e}");
+            let mut e = &e as &dyn std::error::Error;
+            while let Some(e2) = e.source() {
+                e = e2;
+                println!("     : {e}");
+            }
+        }
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("{} self-test check(s) failed", failures))]
+    Failed { failures: usize },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
@@ -0,0 +1,188 @@
+use crate::{
+    domain::{AccountId, Destination, Message, OutgoingNotification},
+    error::IsTransient,
+    pushover, telegram, webhook,
+};
+use futures::{future::BoxFuture, FutureExt};
+use snafu::Snafu;
+use std::{collections::HashMap, error::Error, fmt, time::Duration};
+
+// A backend that notifications can be delivered to
+pub trait NotificationSink: fmt::Debug + Send + Sync {
+    fn deliver(
+        &self,
+        notifications: Vec<OutgoingNotification>,
+    ) -> BoxFuture<'_, Result<(), SinkError>>;
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(display("Unable to deliver notifications"))]
+pub struct SinkError {
+    source: Box<dyn Error + Send + Sync + 'static>,
+    transient: bool,
+    retry_after: Option<Duration>,
+}
+
+impl SinkError {
+    pub fn new<E>(source: E) -> Self
+    where
+        E: Error + IsTransient + Send + Sync + 'static,
+    {
+        let transient = source.is_transient();
+        let retry_after = source.retry_after();
+        Self {
+            source: Box::new(source),
+            transient,
+            retry_after,
+        }
+    }
+}
+
+impl IsTransient for SinkError {
+    fn is_transient(&self) -> bool {
+        self.transient
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+}
+
+// Routes each notification to the backend its destination belongs to
+#[derive(Debug, Clone)]
+pub struct Backends {
+    pushover: pushover::Client,
+    telegram: Option<telegram::Client>,
+    webhook: Option<webhook::Client>,
+}
+
+impl Backends {
+    pub fn new(
+        pushover: pushover::Client,
+        telegram: Option<telegram::Client>,
+        webhook: Option<webhook::Client>,
+    ) -> Self {
+        Self {
+            pushover,
+            telegram,
+            webhook,
+        }
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        let Self {
+            telegram, webhook, ..
+        } = self;
+
+        let mut names = vec!["pushover"];
+        if telegram.is_some() {
+            names.push("telegram");
+        }
+        if webhook.is_some() {
+            names.push("webhook");
+        }
+        names
+    }
+
+    pub fn supports_telegram(&self) -> bool {
+        self.telegram.is_some()
+    }
+
+    pub fn supports_webhook(&self) -> bool {
+        self.webhook.is_some()
+    }
+
+    pub async fn notify(
+        &self,
+        account_id: AccountId,
+        destination: &Destination,
+        messages: Vec<Message>,
+    ) -> Result<(), SinkError> {
+        let Self {
+            pushover,
+            telegram,
+            webhook,
+        } = self;
+
+        match destination {
+            Destination::Pushover(user) => pushover
+                .notify(user, messages)
+                .await
+                .map_err(SinkError::new),
+            Destination::Telegram(chat_id) => match telegram {
+                Some(telegram) => telegram
+                    .notify(*chat_id, messages)
+                    .await
+                    .map_err(SinkError::new),
+                None => Err(not_configured("Telegram")),
+            },
+            Destination::Webhook(url) => match webhook {
+                Some(webhook) => webhook
+                    .notify(url, account_id, messages)
+                    .await
+                    .map_err(SinkError::new),
+                None => Err(not_configured("webhook")),
+            },
+        }
+    }
+
+    fn sink_for(&self, destination: &Destination) -> Result<&dyn NotificationSink, SinkError> {
+        let Self {
+            pushover,
+            telegram,
+            webhook,
+        } = self;
+
+        match destination {
+            Destination::Pushover(_) => Ok(pushover),
+            Destination::Telegram(_) => match telegram {
+                Some(telegram) => Ok(telegram),
+                None => Err(not_configured("Telegram")),
+            },
+            Destination::Webhook(_) => match webhook {
+                Some(webhook) => Ok(webhook),
+                None => Err(not_configured("webhook")),
+            },
+        }
+    }
+}
+
+impl NotificationSink for Backends {
+    fn deliver(
+        &self,
+        notifications: Vec<OutgoingNotification>,
+    ) -> BoxFuture<'_, Result<(), SinkError>> {
+        async move {
+            let mut by_destination = HashMap::<_, Vec<_>>::new();
+            for n in notifications {
+                by_destination
+                    .entry(n.destination.clone())
+                    .or_default()
+                    .push(n);
+            }
+
+            for (destination, notifications) in by_destination {
+                self.sink_for(&destination)?.deliver(notifications).await?;
+            }
+
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(display("The {} backend has not been configured", name))]
+struct BackendNotConfigured {
+    name: &'static str,
+}
+
+fn not_configured(name: &'static str) -> SinkError {
+    SinkError::new(BackendNotConfigured { name })
+}
+
+impl IsTransient for BackendNotConfigured {
+    fn is_transient(&self) -> bool {
+        false
+    }
+}
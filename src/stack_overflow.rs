@@ -1,13 +1,34 @@
 use crate::error::IsTransient;
 use futures::{future::BoxFuture, FutureExt};
-use serde::{Deserialize, Serialize};
-use snafu::{OptionExt, ResultExt, Snafu};
-use std::env;
-use tracing::{trace, trace_span, Instrument};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::{
+    convert::TryFrom,
+    env,
+    sync::{
+        atomic::{AtomicI32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration as StdDuration,
+};
+use time::{OffsetDateTime, UtcOffset};
+use tracing::{trace, trace_span, warn, Instrument};
 use url::Url;
 
-const OAUTH_ENTRY_URI: &str = "https://stackoverflow.com/oauth";
-const OAUTH_ACCESS_TOKEN_URI: &str = "https://stackoverflow.com/oauth/access_token/json";
+#[cfg(test)]
+mod test;
+
+const DEFAULT_API_BASE: &str = "https://api.stackexchange.com/";
+const DEFAULT_OAUTH_BASE: &str = "https://stackoverflow.com/";
+
+// Keys look like base64, but with `*`, `(`, and `)` as punctuation
+const CLIENT_KEY_LENGTH: usize = 24;
+
+// A burst of signups can trip the token endpoint's rate limit. The
+// user is waiting on the response, so don't retry for long.
+const ACCESS_TOKEN_ATTEMPTS: u32 = 3;
+const ACCESS_TOKEN_RETRY_DELAY: StdDuration = StdDuration::from_secs(1);
+const ACCESS_TOKEN_MAX_RETRY_AFTER: StdDuration = StdDuration::from_secs(10);
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct AccessToken(pub String);
@@ -18,12 +39,42 @@ pub struct AccountId(pub i32);
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize)]
 pub struct UserId(pub i32);
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize)]
+// Seconds since the Unix epoch
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct Date(pub i64);
 
+impl Date {
+    // `None` when the date is too far from the epoch to represent
+    pub fn to_datetime(self) -> Option<OffsetDateTime> {
+        OffsetDateTime::from_unix_timestamp(self.0).ok()
+    }
+
+    // Nothing on Stack Overflow predates its launch or is from the
+    // future, so those dates fall back to `now`
+    pub fn to_datetime_or(self, now: OffsetDateTime) -> OffsetDateTime {
+        const LAUNCH: i64 = 1_217_548_800; // 2008-08-01
+
+        match self.to_datetime() {
+            Some(d) if self.0 >= LAUNCH && d <= now => d,
+            _ => {
+                trace!(date = self.0, "Date is implausible, using now instead");
+                now
+            }
+        }
+    }
+}
+
+// Seconds
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize)]
 pub struct Duration(pub i64);
 
+impl Duration {
+    // `None` when the duration is negative
+    pub fn to_std(self) -> Option<StdDuration> {
+        u64::try_from(self.0).ok().map(StdDuration::from_secs)
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize)]
 pub struct PostId(pub i64);
 
@@ -59,6 +110,24 @@ impl<T> ApiSuccess<T> {
     }
 }
 
+// Everything from the pages that were fetched
+#[derive(Debug)]
+pub struct Pages<T> {
+    pub items: Vec<T>,
+    // False when pagination stopped while more items remained
+    pub complete: bool,
+}
+
+// Nothing was asked for, so nothing was missed
+impl<T> Default for Pages<T> {
+    fn default() -> Self {
+        Self {
+            items: vec![],
+            complete: true,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Quota {
     #[serde(rename = "quota_max")]
@@ -91,6 +160,14 @@ impl ApiError {
     const INTERNAL_ERROR: i32 = 500;
     const THROTTLE_VIOLATION: i32 = 502;
     const TEMPORARILY_UNAVAILABLE: i32 = 503;
+
+    // The user has to authorize us again before any request can succeed
+    pub fn is_auth_failure(&self) -> bool {
+        matches!(
+            self.id,
+            Self::INVALID_ACCESS_TOKEN | Self::ACCESS_DENIED | Self::ACCESS_TOKEN_COMPROMISED
+        )
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -102,7 +179,7 @@ pub struct Notification {
     pub post_id: Option<PostId>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum NotificationType {
     Generic,
@@ -120,6 +197,7 @@ pub enum NotificationType {
     EditSuggested,
     SubstantiveEdit,
     BountyGracePeriodStarted,
+    #[serde(untagged)]
     Other(String),
 }
 
@@ -129,10 +207,11 @@ pub struct Inbox {
     pub creation_date: Date,
     pub is_unread: bool,
     pub item_type: InboxType,
+    pub link: Option<String>,
     pub post_id: Option<PostId>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum InboxType {
     Comment,
@@ -147,6 +226,7 @@ pub enum InboxType {
     FollowedPostActivity,
     SubcommunityEndorsement,
     SubcommunityLeaderboard,
+    #[serde(untagged)]
     Other(String),
 }
 
@@ -163,9 +243,17 @@ pub struct Config {
     client_id: String,
     client_secret: String,
     client_key: String,
+    oauth_entry: Url,
+    oauth_access_token: Url,
     unread_notifications: Url,
     unread_inbox: Url,
     current_user: Url,
+    info: Url,
+    site: String,
+    current_user_filter: String,
+    unread_notifications_filter: String,
+    unread_inbox_filter: String,
+    quota_threshold: i32,
 }
 
 impl Config {
@@ -175,7 +263,36 @@ impl Config {
             env::var("STACK_OVERFLOW_CLIENT_SECRET").context(UnknownClientSecretSnafu)?;
         let client_key = env::var("STACK_OVERFLOW_CLIENT_KEY").context(UnknownClientKeySnafu)?;
 
-        Self::new(client_id, client_secret, client_key)
+        // Overridden to point at a mock server
+        let api_base =
+            env::var("STACK_EXCHANGE_API_BASE").unwrap_or_else(|_| DEFAULT_API_BASE.into());
+        let api_base = Url::parse(&api_base).context(InvalidApiBaseSnafu { api_base })?;
+        let oauth_base =
+            env::var("STACK_OVERFLOW_OAUTH_BASE").unwrap_or_else(|_| DEFAULT_OAUTH_BASE.into());
+        let oauth_base = Url::parse(&oauth_base).context(InvalidOauthBaseSnafu { oauth_base })?;
+
+        let mut config =
+            Self::with_bases(client_id, client_secret, client_key, &api_base, &oauth_base)?;
+
+        if let Some(filter) = setting_from_environment("STACK_OVERFLOW_CURRENT_USER_FILTER")? {
+            config.current_user_filter = filter;
+        }
+        if let Some(filter) = setting_from_environment("STACK_OVERFLOW_NOTIFICATIONS_FILTER")? {
+            config.unread_notifications_filter = filter;
+        }
+        if let Some(filter) = setting_from_environment("STACK_OVERFLOW_INBOX_FILTER")? {
+            config.unread_inbox_filter = filter;
+        }
+        if let Some(site) = setting_from_environment("STACK_EXCHANGE_SITE")? {
+            config.site = site;
+        }
+        if let Some(threshold) = setting_from_environment("STACK_EXCHANGE_QUOTA_THRESHOLD")? {
+            config.quota_threshold = threshold
+                .parse()
+                .context(InvalidQuotaThresholdSnafu { threshold })?;
+        }
+
+        Ok(config)
     }
 
     fn new(
@@ -183,31 +300,87 @@ impl Config {
         client_secret: impl Into<String>,
         client_key: impl Into<String>,
     ) -> Result<Self> {
-        let client_id = client_id.into();
-        let client_secret = client_secret.into();
-        let client_key = client_key.into();
-        let unread_notifications =
-            Url::parse("https://api.stackexchange.com/2.2/me/notifications/unread")
-                .context(UnableToConfigureUnreadNotificationsUrlSnafu)?;
-        let unread_inbox = Url::parse("https://api.stackexchange.com/2.3/me/inbox/unread")
+        let api_base = Url::parse(DEFAULT_API_BASE).context(InvalidApiBaseSnafu {
+            api_base: DEFAULT_API_BASE,
+        })?;
+        let oauth_base = Url::parse(DEFAULT_OAUTH_BASE).context(InvalidOauthBaseSnafu {
+            oauth_base: DEFAULT_OAUTH_BASE,
+        })?;
+
+        Self::with_bases(client_id, client_secret, client_key, &api_base, &oauth_base)
+    }
+
+    fn with_bases(
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+        client_key: impl Into<String>,
+        api_base: &Url,
+        oauth_base: &Url,
+    ) -> Result<Self> {
+        let client_id = credential("STACK_OVERFLOW_CLIENT_ID", client_id.into())?;
+        let client_secret = credential("STACK_OVERFLOW_CLIENT_SECRET", client_secret.into())?;
+        let client_key = credential("STACK_OVERFLOW_CLIENT_KEY", client_key.into())?;
+
+        ensure!(
+            client_id.chars().all(|c| c.is_ascii_digit()),
+            InvalidClientIdSnafu
+        );
+
+        let length = client_key.chars().count();
+        ensure!(
+            length == CLIENT_KEY_LENGTH,
+            InvalidClientKeyLengthSnafu { length }
+        );
+        ensure!(
+            client_key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '*' | '(' | ')')),
+            InvalidClientKeyCharactersSnafu
+        );
+
+        let oauth_entry = oauth_base
+            .join("oauth")
+            .context(UnableToConfigureOauthEntryUrlSnafu)?;
+        let oauth_access_token = oauth_base
+            .join("oauth/access_token/json")
+            .context(UnableToConfigureAccessTokenUrlSnafu)?;
+
+        let unread_notifications = api_base
+            .join("2.2/me/notifications/unread")
+            .context(UnableToConfigureUnreadNotificationsUrlSnafu)?;
+        let unread_inbox = api_base
+            .join("2.3/me/inbox/unread")
             .context(UnableToConfigureUnreadInboxUrlSnafu)?;
 
-        let current_user = Url::parse("https://api.stackexchange.com/2.2/me")
+        let current_user = api_base
+            .join("2.2/me")
             .context(UnableToConfigureCurrentUserUrlSnafu)?;
 
+        let info = api_base
+            .join("2.3/info")
+            .context(UnableToConfigureInfoUrlSnafu)?;
+
         Ok(Config {
             client_id,
             client_secret,
             client_key,
+            oauth_entry,
+            oauth_access_token,
             unread_notifications,
             unread_inbox,
             current_user,
+            info,
+            site: SITE_STACKOVERFLOW.into(),
+            current_user_filter: FILTER_DEFAULT.into(),
+            unread_notifications_filter: FILTER_DEFAULT.into(),
+            unread_inbox_filter: FILTER_WITH_BODY.into(),
+            quota_threshold: DEFAULT_QUOTA_THRESHOLD,
         })
     }
 
     pub fn oauth_entry_url(&self, redirect_uri: &str, state: &str) -> Result<Url> {
         Url::parse_with_params(
-            OAUTH_ENTRY_URI,
+            self.oauth_entry.as_str(),
             &[
                 ("client_id", &*self.client_id),
                 ("scope", "read_inbox,no_expiry"),
@@ -218,6 +391,10 @@ impl Config {
         .context(UnableToBuildOauthEntryUrlSnafu)
     }
 
+    pub fn site(&self) -> &str {
+        &self.site
+    }
+
     pub fn into_unauth_client(self) -> UnauthClient {
         UnauthClient {
             client: super::reqwest_client(),
@@ -226,10 +403,53 @@ impl Config {
     }
 }
 
+fn setting_from_environment(name: &'static str) -> Result<Option<String>> {
+    match env::var(name) {
+        Ok(value) => {
+            let value = value.trim();
+            ensure!(!value.is_empty(), EmptySettingSnafu { name });
+            Ok(Some(value.into()))
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+// Catch blank values here instead of as a confusing OAuth failure
+fn credential(name: &'static str, value: String) -> Result<String> {
+    let value = value.trim();
+    ensure!(!value.is_empty(), EmptyCredentialSnafu { name });
+    Ok(value.into())
+}
+
 const SITE_STACKOVERFLOW: &str = "stackoverflow";
+
+// The API names a site by its short name or by its domain. Most short
+// names live under stackexchange.com, but the oldest sites have their
+// own domains, which their meta and localized sites build on.
+fn site_host(site: &str) -> String {
+    match site {
+        "stackoverflow" | "serverfault" | "superuser" | "askubuntu" | "stackapps" => {
+            format!("{}.com", site)
+        }
+        "mathoverflow" => "mathoverflow.net".into(),
+        _ if site.ends_with(".com") || site.ends_with(".net") => site.into(),
+        _ => match site.split_once('.') {
+            Some((prefix, parent)) => format!("{}.{}", prefix, site_host(parent)),
+            None => format!("{}.stackexchange.com", site),
+        },
+    }
+}
 const FILTER_DEFAULT: &str = "default";
 const FILTER_WITH_BODY: &str = "withbody";
 
+// Leaves room for the OAuth flow after the pollers stop
+const DEFAULT_QUOTA_THRESHOLD: i32 = 100;
+
+// https://api.stackexchange.com/docs/paging
+const PAGE_SIZE: u32 = 100;
+// Guards against an upstream that always claims to have more
+pub(crate) const MAX_PAGES: u32 = 10;
+
 pub struct UnauthClient {
     client: reqwest::Client,
     config: Config,
@@ -238,13 +458,7 @@ pub struct UnauthClient {
 impl UnauthClient {
     pub fn into_auth_client(self, access_token: AccessToken) -> AuthClient {
         let Self { client, config } = self;
-        AuthClient {
-            client,
-            auth_config: AuthConfig {
-                access_token,
-                config,
-            },
-        }
+        AuthClient::with_client(client, config, access_token)
     }
 
     pub async fn get_access_token(
@@ -274,21 +488,96 @@ impl UnauthClient {
             redirect_uri,
         };
 
-        let res = client
-            .post(OAUTH_ACCESS_TOKEN_URI)
-            .form(&params)
-            .send()
-            .await
-            .context(UnableToExecuteAccessTokenRequestSnafu)?
-            .ensure_success()
-            .await
-            .context(AccessTokenRequestRejectedSnafu)?
+        let mut attempt = 1;
+        let res = loop {
+            let res = client
+                .post(config.oauth_access_token.clone())
+                .form(&params)
+                .send()
+                .await;
+
+            // A dropped connection says nothing about the code itself
+            let res = match res {
+                Ok(res) => res,
+                Err(e) if e.is_transient() && attempt < ACCESS_TOKEN_ATTEMPTS => {
+                    let delay = jittered_delay(attempt);
+                    warn!(
+                        attempt,
+                        ?delay,
+                        "Access token request failed; retrying: {}",
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                    continue;
+                }
+                Err(e) => return Err(e).context(UnableToExecuteAccessTokenRequestSnafu),
+            };
+
+            let retry_after = retry_after(&res);
+
+            let e = match res.ensure_success().await {
+                Ok(res) => break res,
+                Err(e) => e,
+            };
+
+            // Anything else means our credentials or the code are
+            // wrong, which retrying won't fix
+            let delay = retry_after.unwrap_or_else(|| jittered_delay(attempt));
+            let retry = (e.is_rate_limited() || e.status.is_server_error())
+                && attempt < ACCESS_TOKEN_ATTEMPTS
+                && delay <= ACCESS_TOKEN_MAX_RETRY_AFTER;
+            if !retry {
+                return Err(e).context(AccessTokenRequestRejectedSnafu);
+            }
+
+            warn!(
+                attempt,
+                ?delay,
+                status = %e.status,
+                "Access token request was rejected; retrying"
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        };
+
+        let res = res
             .json::<AccessTokenResponse>()
             .await
             .context(UnableToDeserializeAccessTokenRequestSnafu)?;
 
         Ok(res.access_token)
     }
+
+    // The cheapest request that still rejects an invalid key
+    pub async fn check_key(&self) -> Result<()> {
+        let Self { client, config } = self;
+
+        #[derive(Debug, Serialize)]
+        struct InfoParams<'a> {
+            key: &'a str,
+            site: &'a str,
+        }
+
+        let params = InfoParams {
+            key: &config.client_key,
+            site: &config.site,
+        };
+
+        client
+            .get(config.info.clone())
+            .query(&params)
+            .send()
+            .await
+            .context(UnableToExecuteKeyCheckRequestSnafu)?
+            .json::<Wrapper<serde::de::IgnoredAny>>()
+            .await
+            .context(UnableToDeserializeKeyCheckRequestSnafu)?
+            .into_result()
+            .context(KeyCheckFailedSnafu)?;
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -304,6 +593,26 @@ struct AuthParams<'a, T> {
 pub struct AuthClient {
     client: reqwest::Client,
     auth_config: AuthConfig,
+    // The largest `backoff`, in seconds, seen since it was last taken
+    backoff: Arc<AtomicU64>,
+    // As reported by the most recent response
+    quota_remaining: Arc<AtomicI32>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum QuotaStatus {
+    Available,
+    Low { remaining: i32 },
+}
+
+// The daily quota resets at midnight UTC
+pub fn until_quota_reset(now: OffsetDateTime) -> StdDuration {
+    let now = now.to_offset(UtcOffset::UTC);
+    let reset = match now.date().next_day() {
+        Some(tomorrow) => tomorrow.midnight().assume_utc(),
+        None => return StdDuration::ZERO,
+    };
+    StdDuration::try_from(reset - now).unwrap_or(StdDuration::ZERO)
 }
 
 #[derive(Debug, Clone)]
@@ -323,7 +632,7 @@ impl AuthConfig {
         AuthParams {
             key: &config.client_key,
             access_token,
-            site: SITE_STACKOVERFLOW,
+            site: &config.site,
             request_params,
         }
     }
@@ -331,12 +640,18 @@ impl AuthConfig {
 
 impl AuthClient {
     pub fn new(config: Config, access_token: AccessToken) -> Self {
+        Self::with_client(super::reqwest_client(), config, access_token)
+    }
+
+    fn with_client(client: reqwest::Client, config: Config, access_token: AccessToken) -> Self {
         Self {
-            client: super::reqwest_client(),
+            client,
             auth_config: AuthConfig {
                 access_token,
                 config,
             },
+            backoff: Default::default(),
+            quota_remaining: Arc::new(AtomicI32::new(i32::MAX)),
         }
     }
 
@@ -344,6 +659,38 @@ impl AuthClient {
         &self.auth_config.access_token
     }
 
+    // Notifications only carry the post's ID, which is only
+    // meaningful on the site they came from
+    pub fn post_url(&self, post_id: PostId) -> String {
+        let host = site_host(&self.auth_config.config.site);
+        format!("https://{}/q/{}", host, post_id.0)
+    }
+
+    // The API rejects requests to the same method until the backoff
+    // has elapsed
+    pub fn take_backoff(&self) -> Option<StdDuration> {
+        match self.backoff.swap(0, Ordering::SeqCst) {
+            0 => None,
+            seconds => Some(StdDuration::from_secs(seconds)),
+        }
+    }
+
+    // Once the quota runs out, every request fails until it resets
+    pub fn quota_status(&self) -> QuotaStatus {
+        let Self {
+            auth_config,
+            quota_remaining,
+            ..
+        } = self;
+
+        let remaining = quota_remaining.load(Ordering::SeqCst);
+        if remaining < auth_config.config.quota_threshold {
+            QuotaStatus::Low { remaining }
+        } else {
+            QuotaStatus::Available
+        }
+    }
+
     pub async fn current_user(&self) -> Result<User, CurrentUserError> {
         let s = trace_span!("current_user");
 
@@ -351,6 +698,7 @@ impl AuthClient {
             let Self {
                 client,
                 auth_config,
+                ..
             } = self;
 
             #[derive(Debug, Serialize)]
@@ -359,7 +707,7 @@ impl AuthClient {
             }
 
             let params = auth_config.auth_params(CurrentUserParams {
-                filter: FILTER_DEFAULT,
+                filter: &auth_config.config.current_user_filter,
             });
 
             client
@@ -370,12 +718,12 @@ impl AuthClient {
                 .context(UnableToExecuteRequestSnafu)?
                 .ensure_success()
                 .await
-                .context(RequestRejectedSnafu)?
+                .map_err(CommonError::rejected)?
                 .json::<Wrapper<User>>()
                 .await
                 .context(UnableToDeserializeRequestSnafu)?
                 .into_result()
-                .context(RequestFailedSnafu)?
+                .map_err(CommonError::failed)?
                 .trace_quota()
                 .into_singleton()
                 .context(RequestDidNotHaveOneResultSnafu)
@@ -386,101 +734,228 @@ impl AuthClient {
 
     pub async fn unread_notifications(
         &self,
+        max_pages: u32,
     ) -> Result<Vec<Notification>, UnreadNotificationsError> {
-        let s = trace_span!("unread_notifications");
+        let pages = self.unread_notifications_since(max_pages, None).await?;
+        Ok(pages.items)
+    }
+
+    // Only items created at or after `since`, which saves quota when
+    // the older ones have already been relayed
+    pub async fn unread_notifications_since(
+        &self,
+        max_pages: u32,
+        since: Option<Date>,
+    ) -> Result<Pages<Notification>, UnreadNotificationsError> {
+        let s = trace_span!("unread_notifications", ?since);
 
         async {
-            let Self {
-                client,
-                auth_config,
-            } = self;
+            let Self { auth_config, .. } = self;
 
             #[derive(Debug, Serialize)]
             struct UnreadNotificationsParams<'a> {
                 filter: &'a str,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                since: Option<Date>,
             }
 
-            let params = auth_config.auth_params(UnreadNotificationsParams {
-                filter: FILTER_DEFAULT,
-            });
+            let params = UnreadNotificationsParams {
+                filter: &auth_config.config.unread_notifications_filter,
+                since,
+            };
 
-            let r = client
-                .get(auth_config.config.unread_notifications.clone())
-                .query(&params)
-                .send()
-                .await
-                .context(UnableToExecuteRequestSnafu)?
-                .ensure_success()
-                .await
-                .context(RequestRejectedSnafu)?
-                .json::<Wrapper<Notification>>()
-                .await
-                .context(UnableToDeserializeRequestSnafu)?
-                .into_result()
-                .context(RequestFailedSnafu)?
-                .trace_quota();
+            let pages = self
+                .all_pages(&auth_config.config.unread_notifications, params, max_pages)
+                .await?;
 
-            Ok(r.items)
+            Ok(pages)
         }
         .instrument(s)
         .await
     }
 
-    pub async fn unread_inbox(&self) -> Result<Vec<Inbox>, UnreadInboxError> {
-        let s = trace_span!("unread_inbox");
+    pub async fn unread_inbox(&self, max_pages: u32) -> Result<Vec<Inbox>, UnreadInboxError> {
+        let pages = self.unread_inbox_since(max_pages, None).await?;
+        Ok(pages.items)
+    }
+
+    pub async fn unread_inbox_since(
+        &self,
+        max_pages: u32,
+        since: Option<Date>,
+    ) -> Result<Pages<Inbox>, UnreadInboxError> {
+        let s = trace_span!("unread_inbox", ?since);
 
         async {
-            let Self {
-                client,
-                auth_config,
-            } = self;
+            let Self { auth_config, .. } = self;
 
             #[derive(Debug, Serialize)]
             struct UnreadInboxParams<'a> {
                 filter: &'a str,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                since: Option<Date>,
             }
 
-            let params = auth_config.auth_params(UnreadInboxParams {
-                filter: FILTER_WITH_BODY,
+            let params = UnreadInboxParams {
+                filter: &auth_config.config.unread_inbox_filter,
+                since,
+            };
+
+            let pages = self
+                .all_pages(&auth_config.config.unread_inbox, params, max_pages)
+                .await?;
+
+            Ok(pages)
+        }
+        .instrument(s)
+        .await
+    }
+
+    async fn all_pages<T, P>(
+        &self,
+        url: &Url,
+        request_params: P,
+        max_pages: u32,
+    ) -> Result<Pages<T>, CommonError>
+    where
+        T: DeserializeOwned,
+        P: Serialize,
+    {
+        let Self {
+            client,
+            auth_config,
+            backoff,
+            quota_remaining,
+        } = self;
+
+        #[derive(Debug, Serialize)]
+        struct PageParams<P> {
+            page: u32,
+            pagesize: u32,
+            #[serde(flatten)]
+            request_params: P,
+        }
+
+        let mut items = vec![];
+
+        let max_pages = max_pages.min(MAX_PAGES);
+
+        for page in 1..=max_pages {
+            let params = auth_config.auth_params(PageParams {
+                page,
+                pagesize: PAGE_SIZE,
+                request_params: &request_params,
             });
 
             let r = client
-                .get(auth_config.config.unread_inbox.clone())
+                .get(url.clone())
                 .query(&params)
                 .send()
                 .await
                 .context(UnableToExecuteRequestSnafu)?
                 .ensure_success()
                 .await
-                .context(RequestRejectedSnafu)?
-                .json::<Wrapper<Inbox>>()
+                .map_err(CommonError::rejected)?
+                .json::<Wrapper<T>>()
                 .await
                 .context(UnableToDeserializeRequestSnafu)?
                 .into_result()
-                .context(RequestFailedSnafu)?
+                .map_err(CommonError::failed)?
                 .trace_quota();
 
-            Ok(r.items)
+            if let Some(seconds) = r.backoff.and_then(|b| u64::try_from(b).ok()) {
+                backoff.fetch_max(seconds, Ordering::SeqCst);
+            }
+            quota_remaining.store(r.quota.remaining, Ordering::SeqCst);
+
+            let page_was_empty = r.items.is_empty();
+            items.extend(r.items);
+
+            if !r.has_more {
+                return Ok(Pages {
+                    items,
+                    complete: true,
+                });
+            }
+
+            if page_was_empty {
+                warn!(
+                    "Page {} had no items but claimed to have more; stopping pagination",
+                    page,
+                );
+                return Ok(Pages {
+                    items,
+                    complete: false,
+                });
+            }
         }
-        .instrument(s)
-        .await
+
+        warn!(
+            "Stopping pagination after {} pages even though there are more",
+            max_pages,
+        );
+        Ok(Pages {
+            items,
+            complete: false,
+        })
     }
 }
 
+// Enough to diagnose the problem without holding an arbitrarily large
+// error page in memory
+const ERROR_BODY_LIMIT: usize = 8 * 1024;
+
 #[derive(Debug, Snafu)]
 #[snafu(display(
-    "NotSuccess: {:?} {} {} {:?} {}",
+    "NotSuccess: {:?} {} {} {:?} {}{}",
     status,
     res,
     headers,
     body,
-    String::from_utf8_lossy(body)
+    String::from_utf8_lossy(body),
+    if *truncated { " (truncated)" } else { "" },
 ))]
 pub struct NotSuccess {
     status: reqwest::StatusCode,
     res: String,
     headers: String,
     body: Vec<u8>,
+    truncated: bool,
+}
+
+impl NotSuccess {
+    fn is_rate_limited(&self) -> bool {
+        // Stack Exchange reports throttling as a 400 with its own error
+        self.status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            || (self.status == reqwest::StatusCode::BAD_REQUEST
+                && String::from_utf8_lossy(&self.body).contains("throttle_violation"))
+    }
+
+    // Stack Exchange usually reports errors with a non-success status
+    // and the error wrapper as the body
+    fn api_error(&self) -> Option<ApiError> {
+        if self.truncated {
+            return None;
+        }
+        serde_json::from_slice(&self.body).ok()
+    }
+}
+
+// Only the delay-seconds form; HTTP dates are treated as absent
+fn retry_after(res: &reqwest::Response) -> Option<StdDuration> {
+    let value = res.headers().get(reqwest::header::RETRY_AFTER)?;
+    let seconds = value.to_str().ok()?.trim().parse().ok()?;
+    Some(StdDuration::from_secs(seconds))
+}
+
+// Exponential, with jitter so that concurrent signups don't retry in
+// lockstep
+fn jittered_delay(attempt: u32) -> StdDuration {
+    use rand::Rng;
+
+    let base = ACCESS_TOKEN_RETRY_DELAY * 2u32.pow(attempt.saturating_sub(1));
+    let jitter = rand::thread_rng().gen_range(0..=ACCESS_TOKEN_RETRY_DELAY.as_millis() as u64);
+    base + StdDuration::from_millis(jitter)
 }
 
 trait EnsureSuccess: Sized {
@@ -488,19 +963,34 @@ trait EnsureSuccess: Sized {
 }
 
 impl EnsureSuccess for reqwest::Response {
-    fn ensure_success(self) -> BoxFuture<'static, Result<Self, NotSuccess>> {
+    fn ensure_success(mut self) -> BoxFuture<'static, Result<Self, NotSuccess>> {
         async {
             let status = self.status();
             if !status.is_success() {
-                let res = format!("{self:?}");
+                // The query holds the access token and key
+                let mut url = self.url().clone();
+                url.set_query(None);
+                let res = url.to_string();
                 let headers = format!("{:?}", self.headers());
-                let body = self.bytes().await.unwrap_or_default();
+
+                let mut body = Vec::new();
+                let mut truncated = false;
+                while let Ok(Some(chunk)) = self.chunk().await {
+                    let remaining = ERROR_BODY_LIMIT - body.len();
+                    if chunk.len() > remaining {
+                        body.extend_from_slice(&chunk[..remaining]);
+                        truncated = true;
+                        break;
+                    }
+                    body.extend_from_slice(&chunk);
+                }
 
                 NotSuccessSnafu {
                     status,
                     res,
                     headers,
-                    body: &body[..],
+                    body,
+                    truncated,
                 }
                 .fail()
             } else {
@@ -539,6 +1029,57 @@ pub enum Error {
         source: env::VarError,
     },
 
+    #[snafu(display("{} must not be empty", name))]
+    EmptySetting {
+        name: &'static str,
+    },
+
+    #[snafu(display("STACK_EXCHANGE_QUOTA_THRESHOLD is invalid"))]
+    InvalidQuotaThreshold {
+        source: std::num::ParseIntError,
+        threshold: String,
+    },
+
+    #[snafu(display("{} must not be empty", name))]
+    EmptyCredential {
+        name: &'static str,
+    },
+
+    #[snafu(display("STACK_OVERFLOW_CLIENT_ID must be a number"))]
+    InvalidClientId,
+
+    #[snafu(display(
+        "STACK_OVERFLOW_CLIENT_KEY must be {} characters long, but was {}",
+        CLIENT_KEY_LENGTH,
+        length
+    ))]
+    InvalidClientKeyLength {
+        length: usize,
+    },
+
+    #[snafu(display("STACK_OVERFLOW_CLIENT_KEY contains characters that are not in an API key"))]
+    InvalidClientKeyCharacters,
+
+    #[snafu(display("STACK_EXCHANGE_API_BASE is invalid"))]
+    InvalidApiBase {
+        source: url::ParseError,
+        api_base: String,
+    },
+
+    #[snafu(display("STACK_OVERFLOW_OAUTH_BASE is invalid"))]
+    InvalidOauthBase {
+        source: url::ParseError,
+        oauth_base: String,
+    },
+
+    UnableToConfigureOauthEntryUrl {
+        source: url::ParseError,
+    },
+
+    UnableToConfigureAccessTokenUrl {
+        source: url::ParseError,
+    },
+
     UnableToConfigureUnreadNotificationsUrl {
         source: url::ParseError,
     },
@@ -551,11 +1092,16 @@ pub enum Error {
         source: url::ParseError,
     },
 
+    UnableToConfigureInfoUrl {
+        source: url::ParseError,
+    },
+
     UnableToBuildOauthEntryUrl {
         source: url::ParseError,
     },
 
     UnableToExecuteAccessTokenRequest {
+        #[snafu(source(from(reqwest::Error, reqwest::Error::without_url)))]
         source: reqwest::Error,
     },
 
@@ -564,8 +1110,24 @@ pub enum Error {
     },
 
     UnableToDeserializeAccessTokenRequest {
+        #[snafu(source(from(reqwest::Error, reqwest::Error::without_url)))]
         source: reqwest::Error,
     },
+
+    UnableToExecuteKeyCheckRequest {
+        #[snafu(source(from(reqwest::Error, reqwest::Error::without_url)))]
+        source: reqwest::Error,
+    },
+
+    UnableToDeserializeKeyCheckRequest {
+        #[snafu(source(from(reqwest::Error, reqwest::Error::without_url)))]
+        source: reqwest::Error,
+    },
+
+    #[snafu(display("The Stack Exchange key was rejected"))]
+    KeyCheckFailed {
+        source: ApiError,
+    },
 }
 
 type Result<T, E = Error> = std::result::Result<T, E>;
@@ -580,9 +1142,24 @@ pub enum CurrentUserError {
     RequestDidNotHaveOneResult,
 }
 
+impl CurrentUserError {
+    pub fn is_auth_failure(&self) -> bool {
+        match self {
+            Self::Common { source } => source.is_auth_failure(),
+            _ => false,
+        }
+    }
+}
+
 #[derive(Debug, Snafu)]
 pub struct UnreadNotificationsError(CommonError);
 
+impl UnreadNotificationsError {
+    pub fn is_auth_failure(&self) -> bool {
+        self.0.is_auth_failure()
+    }
+}
+
 impl IsTransient for UnreadNotificationsError {
     fn is_transient(&self) -> bool {
         self.0.is_transient()
@@ -592,6 +1169,12 @@ impl IsTransient for UnreadNotificationsError {
 #[derive(Debug, Snafu)]
 pub struct UnreadInboxError(CommonError);
 
+impl UnreadInboxError {
+    pub fn is_auth_failure(&self) -> bool {
+        self.0.is_auth_failure()
+    }
+}
+
 impl IsTransient for UnreadInboxError {
     fn is_transient(&self) -> bool {
         self.0.is_transient()
@@ -600,13 +1183,49 @@ impl IsTransient for UnreadInboxError {
 
 #[derive(Debug, Snafu)]
 pub enum CommonError {
-    UnableToExecuteRequest { source: reqwest::Error },
+    UnableToExecuteRequest {
+        #[snafu(source(from(reqwest::Error, reqwest::Error::without_url)))]
+        source: reqwest::Error,
+    },
 
-    RequestRejected { source: NotSuccess },
+    RequestRejected {
+        source: NotSuccess,
+    },
 
-    UnableToDeserializeRequest { source: reqwest::Error },
+    UnableToDeserializeRequest {
+        #[snafu(source(from(reqwest::Error, reqwest::Error::without_url)))]
+        source: reqwest::Error,
+    },
+
+    RequestFailed {
+        source: ApiError,
+    },
+
+    #[snafu(display("The access token is no longer accepted"))]
+    AccessTokenRejected {
+        source: ApiError,
+    },
+}
 
-    RequestFailed { source: ApiError },
+impl CommonError {
+    fn rejected(source: NotSuccess) -> Self {
+        match source.api_error() {
+            Some(e) if e.is_auth_failure() => Self::AccessTokenRejected { source: e },
+            _ => Self::RequestRejected { source },
+        }
+    }
+
+    fn failed(source: ApiError) -> Self {
+        if source.is_auth_failure() {
+            Self::AccessTokenRejected { source }
+        } else {
+            Self::RequestFailed { source }
+        }
+    }
+
+    pub fn is_auth_failure(&self) -> bool {
+        matches!(self, Self::AccessTokenRejected { .. })
+    }
 }
 
 impl IsTransient for CommonError {
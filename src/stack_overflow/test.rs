@@ -0,0 +1,597 @@
+use super::*;
+use std::{convert::TryFrom, net::SocketAddr};
+use warp::Filter;
+
+const UNREAD_NOTIFICATIONS: &str = include_str!("fixtures/unread_notifications.json");
+const UNREAD_INBOX: &str = include_str!("fixtures/unread_inbox.json");
+const CURRENT_USER: &str = include_str!("fixtures/current_user.json");
+const ERROR_INVALID_ACCESS_TOKEN: &str = include_str!("fixtures/error_invalid_access_token.json");
+const MALFORMED_EMPTY_PAGE: &str = include_str!("fixtures/malformed_empty_page.json");
+
+fn parse<T>(fixture: &str) -> Wrapper<T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    serde_json::from_str(fixture).expect("Fixture is not valid")
+}
+
+#[test]
+fn wrapper_discriminates_success() {
+    let w = parse::<Notification>(UNREAD_NOTIFICATIONS);
+    assert!(matches!(w, Wrapper::Success(_)), "{:?}", w);
+}
+
+#[test]
+fn site_host_follows_the_site() {
+    assert_eq!(site_host("stackoverflow"), "stackoverflow.com");
+    assert_eq!(site_host("mathoverflow"), "mathoverflow.net");
+    assert_eq!(site_host("math"), "math.stackexchange.com");
+    assert_eq!(site_host("meta"), "meta.stackexchange.com");
+    assert_eq!(site_host("meta.stackoverflow"), "meta.stackoverflow.com");
+    assert_eq!(site_host("meta.math"), "meta.math.stackexchange.com");
+    assert_eq!(site_host("es.stackoverflow"), "es.stackoverflow.com");
+    assert_eq!(
+        site_host("math.stackexchange.com"),
+        "math.stackexchange.com"
+    );
+}
+
+#[test]
+fn wrapper_discriminates_error() {
+    let w = parse::<Notification>(ERROR_INVALID_ACCESS_TOKEN);
+    let e = w.into_result().unwrap_err();
+
+    assert_eq!(e.id, ApiError::INVALID_ACCESS_TOKEN);
+    assert_eq!(e.name, "invalid_access_token");
+    assert_eq!(e.message, "Account/Application association is gone");
+}
+
+#[test]
+fn quota_and_paging_fields() {
+    let s = parse::<Notification>(UNREAD_NOTIFICATIONS)
+        .into_result()
+        .unwrap();
+    assert_eq!(s.quota.max, 10000);
+    assert_eq!(s.quota.remaining, 9876);
+    assert!(!s.has_more);
+    assert_eq!(s.backoff, None);
+
+    let s = parse::<Inbox>(UNREAD_INBOX).into_result().unwrap();
+    assert_eq!(s.quota.remaining, 9875);
+    assert!(s.has_more);
+    assert_eq!(s.backoff, Some(10));
+}
+
+#[test]
+fn notifications() {
+    let s = parse::<Notification>(UNREAD_NOTIFICATIONS)
+        .into_result()
+        .unwrap();
+    let [known, unknown] = <[_; 2]>::try_from(s.items).unwrap();
+
+    assert!(matches!(
+        known.notification_type,
+        NotificationType::BadgeEarned
+    ));
+    assert_eq!(known.creation_date, Date(1608842400));
+    assert_eq!(known.post_id, None);
+    assert!(known.is_unread);
+
+    assert!(
+        matches!(&unknown.notification_type, NotificationType::Other(t) if t == "a_brand_new_notification_type"),
+        "{:?}",
+        unknown.notification_type,
+    );
+    assert_eq!(unknown.post_id, Some(PostId(65412345)));
+}
+
+#[test]
+fn inbox() {
+    let s = parse::<Inbox>(UNREAD_INBOX).into_result().unwrap();
+    let [known, unknown] = <[_; 2]>::try_from(s.items).unwrap();
+
+    assert!(matches!(known.item_type, InboxType::Comment));
+    assert_eq!(known.body, "Have you tried turning it off and on again?");
+    assert_eq!(known.post_id, Some(PostId(65412345)));
+
+    assert!(
+        matches!(&unknown.item_type, InboxType::Other(t) if t == "a_brand_new_inbox_type"),
+        "{:?}",
+        unknown.item_type,
+    );
+    assert_eq!(unknown.post_id, None);
+}
+
+#[test]
+fn current_user() {
+    let user = parse::<User>(CURRENT_USER)
+        .into_result()
+        .unwrap()
+        .into_singleton()
+        .unwrap();
+
+    assert_eq!(user.account_id, AccountId(1234567));
+    assert_eq!(user.user_id, UserId(7654321));
+}
+
+// Serves the fixture as the response to every request
+fn mock_server(fixture: &'static str) -> Url {
+    let route = warp::any()
+        .map(move || warp::reply::with_header(fixture, "content-type", "application/json"));
+    let (address, server) =
+        warp::serve(route).bind_ephemeral(SocketAddr::from(([127, 0, 0, 1], 0)));
+    tokio::spawn(server);
+
+    Url::parse(&format!("http://{address}/")).expect("Mock server URL is invalid")
+}
+
+// Like `mock_server`, but the response is gzip-encoded
+fn mock_gzip_server(fixture: &'static str) -> Url {
+    let route = warp::any()
+        .map(move || warp::reply::with_header(fixture, "content-type", "application/json"))
+        .with(warp::compression::gzip());
+    let (address, server) =
+        warp::serve(route).bind_ephemeral(SocketAddr::from(([127, 0, 0, 1], 0)));
+    tokio::spawn(server);
+
+    Url::parse(&format!("http://{address}/")).expect("Mock server URL is invalid")
+}
+
+fn mock_client(fixture: &'static str) -> AuthClient {
+    client_for(mock_server(fixture))
+}
+
+const CLIENT_ID: &str = "12345";
+const CLIENT_KEY: &str = "abcdefghijkl*MNOPQRSTU((";
+
+fn client_for(url: Url) -> AuthClient {
+    let config = Config::with_bases(CLIENT_ID, "client-secret", CLIENT_KEY, &url, &url).unwrap();
+    AuthClient::new(config, AccessToken("access-token".into()))
+}
+
+#[test]
+fn endpoints_are_relative_to_the_bases() {
+    let api_base = Url::parse("http://127.0.0.1:1234/se/").unwrap();
+    let oauth_base = Url::parse("http://127.0.0.1:5678/").unwrap();
+    let config = Config::with_bases(
+        CLIENT_ID,
+        "client-secret",
+        CLIENT_KEY,
+        &api_base,
+        &oauth_base,
+    )
+    .unwrap();
+
+    assert_eq!(
+        config.current_user.as_str(),
+        "http://127.0.0.1:1234/se/2.2/me"
+    );
+    assert_eq!(
+        config.oauth_access_token.as_str(),
+        "http://127.0.0.1:5678/oauth/access_token/json"
+    );
+
+    let entry = config.oauth_entry_url("http://relay/", "state").unwrap();
+    assert_eq!(entry.path(), "/oauth");
+    assert_eq!(entry.port(), Some(5678));
+}
+
+#[tokio::test]
+async fn client_exchanges_the_oauth_code() {
+    let url = mock_server(r#"{"access_token":"from-the-mock"}"#);
+    let config = Config::with_bases(CLIENT_ID, "client-secret", CLIENT_KEY, &url, &url).unwrap();
+
+    let access_token = config
+        .into_unauth_client()
+        .get_access_token("code", "http://relay/")
+        .await
+        .unwrap();
+    assert_eq!(access_token, AccessToken("from-the-mock".into()));
+}
+
+#[tokio::test]
+async fn client_retries_the_oauth_code_exchange() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Fails with a server error, then a bad code, then succeeds
+    let requests = Arc::new(AtomicUsize::new(0));
+    let route = {
+        let requests = requests.clone();
+        warp::any().map(move || {
+            let (status, body) = match requests.fetch_add(1, Ordering::SeqCst) {
+                0 => (503, ""),
+                1 => (400, r#"{"error_id":400,"error_name":"bad_parameter"}"#),
+                _ => (200, r#"{"access_token":"from-the-mock"}"#),
+            };
+            let status = warp::http::StatusCode::from_u16(status).unwrap();
+            warp::reply::with_status(body, status)
+        })
+    };
+    let (address, server) =
+        warp::serve(route).bind_ephemeral(SocketAddr::from(([127, 0, 0, 1], 0)));
+    tokio::spawn(server);
+
+    let url = Url::parse(&format!("http://{address}/")).unwrap();
+    let config = Config::with_bases(CLIENT_ID, "client-secret", CLIENT_KEY, &url, &url).unwrap();
+    let client = config.into_unauth_client();
+
+    // The bad code isn't retried
+    let e = client.get_access_token("code", "http://relay/").await;
+    assert!(e.is_err());
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+    let access_token = client.get_access_token("code", "http://relay/").await;
+    assert_eq!(access_token.unwrap(), AccessToken("from-the-mock".into()));
+}
+
+#[tokio::test]
+async fn client_unread_notifications() {
+    let client = mock_client(UNREAD_NOTIFICATIONS);
+
+    let notifications = client.unread_notifications(MAX_PAGES).await.unwrap();
+    assert_eq!(notifications.len(), 2);
+}
+
+#[tokio::test]
+async fn client_unread_inbox() {
+    let client = mock_client(UNREAD_INBOX);
+
+    // The fixture always claims to have more
+    let inbox = client.unread_inbox(MAX_PAGES).await.unwrap();
+    assert_eq!(inbox.len(), 2 * MAX_PAGES as usize);
+}
+
+#[tokio::test]
+async fn client_errors_do_not_reveal_secrets() {
+    use crate::error::Chain;
+
+    fn assert_no_secrets(e: &UnreadNotificationsError) {
+        let e = Chain(e).to_string();
+        assert!(!e.contains("access-token"), "{}", e);
+        assert!(!e.contains("abcdefghijkl"), "{}", e);
+    }
+
+    // Nothing is listening
+    let client = client_for(Url::parse("http://127.0.0.1:1/").unwrap());
+    let e = client.unread_notifications(MAX_PAGES).await.unwrap_err();
+    assert_no_secrets(&e);
+
+    let route = warp::any()
+        .map(|| warp::reply::with_status("", warp::http::StatusCode::SERVICE_UNAVAILABLE));
+    let (address, server) =
+        warp::serve(route).bind_ephemeral(SocketAddr::from(([127, 0, 0, 1], 0)));
+    tokio::spawn(server);
+
+    let client = client_for(Url::parse(&format!("http://{address}/")).unwrap());
+    let e = client.unread_notifications(MAX_PAGES).await.unwrap_err();
+    assert_no_secrets(&e);
+}
+
+#[tokio::test]
+async fn client_unread_inbox_page_cap() {
+    let client = mock_client(UNREAD_INBOX);
+
+    let inbox = client.unread_inbox(3).await.unwrap();
+    assert_eq!(inbox.len(), 2 * 3);
+
+    // Callers can't raise the cap past the built-in safety limit
+    let inbox = client.unread_inbox(MAX_PAGES + 5).await.unwrap();
+    assert_eq!(inbox.len(), 2 * MAX_PAGES as usize);
+}
+
+#[tokio::test]
+async fn client_reports_when_pagination_is_cut_short() {
+    let client = mock_client(UNREAD_INBOX);
+    let inbox = client.unread_inbox_since(3, None).await.unwrap();
+    assert!(!inbox.complete);
+
+    let client = mock_client(UNREAD_NOTIFICATIONS);
+    let notifications = client.unread_notifications_since(3, None).await.unwrap();
+    assert!(notifications.complete);
+}
+
+#[tokio::test]
+async fn client_records_backoff() {
+    let client = mock_client(UNREAD_INBOX);
+    assert_eq!(client.take_backoff(), None);
+
+    client.unread_inbox(MAX_PAGES).await.unwrap();
+
+    let backoff = client.take_backoff();
+    assert_eq!(backoff, Some(StdDuration::from_secs(10)));
+    assert_eq!(client.take_backoff(), None);
+
+    let poll_delay = crate::poll_spawner::poll_delay;
+    let interval = StdDuration::from_secs(60);
+    assert_eq!(poll_delay(interval, backoff), interval);
+    let long = StdDuration::from_secs(120);
+    assert_eq!(poll_delay(interval, Some(long)), long);
+}
+
+#[tokio::test]
+async fn client_reports_low_quota() {
+    let client = mock_client(UNREAD_INBOX);
+    assert_eq!(client.quota_status(), QuotaStatus::Available);
+
+    client.unread_inbox(1).await.unwrap();
+    assert_eq!(client.quota_status(), QuotaStatus::Available);
+
+    let mut config = Config::new(CLIENT_ID, "client-secret", CLIENT_KEY).unwrap();
+    config.unread_inbox = mock_server(UNREAD_INBOX);
+    config.quota_threshold = 10000;
+    let client = AuthClient::new(config, AccessToken("access-token".into()));
+
+    client.unread_inbox(1).await.unwrap();
+    assert_eq!(client.quota_status(), QuotaStatus::Low { remaining: 9875 });
+}
+
+#[test]
+fn quota_resets_at_utc_midnight() {
+    use time::macros::datetime;
+
+    let now = datetime!(2020-01-01 23:30 UTC);
+    assert_eq!(until_quota_reset(now), StdDuration::from_secs(30 * 60));
+
+    let now = datetime!(2020-01-01 20:30 -3);
+    assert_eq!(until_quota_reset(now), StdDuration::from_secs(30 * 60));
+}
+
+#[tokio::test]
+async fn client_stops_on_empty_page_with_more() {
+    let client = mock_client(MALFORMED_EMPTY_PAGE);
+
+    let notifications = client.unread_notifications(MAX_PAGES).await.unwrap();
+    assert!(notifications.is_empty());
+}
+
+#[tokio::test]
+async fn client_decodes_gzip() {
+    let client = client_for(mock_gzip_server(UNREAD_NOTIFICATIONS));
+
+    let notifications = client.unread_notifications(MAX_PAGES).await.unwrap();
+    assert_eq!(notifications.len(), 2);
+}
+
+#[tokio::test]
+async fn client_current_user() {
+    let client = mock_client(CURRENT_USER);
+
+    let user = client.current_user().await.unwrap();
+    assert_eq!(user.account_id, AccountId(1234567));
+}
+
+#[tokio::test]
+async fn client_current_user_on_another_site() {
+    use std::collections::HashMap;
+
+    let route = warp::query::<HashMap<String, String>>().map(|q: HashMap<String, String>| {
+        let site = q.get("site").map(String::as_str);
+        let fixture = if site == Some("serverfault") {
+            CURRENT_USER
+        } else {
+            ERROR_INVALID_ACCESS_TOKEN
+        };
+        warp::reply::with_header(fixture, "content-type", "application/json")
+    });
+    let (address, server) =
+        warp::serve(route).bind_ephemeral(SocketAddr::from(([127, 0, 0, 1], 0)));
+    tokio::spawn(server);
+
+    let url = Url::parse(&format!("http://{address}/")).unwrap();
+    let mut config = Config::new(CLIENT_ID, "client-secret", CLIENT_KEY).unwrap();
+    config.current_user = url;
+    config.site = "serverfault".into();
+    let client = AuthClient::new(config, AccessToken("access-token".into()));
+
+    // Accounts span every site, so the same account is found
+    let user = client.current_user().await.unwrap();
+    assert_eq!(user.account_id, AccountId(1234567));
+}
+
+#[tokio::test]
+async fn client_unread_notifications_since() {
+    use std::collections::HashMap;
+
+    let route = warp::query::<HashMap<String, String>>().map(|q: HashMap<String, String>| {
+        let fixture = match q.get("since").map(String::as_str) {
+            Some("1608842400") => UNREAD_NOTIFICATIONS,
+            _ => ERROR_INVALID_ACCESS_TOKEN,
+        };
+        warp::reply::with_header(fixture, "content-type", "application/json")
+    });
+    let (address, server) =
+        warp::serve(route).bind_ephemeral(SocketAddr::from(([127, 0, 0, 1], 0)));
+    tokio::spawn(server);
+
+    let client = client_for(Url::parse(&format!("http://{address}/")).unwrap());
+
+    let since = Some(Date(1608842400));
+    let notifications = client
+        .unread_notifications_since(MAX_PAGES, since)
+        .await
+        .unwrap();
+    assert_eq!(notifications.items.len(), 2);
+
+    // Without a date, the parameter is left off entirely
+    assert!(client.unread_notifications(MAX_PAGES).await.is_err());
+}
+
+#[tokio::test]
+async fn client_api_error() {
+    let client = mock_client(ERROR_INVALID_ACCESS_TOKEN);
+
+    let e = client.unread_notifications(MAX_PAGES).await.unwrap_err();
+    assert!(
+        matches!(e.0, CommonError::AccessTokenRejected { ref source } if source.id == ApiError::INVALID_ACCESS_TOKEN),
+        "{:?}",
+        e,
+    );
+    assert!(!e.is_transient());
+    assert!(e.is_auth_failure());
+}
+
+fn not_success(status: reqwest::StatusCode, body: &str) -> NotSuccess {
+    NotSuccess {
+        status,
+        res: String::new(),
+        headers: String::new(),
+        body: body.into(),
+        truncated: false,
+    }
+}
+
+#[test]
+fn rate_limits_are_distinguished_from_credential_errors() {
+    use reqwest::StatusCode;
+
+    let throttled =
+        r#"{"error_id":502,"error_message":"too many requests","error_name":"throttle_violation"}"#;
+    let bad_code = r#"{"error":{"type":"invalid_request","message":"code is invalid"}}"#;
+
+    assert!(not_success(StatusCode::TOO_MANY_REQUESTS, "").is_rate_limited());
+    assert!(not_success(StatusCode::BAD_REQUEST, throttled).is_rate_limited());
+    assert!(!not_success(StatusCode::BAD_REQUEST, bad_code).is_rate_limited());
+    assert!(!not_success(StatusCode::UNAUTHORIZED, "").is_rate_limited());
+}
+
+#[test]
+fn rejected_access_tokens_are_auth_failures() {
+    use reqwest::StatusCode;
+
+    let revoked = include_str!("fixtures/error_invalid_access_token.json");
+    let throttled =
+        r#"{"error_id":502,"error_message":"too many requests","error_name":"throttle_violation"}"#;
+
+    let e = CommonError::rejected(not_success(StatusCode::BAD_REQUEST, revoked));
+    assert!(e.is_auth_failure(), "{:?}", e);
+
+    let e = CommonError::rejected(not_success(StatusCode::BAD_REQUEST, throttled));
+    assert!(!e.is_auth_failure(), "{:?}", e);
+
+    let e = CommonError::rejected(not_success(StatusCode::FORBIDDEN, "<html></html>"));
+    assert!(!e.is_auth_failure(), "{:?}", e);
+}
+
+#[test]
+fn retry_delay_grows_and_is_jittered() {
+    for attempt in 1..ACCESS_TOKEN_ATTEMPTS {
+        let base = ACCESS_TOKEN_RETRY_DELAY * 2u32.pow(attempt - 1);
+        let delay = jittered_delay(attempt);
+
+        assert!(delay >= base, "{:?} < {:?}", delay, base);
+        assert!(
+            delay <= base + ACCESS_TOKEN_RETRY_DELAY,
+            "{:?} is too long",
+            delay
+        );
+    }
+}
+
+#[test]
+fn credentials_are_trimmed() {
+    let config = Config::new(" 12345 ", "secret\n", format!("\t{CLIENT_KEY}")).unwrap();
+    assert_eq!(config.client_id, "12345");
+    assert_eq!(config.client_secret, "secret");
+    assert_eq!(config.client_key, CLIENT_KEY);
+}
+
+#[test]
+fn empty_credentials_are_rejected() {
+    let e = Config::new("", "secret", CLIENT_KEY).unwrap_err();
+    assert!(
+        matches!(
+            e,
+            Error::EmptyCredential {
+                name: "STACK_OVERFLOW_CLIENT_ID"
+            }
+        ),
+        "{:?}",
+        e
+    );
+
+    let e = Config::new(CLIENT_ID, "  \t", CLIENT_KEY).unwrap_err();
+    assert!(
+        matches!(
+            e,
+            Error::EmptyCredential {
+                name: "STACK_OVERFLOW_CLIENT_SECRET"
+            }
+        ),
+        "{:?}",
+        e
+    );
+
+    let e = Config::new(CLIENT_ID, "secret", "\n").unwrap_err();
+    assert!(
+        matches!(
+            e,
+            Error::EmptyCredential {
+                name: "STACK_OVERFLOW_CLIENT_KEY"
+            }
+        ),
+        "{:?}",
+        e
+    );
+}
+
+#[test]
+fn malformed_credentials_are_rejected() {
+    let e = Config::new("client-id", "secret", CLIENT_KEY).unwrap_err();
+    assert!(matches!(e, Error::InvalidClientId), "{:?}", e);
+
+    let e = Config::new(CLIENT_ID, "secret", "too-short").unwrap_err();
+    assert!(
+        matches!(e, Error::InvalidClientKeyLength { length: 9 }),
+        "{:?}",
+        e
+    );
+
+    let e = Config::new(CLIENT_ID, "secret", "abcdefghijkl-MNOPQRSTU((").unwrap_err();
+    assert!(matches!(e, Error::InvalidClientKeyCharacters), "{:?}", e);
+}
+
+#[test]
+fn date_conversion_at_the_edges() {
+    use time::macros::datetime;
+
+    assert_eq!(Date(0).to_datetime(), Some(datetime!(1970-01-01 0:00 UTC)));
+    assert_eq!(
+        Date(-1).to_datetime(),
+        Some(datetime!(1969-12-31 23:59:59 UTC))
+    );
+    assert_eq!(
+        Date(i64::from(i32::MAX)).to_datetime(),
+        Some(datetime!(2038-01-19 3:14:07 UTC))
+    );
+    assert_eq!(Date(i64::MAX).to_datetime(), None);
+    assert_eq!(Date(i64::MIN).to_datetime(), None);
+}
+
+#[test]
+fn implausible_dates_fall_back() {
+    use time::macros::datetime;
+
+    let now = datetime!(2024-06-01 12:00 UTC);
+
+    assert_eq!(
+        Date(1608842400).to_datetime_or(now),
+        datetime!(2020-12-24 20:40 UTC)
+    );
+    assert_eq!(Date(0).to_datetime_or(now), now);
+    assert_eq!(Date(-1).to_datetime_or(now), now);
+    assert_eq!(Date(i64::MAX).to_datetime_or(now), now);
+    assert_eq!(Date(now.unix_timestamp() + 60).to_datetime_or(now), now);
+}
+
+#[test]
+fn duration_conversion_at_the_edges() {
+    use std::time::Duration as StdDuration;
+
+    assert_eq!(Duration(0).to_std(), Some(StdDuration::ZERO));
+    assert_eq!(Duration(90).to_std(), Some(StdDuration::from_secs(90)));
+    assert_eq!(
+        Duration(i64::MAX).to_std(),
+        Some(StdDuration::from_secs(i64::MAX as u64))
+    );
+    assert_eq!(Duration(-1).to_std(), None);
+}
@@ -0,0 +1,315 @@
+use crate::{
+    domain::{Destination, Message, OutgoingNotification},
+    error::IsTransient,
+    sink::{NotificationSink, SinkError},
+};
+use futures::{future::BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::{collections::HashMap, env};
+use tracing::{trace, trace_span, warn, Instrument};
+use url::Url;
+
+const DEFAULT_API_BASE: &str = "https://api.telegram.org/";
+
+// https://core.telegram.org/bots/api#sendmessage
+const MESSAGE_LIMIT: usize = 4096;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ChatId(pub i64);
+
+impl ChatId {
+    pub fn parse(id: &str) -> Result<Self> {
+        // Group chats have negative identifiers
+        let id = id.trim();
+        let id = id.parse().context(InvalidChatIdSnafu { id })?;
+        Ok(Self(id))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    send_message_url: Url,
+    max_message_len: usize,
+}
+
+impl Config {
+    // Telegram is optional; without a bot token only Pushover is available
+    pub fn from_environment() -> Result<Option<Self>> {
+        let token = match env::var("TELEGRAM_BOT_TOKEN") {
+            Ok(token) => token,
+            Err(env::VarError::NotPresent) => return Ok(None),
+            Err(e) => return Err(e).context(InvalidBotTokenSnafu),
+        };
+        let api_base = env::var("TELEGRAM_API_BASE").unwrap_or_else(|_| DEFAULT_API_BASE.into());
+
+        let api_base = Url::parse(&api_base).context(InvalidApiBaseSnafu { api_base })?;
+
+        Self::new(&token, &api_base).map(Some)
+    }
+
+    fn new(token: &str, api_base: &Url) -> Result<Self> {
+        let send_message_url = api_base
+            .join(&format!("bot{token}/sendMessage"))
+            .context(UnableToConfigureSendMessageUrlSnafu)?;
+
+        Ok(Self {
+            send_message_url,
+            max_message_len: MESSAGE_LIMIT,
+        })
+    }
+
+    pub fn into_client(self) -> Client {
+        Client {
+            client: super::reqwest_client(),
+            config: self,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    client: reqwest::Client,
+    config: Config,
+}
+
+impl Client {
+    pub async fn notify(&self, chat_id: ChatId, messages: Vec<Message>) -> Result<()> {
+        let Self { client, config } = self;
+        let s = trace_span!("notify", count = messages.len());
+
+        #[derive(Debug, Serialize)]
+        struct SendMessageParams<'a> {
+            chat_id: ChatId,
+            text: &'a str,
+        }
+
+        async {
+            trace!("Performing notifications");
+
+            for message in &messages {
+                // Telegram has no separate link field
+                let text = match &message.url {
+                    Some(url) => format!("{}\n\n{}", message.text, url),
+                    None => message.text.clone(),
+                };
+                let text = truncate(&text, config.max_message_len);
+
+                let params = SendMessageParams {
+                    chat_id,
+                    text: &text,
+                };
+
+                let res = client
+                    .post(config.send_message_url.clone())
+                    .json(&params)
+                    .send()
+                    .await
+                    .context(UnableToSendNotificationSnafu)?;
+
+                ensure_delivered(res).await?;
+            }
+
+            Ok(())
+        }
+        .instrument(s)
+        .await
+    }
+}
+
+impl NotificationSink for Client {
+    fn deliver(
+        &self,
+        notifications: Vec<OutgoingNotification>,
+    ) -> BoxFuture<'_, Result<(), SinkError>> {
+        async move {
+            let mut by_chat = HashMap::<_, Vec<_>>::new();
+            for n in notifications {
+                match n.destination {
+                    Destination::Telegram(chat_id) => {
+                        by_chat.entry(chat_id).or_default().push(Message {
+                            text: n.text,
+                            url: n.url,
+                        })
+                    }
+                    destination => warn!("Telegram cannot deliver to {:?}", destination),
+                }
+            }
+
+            for (chat_id, messages) in by_chat {
+                self.notify(chat_id, messages)
+                    .await
+                    .map_err(SinkError::new)?;
+            }
+
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+// Telegram counts characters, so a multi-byte character is never cut in half
+fn truncate(message: &str, limit: usize) -> String {
+    if message.chars().count() <= limit {
+        return message.to_owned();
+    }
+
+    let mut message: String = message.chars().take(limit.saturating_sub(1)).collect();
+    message.push('…');
+    message
+}
+
+// https://core.telegram.org/bots/api#making-requests
+#[derive(Debug, Deserialize)]
+struct SendMessageResponse {
+    ok: bool,
+    #[serde(default)]
+    description: String,
+}
+
+async fn ensure_delivered(res: reqwest::Response) -> Result<()> {
+    let status = res.status();
+    let body = res.json::<SendMessageResponse>().await;
+
+    match body {
+        Ok(body) if body.ok => Ok(()),
+        Ok(body) => NotificationRejectedSnafu {
+            status,
+            description: body.description,
+        }
+        .fail(),
+        Err(_) if !status.is_success() => NotificationRejectedSnafu {
+            status,
+            description: String::new(),
+        }
+        .fail(),
+        Err(e) => Err(e).context(UnableToDeserializeResponseSnafu),
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("TELEGRAM_BOT_TOKEN is invalid"))]
+    InvalidBotToken {
+        source: env::VarError,
+    },
+
+    #[snafu(display("TELEGRAM_API_BASE is invalid"))]
+    InvalidApiBase {
+        source: url::ParseError,
+        api_base: String,
+    },
+
+    UnableToConfigureSendMessageUrl {
+        source: url::ParseError,
+    },
+
+    #[snafu(display("A Telegram chat ID must be a number, not {:?}", id))]
+    InvalidChatId {
+        source: std::num::ParseIntError,
+        id: String,
+    },
+
+    // The URL holds the bot token
+    UnableToSendNotification {
+        #[snafu(source(from(reqwest::Error, reqwest::Error::without_url)))]
+        source: reqwest::Error,
+    },
+
+    // The URL holds the bot token
+    UnableToDeserializeResponse {
+        #[snafu(source(from(reqwest::Error, reqwest::Error::without_url)))]
+        source: reqwest::Error,
+    },
+
+    #[snafu(display("Telegram rejected the notification ({}): {}", status, description))]
+    NotificationRejected {
+        status: reqwest::StatusCode,
+        description: String,
+    },
+}
+
+impl IsTransient for Error {
+    fn is_transient(&self) -> bool {
+        match self {
+            Self::UnableToSendNotification { source } => source.is_transient(),
+            Self::NotificationRejected { status, .. } => {
+                status.is_server_error() || *status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }
+            _ => false,
+        }
+    }
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parking_lot::Mutex;
+    use std::{net::SocketAddr, sync::Arc};
+    use warp::Filter;
+
+    type Requests = Arc<Mutex<Vec<serde_json::Value>>>;
+
+    // Records the JSON body of every request
+    fn mock_server() -> (Url, Requests) {
+        let requests = Requests::default();
+
+        let route = {
+            let requests = requests.clone();
+            warp::post()
+                .and(warp::path!("botbot-token" / "sendMessage"))
+                .and(warp::body::json())
+                .map(move |body: serde_json::Value| {
+                    requests.lock().push(body);
+                    r#"{"ok":true,"result":{}}"#
+                })
+        };
+        let (address, server) =
+            warp::serve(route).bind_ephemeral(SocketAddr::from(([127, 0, 0, 1], 0)));
+        tokio::spawn(server);
+
+        let url = Url::parse(&format!("http://{address}/")).expect("Mock server URL is invalid");
+        (url, requests)
+    }
+
+    #[test]
+    fn chat_ids_may_be_negative() {
+        assert_eq!(ChatId::parse(" -1001234 ").unwrap(), ChatId(-1001234));
+        assert!(ChatId::parse("@channel").is_err());
+    }
+
+    #[tokio::test]
+    async fn notify_sends_to_the_chat() {
+        let (api_base, requests) = mock_server();
+        let client = Config::new("bot-token", &api_base).unwrap().into_client();
+        let message = Message {
+            text: "one".into(),
+            url: Some("https://stackoverflow.com/q/42".into()),
+        };
+
+        client.notify(ChatId(42), vec![message]).await.unwrap();
+
+        let requests = requests.lock();
+        assert_eq!(requests[0]["chat_id"], 42);
+        assert_eq!(requests[0]["text"], "one\n\nhttps://stackoverflow.com/q/42");
+    }
+
+    #[tokio::test]
+    async fn notify_truncates_long_messages() {
+        let (api_base, requests) = mock_server();
+        let client = Config::new("bot-token", &api_base).unwrap().into_client();
+
+        let message: String = "é".repeat(5000);
+        client
+            .notify(ChatId(42), vec![message.into()])
+            .await
+            .unwrap();
+
+        let requests = requests.lock();
+        let sent = requests[0]["text"].as_str().unwrap();
+        assert_eq!(sent.chars().count(), MESSAGE_LIMIT);
+        assert!(sent.ends_with('…'));
+    }
+}
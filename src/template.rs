@@ -0,0 +1,65 @@
+use serde::Serialize;
+use snafu::{ResultExt, Snafu};
+use tinytemplate::TinyTemplate;
+
+pub const DEFAULT_TEMPLATE: &str = "{body}";
+
+const NAME: &str = "notification";
+
+#[derive(Debug, Serialize)]
+struct Context<'a, T> {
+    #[serde(rename = "type")]
+    kind: &'a T,
+    body: &'a str,
+    url: &'a str,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Template {
+    source: &'static str,
+}
+
+impl Template {
+    pub fn new(source: &'static str) -> Result<Self> {
+        let template = Self { source };
+
+        // Unknown fields are only detected when rendering
+        template.render(
+            &"comment",
+            "A sample body",
+            Some("https://stackoverflow.com/"),
+        )?;
+
+        Ok(template)
+    }
+
+    pub fn render<T>(&self, kind: &T, body: &str, url: Option<&str>) -> Result<String>
+    where
+        T: Serialize,
+    {
+        let Self { source } = *self;
+
+        let mut tt = TinyTemplate::new();
+        tt.set_default_formatter(&tinytemplate::format_unescaped);
+        tt.add_template(NAME, source).context(InvalidSnafu)?;
+
+        let context = Context {
+            kind,
+            body,
+            url: url.unwrap_or_default(),
+        };
+
+        tt.render(NAME, &context).context(UnableToRenderSnafu)
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("The notification template is invalid"))]
+    Invalid { source: tinytemplate::error::Error },
+
+    #[snafu(display("Unable to render the notification template"))]
+    UnableToRender { source: tinytemplate::error::Error },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
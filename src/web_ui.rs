@@ -1,16 +1,13 @@
 use crate::{
-    domain::{AccountId, UserKey},
-    GlobalConfig, GlobalStackOverflowConfig,
+    database::DbHandle,
+    domain::{AccountId, ChatId, Destination, DestinationId, OauthState, SessionData, SessionId},
+    webhook, GlobalConfig, GlobalStackOverflowConfig,
 };
-use once_cell::sync::Lazy;
-use parking_lot::Mutex;
 use serde::Deserialize;
-use snafu::{OptionExt, ResultExt, Snafu};
-use std::{
-    collections::BTreeMap,
-    convert::{Infallible, TryInto},
-};
-use tracing::{error, info};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::{convert::Infallible, future::Future, net::SocketAddr, time::Duration};
+use time::OffsetDateTime;
+use tracing::{error, info, warn};
 use warp::{
     body,
     filters::cookie,
@@ -18,151 +15,600 @@ use warp::{
     path, reply, Filter, Rejection, Reply,
 };
 
-#[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq)]
-struct SessionId([u8; 32]);
-
-impl rand::distributions::Distribution<SessionId> for rand::distributions::Standard {
-    fn sample<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> SessionId {
-        SessionId(self.sample(rng))
-    }
-}
-
-impl SessionId {
-    fn from_cookie(s: &str) -> Option<Self> {
-        let bytes = hex::decode(s).ok()?;
-        let bytes = bytes.try_into().ok()?;
-        Some(Self(bytes))
-    }
-
-    fn to_cookie(&self) -> String {
-        hex::encode(self.0)
-    }
-}
-
-#[derive(Debug, Clone, Default)]
-struct SessionData {
-    oauth_state: Option<String>,
-    account_id: Option<AccountId>,
-}
-
 #[derive(Debug, Clone)]
 struct Session(SessionId, SessionData);
 
 impl Session {
-    fn set_oauth_state(&mut self, oauth_state: impl Into<String>) {
-        self.1.oauth_state = Some(oauth_state.into())
+    fn set_oauth_state(&mut self, state: impl Into<String>, now: OffsetDateTime) {
+        self.1.oauth_state = Some(OauthState {
+            state: state.into(),
+            issued_at: now,
+        })
     }
 
-    fn take_oauth_state(&mut self) -> Option<String> {
+    // Taking the state means it can only ever be used once
+    fn take_oauth_state(&mut self) -> Option<OauthState> {
         self.1.oauth_state.take()
     }
 
     fn set_account_id(&mut self, account_id: AccountId) {
         self.1.account_id = Some(account_id);
     }
+
+    fn rotate_csrf_token(&mut self) {
+        self.1.csrf_token = Some(random_token());
+    }
+
+    fn csrf_token(&self) -> Option<&str> {
+        self.1.csrf_token.as_deref()
+    }
+
+    fn check_csrf_token(&self, token: &str) -> Result<()> {
+        let expected = self.csrf_token().context(CsrfMismatchSnafu)?;
+        ensure!(constant_time_eq(token, expected), CsrfMismatchSnafu);
+        Ok(())
+    }
 }
 
-#[derive(Debug, Default)]
-struct Sessions(BTreeMap<SessionId, SessionData>);
+fn check_oauth_state(expected: Option<OauthState>, state: &str, now: OffsetDateTime) -> Result<()> {
+    let expected = expected.context(StateParameterMismatchSnafu)?;
+    ensure!(expected.state == state, StateParameterMismatchSnafu);
+    ensure!(!expected.is_expired(now), StateParameterExpiredSnafu);
+    Ok(())
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+// Unguessable and safe to put in a URL or a form
+fn random_token() -> String {
+    use rand::{distributions::Alphanumeric, Rng, SeedableRng};
+
+    rand::rngs::StdRng::from_entropy()
+        .sample_iter(&Alphanumeric)
+        .take(64)
+        .map(char::from)
+        .collect()
+}
+
+// Saving the session on every request would be a write per page view
+const SESSION_TOUCH_INTERVAL: Duration = Duration::from_secs(60);
+
+const SESSION_SWEEP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+// Stored in the database so that restarts don't log everyone out.
+// Sessions that go unused for the TTL expire.
+#[derive(Debug, Clone)]
+struct Sessions(DbHandle, Duration);
 
 impl Sessions {
-    fn create(&mut self) -> SessionId {
+    async fn create(&mut self) -> Result<SessionId> {
         use rand::{Rng, SeedableRng};
 
-        let mut rng = rand::rngs::StdRng::from_entropy();
-        let mut id;
-        loop {
-            id = rng.gen();
-            if !self.0.contains_key(&id) {
-                break;
-            }
+        // 256 random bits won't collide with an existing session
+        let id: SessionId = rand::rngs::StdRng::from_entropy().gen();
+        let data = SessionData::new(OffsetDateTime::now_utc());
+        self.save(Session(id.clone(), data)).await?;
+
+        Ok(id)
+    }
+
+    async fn for_id(&mut self, id: &str) -> Result<Option<Session>> {
+        let id = match SessionId::from_cookie(id) {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+
+        let data = self
+            .0
+            .load_session(id.clone())
+            .await
+            .context(UnableToLoadSessionSnafu)?;
+        let mut data = match data {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+
+        let now = OffsetDateTime::now_utc();
+        if data.is_expired(self.1, now) {
+            self.remove(id).await?;
+            return Ok(None);
+        }
+
+        if data.last_seen_at + SESSION_TOUCH_INTERVAL < now {
+            data.last_seen_at = now;
+            self.save(Session(id.clone(), data.clone())).await?;
         }
 
-        let session = SessionData::default();
-        self.0.insert(id.clone(), session);
+        Ok(Some(Session(id, data)))
+    }
 
-        id
+    async fn save(&mut self, session: Session) -> Result<()> {
+        let Session(id, data) = session;
+        self.0
+            .save_session(id, data)
+            .await
+            .context(UnableToSaveSessionSnafu)
     }
 
-    fn for_id(&self, id: &str) -> Option<Session> {
-        let id = SessionId::from_cookie(id)?;
-        let data = self.0.get(&id).cloned()?;
-        Some(Session(id, data))
+    async fn remove(&mut self, id: SessionId) -> Result<()> {
+        self.0
+            .remove_session(id)
+            .await
+            .context(UnableToSaveSessionSnafu)
     }
 
-    fn save(&mut self, session: Session) {
-        self.0.insert(session.0, session.1);
+    async fn remove_others(&mut self, session: &Session, account_id: AccountId) -> Result<()> {
+        self.0
+            .remove_other_sessions(session.0.clone(), account_id)
+            .await
+            .context(UnableToSaveSessionSnafu)
+    }
+
+    async fn remove_expired(&mut self) -> Result<usize> {
+        let Self(db, ttl) = self;
+
+        let now = OffsetDateTime::now_utc();
+        let cutoff = now - *ttl;
+        let login_cutoff = now - (*ttl).max(SessionData::LOGIN_GRACE);
+
+        db.remove_expired_sessions(cutoff, login_cutoff)
+            .await
+            .context(UnableToSaveSessionSnafu)
     }
 }
 
-static SESSIONS: Lazy<Mutex<Sessions>> = Lazy::new(Default::default);
+// Visitors that never log in, such as bots, still create sessions
+async fn sweep_sessions(mut sessions: Sessions) {
+    let mut interval = tokio::time::interval(SESSION_SWEEP_INTERVAL);
 
+    loop {
+        interval.tick().await;
+
+        match sessions.remove_expired().await {
+            Ok(0) => {}
+            Ok(count) => info!(count, "Removed expired sessions"),
+            Err(e) => warn!("Unable to remove expired sessions: {}", e),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn serve(
     config: GlobalConfig,
     so_config: GlobalStackOverflowConfig,
     register_flow: crate::flow::RegisterFlow,
     set_pushover_user_flow: crate::flow::SetPushoverUserFlow,
+    destinations_flow: crate::flow::DestinationsFlow,
+    deregister_flow: crate::flow::DeregisterFlow,
+    history_flow: crate::flow::HistoryFlow,
+    admin_flow: crate::flow::AdminFlow,
+    resend_flow: crate::flow::ResendFlow,
+    poll_spawner: crate::poll_spawner::PollSpawnerHandle,
+    db: DbHandle,
+    shutdown: impl Future<Output = ()> + Send + 'static,
 ) {
-    let oauth = oauth::routes(config, so_config, register_flow);
+    let sessions = Sessions(db.clone(), config.session_ttl);
+    tokio::spawn(sweep_sessions(sessions.clone()));
+
+    let oauth = oauth::routes(config, so_config, register_flow, sessions.clone());
+    let admin = admin::routes(config, admin_flow);
+
+    // Only expose the internal routes publicly when they don't have their own listener
+    let internal = warp::any()
+        .and_then(move || async move {
+            match config.internal_listen_address {
+                Some(_) => Err(warp::reject::not_found()),
+                None => Ok(()),
+            }
+        })
+        .untuple_one()
+        .and(internal::routes(poll_spawner));
 
     let ping = warp::path!("ping").map(|| "pong");
 
-    let auth_root = path::end()
-        .and(auth_session())
-        .map(|_session| warp::reply::html(html::auth_root().into_string()));
-    let unauth_root = path::end().map(|| {
-        let id = SESSIONS.lock().create();
-        let h = warp::reply::html(html::unauth_root().into_string());
-        reply::with_header(
-            h,
-            header::SET_COOKIE,
-            format!("id={}; Secure; HttpOnly;", id.to_cookie()),
-        ) // samesite?
+    let healthz = warp::path!("healthz").and(warp::get()).and_then(move || {
+        let db = db.clone();
+        async move { Ok::<_, Rejection>(health_check(db).await) }
     });
+
+    let auth_root = {
+        let destinations_flow = destinations_flow.clone();
+        let sessions = sessions.clone();
+        path::end().and(auth_session(sessions.clone())).and_then(
+            move |(account_id, mut session): (_, Session)| {
+                let mut destinations_flow = destinations_flow.clone();
+                let mut sessions = sessions.clone();
+                async move {
+                    // Sessions from before CSRF protection have no token
+                    if session.csrf_token().is_none() {
+                        session.rotate_csrf_token();
+                        sessions.save(session.clone()).await?;
+                    }
+                    let csrf_token = session.csrf_token().unwrap_or_default();
+
+                    let destinations = destinations_flow
+                        .list(account_id)
+                        .await
+                        .context(UnableToListDestinationsSnafu)?;
+                    let page =
+                        html::auth_root(&destinations, destinations_flow.backends(), csrf_token);
+                    Ok::<_, Rejection>(html_page(config, page))
+                }
+            },
+        )
+    };
+    let unauth_root = {
+        let sessions = sessions.clone();
+        path::end().and_then(move || {
+            let mut sessions = sessions.clone();
+            async move {
+                let id = sessions.create().await?;
+                let h = html_page(config, html::unauth_root());
+                Ok::<_, Rejection>(reply::with_header(
+                    h,
+                    header::SET_COOKIE,
+                    session_cookie(&id, config.secure_cookies),
+                ))
+            }
+        })
+    };
     let root = auth_root.or(unauth_root);
 
     #[derive(Deserialize)]
     struct PushoverConfiguration {
         key: String,
+        csrf_token: String,
+    }
+
+    let user_me_post = {
+        let destinations_flow = destinations_flow.clone();
+        warp::path!("user" / "me")
+            .and(auth_session(sessions.clone()))
+            .and(warp::post())
+            .and(warp::header::exact_ignore_case(
+                "content-type",
+                "application/x-www-form-urlencoded",
+            ))
+            .and(body::content_length_limit(1024))
+            .and(body::form())
+            .and_then(
+                move |(account_id, session): (_, Session), form: PushoverConfiguration| {
+                    let mut set_pushover_user_flow = set_pushover_user_flow.clone();
+                    let mut destinations_flow = destinations_flow.clone();
+                    async move {
+                        session.check_csrf_token(&form.csrf_token)?;
+
+                        let r = set_pushover_user_flow
+                            .set_pushover_user(account_id, &form.key)
+                            .await;
+                        let id = match r {
+                            Ok(id) => id,
+                            // Let the user correct the key without starting over
+                            Err(e @ crate::flow::Error::InvalidPushoverKey { .. }) => {
+                                let page = html::pushover_key_invalid(
+                                    &form.key,
+                                    &e.to_string(),
+                                    &form.csrf_token,
+                                );
+                                let page = html_page(config, page);
+                                let r = reply::with_status(page, StatusCode::BAD_REQUEST);
+                                return Ok(r.into_response());
+                            }
+                            Err(e) => Err(e).context(UnableToSetPushoverUserSnafu)?,
+                        };
+                        destinations_flow
+                            .verify(account_id, id)
+                            .await
+                            .context(UnableToVerifyDestinationSnafu)?;
+                        Ok::<_, Rejection>(redirect_to("/").into_response())
+                    }
+                },
+            )
+    };
+
+    #[derive(Deserialize)]
+    struct TelegramConfiguration {
+        chat_id: String,
+        csrf_token: String,
+    }
+
+    let telegram_post = {
+        let destinations_flow = destinations_flow.clone();
+        warp::path!("user" / "me" / "telegram")
+            .and(auth_session(sessions.clone()))
+            .and(warp::post())
+            .and(warp::header::exact_ignore_case(
+                "content-type",
+                "application/x-www-form-urlencoded",
+            ))
+            .and(body::content_length_limit(1024))
+            .and(body::form())
+            .and_then(
+                move |(account_id, session): (_, Session), config: TelegramConfiguration| {
+                    let mut destinations_flow = destinations_flow.clone();
+                    async move {
+                        session.check_csrf_token(&config.csrf_token)?;
+
+                        let chat_id =
+                            ChatId::parse(&config.chat_id).context(InvalidTelegramChatIdSnafu)?;
+                        let id = destinations_flow
+                            .add(account_id, Destination::Telegram(chat_id))
+                            .await
+                            .context(UnableToUpdateDestinationSnafu)?;
+                        destinations_flow
+                            .verify(account_id, id)
+                            .await
+                            .context(UnableToVerifyDestinationSnafu)?;
+                        Ok::<_, Rejection>(redirect_to("/"))
+                    }
+                },
+            )
+    };
+
+    #[derive(Deserialize)]
+    struct WebhookConfiguration {
+        url: String,
+        csrf_token: String,
+    }
+
+    let webhook_post = {
+        let destinations_flow = destinations_flow.clone();
+        warp::path!("user" / "me" / "webhook")
+            .and(auth_session(sessions.clone()))
+            .and(warp::post())
+            .and(warp::header::exact_ignore_case(
+                "content-type",
+                "application/x-www-form-urlencoded",
+            ))
+            .and(body::content_length_limit(4096))
+            .and(body::form())
+            .and_then(
+                move |(account_id, session): (_, Session), config: WebhookConfiguration| {
+                    let mut destinations_flow = destinations_flow.clone();
+                    async move {
+                        session.check_csrf_token(&config.csrf_token)?;
+
+                        let url =
+                            webhook::parse_url(&config.url).context(InvalidWebhookUrlSnafu)?;
+                        let id = destinations_flow
+                            .add(account_id, Destination::Webhook(url))
+                            .await
+                            .context(UnableToUpdateDestinationSnafu)?;
+                        destinations_flow
+                            .verify(account_id, id)
+                            .await
+                            .context(UnableToVerifyDestinationSnafu)?;
+                        Ok::<_, Rejection>(redirect_to("/"))
+                    }
+                },
+            )
+    };
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    enum DestinationAction {
+        Enable,
+        Disable,
+        Verify,
+        Remove,
     }
 
-    let user_me_post = warp::path!("user" / "me")
-        .and(auth_session())
+    #[derive(Deserialize)]
+    struct DestinationForm {
+        action: DestinationAction,
+        csrf_token: String,
+    }
+
+    let destination_post = warp::path!("user" / "me" / "destinations" / i32)
+        .and(auth_session(sessions.clone()))
         .and(warp::post())
+        .and(warp::header::exact_ignore_case(
+            "content-type",
+            "application/x-www-form-urlencoded",
+        ))
+        .and(body::content_length_limit(1024))
         .and(body::form())
+        .and_then(
+            move |id, (account_id, session): (_, Session), form: DestinationForm| {
+                let mut destinations_flow = destinations_flow.clone();
+                async move {
+                    session.check_csrf_token(&form.csrf_token)?;
+
+                    let id = DestinationId(id);
+                    let r = match form.action {
+                        DestinationAction::Enable => {
+                            destinations_flow.set_enabled(account_id, id, true).await
+                        }
+                        DestinationAction::Disable => {
+                            destinations_flow.set_enabled(account_id, id, false).await
+                        }
+                        DestinationAction::Verify => {
+                            let r = destinations_flow.verify(account_id, id).await;
+                            r.context(UnableToVerifyDestinationSnafu)?;
+                            Ok(())
+                        }
+                        DestinationAction::Remove => destinations_flow.remove(account_id, id).await,
+                    };
+                    r.context(UnableToUpdateDestinationSnafu)?;
+                    Ok::<_, Rejection>(redirect_to("/"))
+                }
+            },
+        );
+
+    // Forms that carry nothing but the CSRF token
+    #[derive(Deserialize)]
+    struct CsrfForm {
+        csrf_token: String,
+    }
+
+    let resend_post = warp::path!("user" / "me" / "resend")
+        .and(auth_session(sessions.clone()))
+        .and(warp::post())
+        .and(warp::header::exact_ignore_case(
+            "content-type",
+            "application/x-www-form-urlencoded",
+        ))
         .and(body::content_length_limit(1024))
-        .and_then(move |(account_id, _), config: PushoverConfiguration| {
-            let mut set_pushover_user_flow = set_pushover_user_flow.clone();
+        .and(body::form())
+        .and_then(move |(account_id, session): (_, Session), form: CsrfForm| {
+            let mut resend_flow = resend_flow.clone();
             async move {
-                set_pushover_user_flow
-                    .set_pushover_user(account_id, UserKey(config.key))
+                session.check_csrf_token(&form.csrf_token)?;
+
+                resend_flow
+                    .resend(account_id)
                     .await
-                    .context(UnableToSetPushoverUserSnafu)?;
+                    .context(UnableToResendSnafu)?;
                 Ok::<_, Rejection>(redirect_to("/"))
             }
         });
 
-    let routes = oauth.or(ping).or(root).or(user_me_post);
+    let history = warp::path!("user" / "me" / "history")
+        .and(auth_session(sessions.clone()))
+        .and(warp::get())
+        .and_then(move |(account_id, _)| {
+            let mut history_flow = history_flow.clone();
+            async move {
+                let notifications = history_flow
+                    .recent(account_id)
+                    .await
+                    .context(UnableToLoadHistorySnafu)?;
+                Ok::<_, Rejection>(html_page(config, html::history(&notifications)))
+            }
+        });
+
+    let deregister_post = warp::path!("user" / "me" / "delete")
+        .and(auth_session(sessions.clone()))
+        .and(warp::post())
+        .and(warp::header::exact_ignore_case(
+            "content-type",
+            "application/x-www-form-urlencoded",
+        ))
+        .and(body::content_length_limit(1024))
+        .and(body::form())
+        .and_then(move |(account_id, session): (_, Session), form: CsrfForm| {
+            let mut deregister_flow = deregister_flow.clone();
+            async move {
+                session.check_csrf_token(&form.csrf_token)?;
+
+                deregister_flow
+                    .deregister(account_id)
+                    .await
+                    .context(UnableToDeregisterSnafu)?;
+                Ok::<_, Rejection>(reply::with_header(
+                    redirect_to("/"),
+                    header::SET_COOKIE,
+                    expired_session_cookie(config.secure_cookies),
+                ))
+            }
+        });
+
+    // Logging out twice, or with an expired session, is harmless
+    let logout = {
+        let sessions = sessions.clone();
+        warp::path!("logout")
+            .and(warp::post())
+            .and(cookie::optional("id"))
+            .and_then(move |id: Option<String>| {
+                let mut sessions = sessions.clone();
+                async move {
+                    if let Some(id) = id.as_deref().and_then(SessionId::from_cookie) {
+                        sessions.remove(id).await?;
+                    }
+                    Ok::<_, Rejection>(reply::with_header(
+                        redirect_to("/"),
+                        header::SET_COOKIE,
+                        expired_session_cookie(config.secure_cookies),
+                    ))
+                }
+            })
+    };
+
+    let routes = oauth
+        .or(admin)
+        .or(internal)
+        .or(ping)
+        .or(healthz)
+        .or(root)
+        .or(user_me_post)
+        .or(telegram_post)
+        .or(webhook_post)
+        .or(destination_post)
+        .or(resend_post)
+        .or(history)
+        .or(deregister_post)
+        .or(logout);
     let routes = routes.recover(report_invalid);
 
     info!("Starting web server at {}", &config.listen_address);
-    warp::serve(routes).run(config.listen_address).await
+
+    // Stops accepting connections once `shutdown` resolves, but lets
+    // in-flight requests, such as OAuth callbacks, finish
+    let (_, server) =
+        warp::serve(routes).bind_with_graceful_shutdown(config.listen_address, shutdown);
+    server.await
+}
+
+pub(crate) async fn serve_internal(
+    address: SocketAddr,
+    poll_spawner: crate::poll_spawner::PollSpawnerHandle,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) {
+    let routes = internal::routes(poll_spawner);
+
+    info!("Starting internal web server at {}", address);
+    let (_, server) = warp::serve(routes).bind_with_graceful_shutdown(address, shutdown);
+    server.await
+}
+
+// `Lax` still sends the cookie when the browser follows a link to us,
+// such as the OAuth redirect back from Stack Overflow
+fn session_cookie(id: &SessionId, secure: bool) -> String {
+    format!(
+        "id={}; Path=/; SameSite=Lax; HttpOnly;{}",
+        id.to_cookie(),
+        secure_attribute(secure),
+    )
+}
+
+fn expired_session_cookie(secure: bool) -> String {
+    format!(
+        "id=; Max-Age=0; Path=/; SameSite=Lax; HttpOnly;{}",
+        secure_attribute(secure),
+    )
+}
+
+fn secure_attribute(secure: bool) -> &'static str {
+    if secure {
+        " Secure;"
+    } else {
+        ""
+    }
 }
 
-fn session() -> warp::filters::BoxedFilter<(Session,)> {
+fn session(sessions: Sessions) -> warp::filters::BoxedFilter<(Session,)> {
     cookie::cookie("id")
-        .and_then(|id: String| async move {
-            let sessions = SESSIONS.lock();
-            sessions
-                .for_id(&id)
-                .context(NotAuthenticatedSnafu)
-                .map_err(Rejection::from)
+        .and_then(move |id: String| {
+            let mut sessions = sessions.clone();
+            async move {
+                sessions
+                    .for_id(&id)
+                    .await?
+                    .context(NotAuthenticatedSnafu)
+                    .map_err(Rejection::from)
+            }
         })
         .boxed()
 }
 
-fn auth_session() -> warp::filters::BoxedFilter<((AccountId, Session),)> {
-    session()
+fn auth_session(sessions: Sessions) -> warp::filters::BoxedFilter<((AccountId, Session),)> {
+    session(sessions)
         .and_then(|session: Session| async move {
             let account_id = session.1.account_id.context(NotAuthenticatedSnafu)?;
             Ok::<_, Rejection>((account_id, session))
@@ -170,18 +616,44 @@ fn auth_session() -> warp::filters::BoxedFilter<((AccountId, Session),)> {
         .boxed()
 }
 
+// maud only produces UTF-8, so say so instead of letting clients guess
+fn html_page(config: GlobalConfig, page: maud::Markup) -> impl Reply {
+    let r = reply::with_header(
+        page.into_string(),
+        header::CONTENT_TYPE,
+        "text/html; charset=utf-8",
+    );
+    reply::with_header(r, header::CONTENT_LANGUAGE, &config.content_language)
+}
+
+// Unlike `/ping`, this notices when the database actor or Postgres is gone
+async fn health_check(mut db: DbHandle) -> reply::Response {
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    let problem = match tokio::time::timeout(TIMEOUT, db.try_health_check()).await {
+        Ok(Ok(Ok(()))) => return reply::with_status("ok", StatusCode::OK).into_response(),
+        Ok(Ok(Err(e))) => format!("The database query failed: {}", e),
+        Ok(Err(e)) => format!("The database actor is not running: {}", e),
+        Err(_) => format!("The database did not respond within {:?}", TIMEOUT),
+    };
+
+    warn!("Health check failed: {}", problem);
+    reply::with_status(problem, StatusCode::SERVICE_UNAVAILABLE).into_response()
+}
+
 fn redirect_to(location: impl AsRef<str>) -> impl Reply {
     let r = reply::reply();
     let r = reply::with_header(r, header::LOCATION, location.as_ref());
     reply::with_status(r, StatusCode::TEMPORARY_REDIRECT)
 }
 
-async fn report_invalid(r: Rejection) -> Result<impl Reply, Infallible> {
+async fn report_invalid(r: Rejection) -> Result<reply::Response, Infallible> {
     let internal = || {
         Ok(warp::reply::with_status(
             String::from("An internal error occurred"),
             StatusCode::INTERNAL_SERVER_ERROR,
-        ))
+        )
+        .into_response())
     };
 
     if let Some(e) = r.find::<Error>() {
@@ -190,15 +662,68 @@ async fn report_invalid(r: Rejection) -> Result<impl Reply, Infallible> {
             NotAuthenticated => Ok(warp::reply::with_status(
                 "Not authorized".to_string(),
                 StatusCode::UNAUTHORIZED,
-            )),
+            )
+            .into_response()),
+            AdminNotAuthenticated => {
+                let r = warp::reply::with_status(
+                    "Not authorized".to_string(),
+                    StatusCode::UNAUTHORIZED,
+                );
+                let r = reply::with_header(r, header::WWW_AUTHENTICATE, r#"Basic realm="admin""#);
+                Ok(r.into_response())
+            }
+            UnableToResend {
+                source: crate::flow::Error::ResendRateLimited,
+            } => Ok(warp::reply::with_status(
+                "Notifications were resent recently; please wait before trying again".to_string(),
+                StatusCode::TOO_MANY_REQUESTS,
+            )
+            .into_response()),
+            InvalidTelegramChatId { source } => Ok(warp::reply::with_status(
+                source.to_string(),
+                StatusCode::BAD_REQUEST,
+            )
+            .into_response()),
+            InvalidWebhookUrl { source } => Ok(warp::reply::with_status(
+                source.to_string(),
+                StatusCode::BAD_REQUEST,
+            )
+            .into_response()),
+            UnableToVerifyDestination {
+                source: source @ crate::flow::Error::UnableToVerifyDestination { .. },
+            } => Ok(warp::reply::with_status(
+                format!("The destination could not be verified: {}", source),
+                StatusCode::BAD_REQUEST,
+            )
+            .into_response()),
             StateParameterMismatch { .. } => Ok(warp::reply::with_status(
                 e.to_string(),
                 StatusCode::BAD_REQUEST,
-            )),
+            )
+            .into_response()),
+            StateParameterExpired => Ok(warp::reply::with_status(
+                e.to_string(),
+                StatusCode::BAD_REQUEST,
+            )
+            .into_response()),
+            CsrfMismatch => Ok(warp::reply::with_status(
+                "The form has expired; please reload the page and try again".to_string(),
+                StatusCode::FORBIDDEN,
+            )
+            .into_response()),
             UnableToGetOauthEntryUrl { .. }
             | UnableToCompleteRegistration { .. }
             | UnableToSetPushoverUser { .. }
-            | UnableToBuildRedirectUri { .. } => {
+            | UnableToListDestinations { .. }
+            | UnableToUpdateDestination { .. }
+            | UnableToVerifyDestination { .. }
+            | UnableToListUsers { .. }
+            | UnableToResend { .. }
+            | UnableToLoadHistory { .. }
+            | UnableToDeregister { .. }
+            | UnableToBuildRedirectUri { .. }
+            | UnableToLoadSession { .. }
+            | UnableToSaveSession { .. } => {
                 error!("Unhandled web UI error: {}", e);
                 internal()
             }
@@ -214,16 +739,59 @@ async fn report_invalid(r: Rejection) -> Result<impl Reply, Infallible> {
 enum Error {
     NotAuthenticated,
 
+    AdminNotAuthenticated,
+
     StateParameterMismatch,
 
+    #[snafu(display("The login attempt took too long; please try again"))]
+    StateParameterExpired,
+
+    CsrfMismatch,
+
     UnableToCompleteRegistration {
         source: crate::flow::Error,
     },
 
+    InvalidTelegramChatId {
+        source: crate::telegram::Error,
+    },
+
+    InvalidWebhookUrl {
+        source: crate::webhook::Error,
+    },
+
     UnableToSetPushoverUser {
         source: crate::flow::Error,
     },
 
+    UnableToListDestinations {
+        source: crate::flow::Error,
+    },
+
+    UnableToVerifyDestination {
+        source: crate::flow::Error,
+    },
+
+    UnableToUpdateDestination {
+        source: crate::flow::Error,
+    },
+
+    UnableToListUsers {
+        source: crate::flow::Error,
+    },
+
+    UnableToResend {
+        source: crate::flow::Error,
+    },
+
+    UnableToLoadHistory {
+        source: crate::flow::Error,
+    },
+
+    UnableToDeregister {
+        source: crate::flow::Error,
+    },
+
     UnableToGetOauthEntryUrl {
         source: crate::stack_overflow::Error,
     },
@@ -231,6 +799,14 @@ enum Error {
     UnableToBuildRedirectUri {
         source: url::ParseError,
     },
+
+    UnableToLoadSession {
+        source: crate::database::Error,
+    },
+
+    UnableToSaveSession {
+        source: crate::database::Error,
+    },
 }
 
 type Result<T, E = Error> = std::result::Result<T, E>;
@@ -239,14 +815,14 @@ impl warp::reject::Reject for Error {}
 
 mod oauth {
     use super::{
-        redirect_to, session, Result, Session, StateParameterMismatchSnafu,
+        check_oauth_state, redirect_to, session, Result, Session, Sessions,
         UnableToBuildRedirectUriSnafu, UnableToCompleteRegistrationSnafu,
-        UnableToGetOauthEntryUrlSnafu, SESSIONS,
+        UnableToGetOauthEntryUrlSnafu,
     };
     use crate::{GlobalConfig, GlobalStackOverflowConfig};
-    use rand::{distributions::Alphanumeric, Rng, SeedableRng};
     use serde::Deserialize;
-    use snafu::{ensure, ResultExt};
+    use snafu::ResultExt;
+    use time::OffsetDateTime;
     use url::Url;
     use warp::{
         filters::{query, BoxedFilter},
@@ -257,36 +833,40 @@ mod oauth {
         config: GlobalConfig,
         so_config: GlobalStackOverflowConfig,
         register_flow: crate::flow::RegisterFlow,
+        sessions: Sessions,
     ) -> BoxedFilter<(impl warp::Reply,)> {
         warp::path!("oauth" / "stackoverflow" / ..)
-            .and(begin(config, so_config).or(complete(config, register_flow)))
+            .and(begin(config, so_config, sessions.clone()).or(complete(
+                config,
+                register_flow,
+                sessions,
+            )))
             .boxed()
     }
 
     fn begin(
         config: GlobalConfig,
         so_config: GlobalStackOverflowConfig,
+        sessions: Sessions,
     ) -> BoxedFilter<(impl warp::Reply,)> {
         warp::path("begin")
-            .and(session())
-            .and_then(move |mut session: Session| async move {
-                let rng = rand::rngs::StdRng::from_entropy();
-                let state: String = rng
-                    .sample_iter(&Alphanumeric)
-                    .take(64)
-                    .map(char::from)
-                    .collect();
+            .and(session(sessions.clone()))
+            .and_then(move |mut session: Session| {
+                let mut sessions = sessions.clone();
+                async move {
+                    let state = super::random_token();
 
-                session.set_oauth_state(state.clone());
-                SESSIONS.lock().save(session);
+                    session.set_oauth_state(state.clone(), OffsetDateTime::now_utc());
+                    sessions.save(session).await?;
 
-                let redirect_uri = redirect_uri(config)?.to_string();
+                    let redirect_uri = redirect_uri(config)?.to_string();
 
-                let u = so_config
-                    .oauth_entry_url(&redirect_uri, &state)
-                    .context(UnableToGetOauthEntryUrlSnafu)?;
+                    let u = so_config
+                        .oauth_entry_url(&redirect_uri, &state)
+                        .context(UnableToGetOauthEntryUrlSnafu)?;
 
-                Ok::<_, Rejection>(redirect_to(u))
+                    Ok::<_, Rejection>(redirect_to(u))
+                }
             })
             .boxed()
     }
@@ -300,20 +880,19 @@ mod oauth {
     fn complete(
         config: GlobalConfig,
         flow: crate::flow::RegisterFlow,
+        sessions: Sessions,
     ) -> BoxedFilter<(impl warp::Reply,)> {
         warp::path("complete")
-            .and(session())
+            .and(session(sessions.clone()))
             .and(query::query())
             .and_then(move |mut session: Session, params: CompleteParams| {
                 let mut flow = flow.clone();
+                let mut sessions = sessions.clone();
                 async move {
                     let expected_state = session.take_oauth_state();
-                    SESSIONS.lock().save(session.clone());
+                    sessions.save(session.clone()).await?;
 
-                    ensure!(
-                        expected_state.map_or(false, |e| params.state == e),
-                        StateParameterMismatchSnafu
-                    );
+                    check_oauth_state(expected_state, &params.state, OffsetDateTime::now_utc())?;
 
                     let redirect_uri = redirect_uri(config)?.to_string();
 
@@ -323,7 +902,12 @@ mod oauth {
                         .context(UnableToCompleteRegistrationSnafu)?;
 
                     session.set_account_id(account_id);
-                    SESSIONS.lock().save(session);
+                    session.rotate_csrf_token();
+
+                    if config.single_session {
+                        sessions.remove_others(&session, account_id).await?;
+                    }
+                    sessions.save(session).await?;
 
                     Ok::<_, warp::Rejection>(redirect_to(config.public_uri.clone()))
                 }
@@ -339,7 +923,184 @@ mod oauth {
     }
 }
 
+mod internal {
+    use crate::poll_spawner::{PollSpawnerHandle, POLL_DURATION_BUCKETS};
+    use std::fmt::Write;
+    use warp::{filters::BoxedFilter, http::header, reply, Filter, Rejection};
+
+    pub(crate) fn routes(poll_spawner: PollSpawnerHandle) -> BoxedFilter<(impl warp::Reply,)> {
+        let health = warp::path!("health").map(|| "ok");
+
+        let version = warp::path!("version").map(|| env!("VERGEN_GIT_SHA"));
+
+        let metrics = warp::path!("metrics").and_then(move || {
+            let mut poll_spawner = poll_spawner.clone();
+            async move {
+                let polling = poll_spawner.list_polling().await.len();
+                let mut body = format!(
+                    "# HELP stack_overflow_relay_polling_accounts Accounts currently being polled\n\
+                     # TYPE stack_overflow_relay_polling_accounts gauge\n\
+                     stack_overflow_relay_polling_accounts {}\n",
+                    polling,
+                );
+
+                const DURATION: &str = "stack_overflow_relay_poll_duration_seconds";
+                let durations = poll_spawner.poll_durations().await;
+                // Writing to a `String` cannot fail
+                let _ = writeln!(body, "# HELP {DURATION} Time taken by each poll cycle");
+                let _ = writeln!(body, "# TYPE {DURATION} histogram");
+                for (bound, count) in POLL_DURATION_BUCKETS.iter().zip(&durations.buckets) {
+                    let _ = writeln!(body, "{DURATION}_bucket{{le=\"{bound}\"}} {count}");
+                }
+                let _ = writeln!(body, "{DURATION}_bucket{{le=\"+Inf\"}} {}", durations.count);
+                let _ = writeln!(body, "{DURATION}_sum {}", durations.sum.as_secs_f64());
+                let _ = writeln!(body, "{DURATION}_count {}", durations.count);
+
+                Ok::<_, Rejection>(reply::with_header(
+                    body,
+                    header::CONTENT_TYPE,
+                    "text/plain; version=0.0.4",
+                ))
+            }
+        });
+
+        warp::get().and(health.or(version).or(metrics)).boxed()
+    }
+}
+
+mod admin {
+    use super::{
+        constant_time_eq, html, html_page, redirect_to, AdminNotAuthenticatedSnafu,
+        UnableToListUsersSnafu,
+    };
+    use crate::{domain::AccountId, GlobalConfig};
+    use headers::{authorization::Basic, Authorization, HeaderMapExt};
+    use serde::Deserialize;
+    use snafu::{ensure, OptionExt, ResultExt};
+    use tracing::info;
+    use warp::{
+        filters::{query, BoxedFilter},
+        http::HeaderMap,
+        Filter, Rejection,
+    };
+
+    pub(crate) fn routes(
+        config: GlobalConfig,
+        flow: crate::flow::AdminFlow,
+    ) -> BoxedFilter<(impl warp::Reply,)> {
+        warp::path!("admin" / ..)
+            .and(authenticated(config))
+            .and(
+                users(config, flow.clone())
+                    .or(incomplete(config, flow.clone()))
+                    .or(stop_polling(flow)),
+            )
+            .boxed()
+    }
+
+    fn authenticated(config: GlobalConfig) -> BoxedFilter<()> {
+        warp::header::headers_cloned()
+            .and_then(move |headers: HeaderMap| async move {
+                let expected = config
+                    .admin_token
+                    .as_deref()
+                    .context(AdminNotAuthenticatedSnafu)?;
+                let provided = headers
+                    .typed_get::<Authorization<Basic>>()
+                    .context(AdminNotAuthenticatedSnafu)?;
+
+                ensure!(
+                    constant_time_eq(provided.password(), expected),
+                    AdminNotAuthenticatedSnafu
+                );
+
+                Ok::<_, Rejection>(())
+            })
+            .untuple_one()
+            .boxed()
+    }
+
+    fn stop_polling(flow: crate::flow::AdminFlow) -> BoxedFilter<(impl warp::Reply,)> {
+        warp::path!("users" / i32 / "stop")
+            .and(warp::post())
+            .and_then(move |account_id| {
+                let mut flow = flow.clone();
+                async move {
+                    let account_id = AccountId(account_id);
+
+                    if flow.stop_polling(account_id).await {
+                        info!("Stopped polling for {:?}", account_id);
+                    }
+
+                    Ok::<_, Rejection>(redirect_to("/admin/users"))
+                }
+            })
+            .boxed()
+    }
+
+    fn incomplete(
+        config: GlobalConfig,
+        flow: crate::flow::AdminFlow,
+    ) -> BoxedFilter<(impl warp::Reply,)> {
+        warp::path!("incomplete")
+            .and(warp::get())
+            .and_then(move || {
+                let mut flow = flow.clone();
+                async move {
+                    let account_ids = flow
+                        .incomplete_setups()
+                        .await
+                        .context(UnableToListUsersSnafu)?;
+
+                    let page = html::admin_incomplete(&account_ids);
+                    Ok::<_, Rejection>(html_page(config, page))
+                }
+            })
+            .boxed()
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct UsersParams {
+        account_id: Option<String>,
+        page: Option<u32>,
+    }
+
+    fn users(
+        config: GlobalConfig,
+        flow: crate::flow::AdminFlow,
+    ) -> BoxedFilter<(impl warp::Reply,)> {
+        warp::path!("users")
+            .and(warp::get())
+            .and(query::query())
+            .and_then(move |params: UsersParams| {
+                let mut flow = flow.clone();
+                async move {
+                    let search = params
+                        .account_id
+                        .as_deref()
+                        .and_then(|id| id.trim().parse().ok())
+                        .map(AccountId);
+                    let page = params.page.unwrap_or(0);
+
+                    let users = flow
+                        .users(search, page)
+                        .await
+                        .context(UnableToListUsersSnafu)?;
+
+                    let page = html::admin_users(search, page, &users);
+                    Ok::<_, Rejection>(html_page(config, page))
+                }
+            })
+            .boxed()
+    }
+}
+
 mod html {
+    use crate::{
+        domain::{AccountId, ConfiguredDestination, Destination, RecentNotification},
+        flow::UserPage,
+        sink::Backends,
+    };
     use maud::{html, Markup};
 
     pub fn unauth_root() -> Markup {
@@ -350,17 +1111,243 @@ mod html {
         })
     }
 
-    pub fn auth_root() -> Markup {
+    pub fn auth_root(
+        destinations: &[ConfiguredDestination],
+        backends: &Backends,
+        csrf_token: &str,
+    ) -> Markup {
+        let destination_configured = destinations.iter().any(|d| d.enabled && d.verified);
+
         page(|| {
             html! {
-                form action="/user/me" method="post" {
-                    input type="text" name="key" placeholder="pushover key";
-                    input type="submit";
+                ol {
+                    (setup_step("Stack Overflow account linked", true))
+                    (setup_step("Destination configured", destination_configured))
+                }
+
+                @if !destinations.is_empty() {
+                    ul {
+                        @for d in destinations {
+                            li {
+                                (destination(&d.destination))
+                                @if !d.verified {
+                                    " (unverified)"
+                                }
+                                @if !d.enabled {
+                                    " (disabled)"
+                                }
+                                form action={ "/user/me/destinations/" (d.id.0) } method="post" {
+                                    (csrf_field(csrf_token))
+                                    @if d.enabled {
+                                        button name="action" value="disable" { "Disable" }
+                                    } @else {
+                                        button name="action" value="enable" { "Enable" }
+                                    }
+                                    @if !d.verified {
+                                        button name="action" value="verify" { "Verify" }
+                                    }
+                                    button name="action" value="remove" { "Remove" }
+                                }
+                            }
+                        }
+                    }
+                }
+
+                (pushover_key_form(None, csrf_token))
+
+                @if backends.supports_telegram() {
+                    form action="/user/me/telegram" method="post" {
+                        (csrf_field(csrf_token))
+                        input type="text" name="chat_id" placeholder="telegram chat id";
+                        input type="submit";
+                    }
+                }
+
+                @if backends.supports_webhook() {
+                    form action="/user/me/webhook" method="post" {
+                        (csrf_field(csrf_token))
+                        input type="url" name="url" placeholder="webhook url";
+                        input type="submit";
+                    }
+                }
+
+                p { a href="/user/me/history" { "Recent notifications" } }
+
+                form action="/user/me/resend" method="post" {
+                    (csrf_field(csrf_token))
+                    button { "Resend recent notifications (test)" }
+                }
+
+                form action="/logout" method="post" {
+                    button { "Log out" }
+                }
+
+                form action="/user/me/delete" method="post" {
+                    (csrf_field(csrf_token))
+                    button { "Stop relaying and delete my account" }
+                }
+            }
+        })
+    }
+
+    fn setup_step(name: &str, complete: bool) -> Markup {
+        html! {
+            li {
+                (name) " "
+                @if complete { "✓" } @else { "✗" }
+            }
+        }
+    }
+
+    pub fn pushover_key_invalid(key: &str, message: &str, csrf_token: &str) -> Markup {
+        page(|| {
+            html! {
+                p { (message) }
+                (pushover_key_form(Some(key), csrf_token))
+                p { a href="/" { "Back" } }
+            }
+        })
+    }
+
+    // Every form that changes the account carries this, so another
+    // site can't submit it with the user's cookie
+    fn csrf_field(csrf_token: &str) -> Markup {
+        html! {
+            input type="hidden" name="csrf_token" value=(csrf_token);
+        }
+    }
+
+    fn pushover_key_form(key: Option<&str>, csrf_token: &str) -> Markup {
+        html! {
+            form action="/user/me" method="post" {
+                (csrf_field(csrf_token))
+                input type="text" name="key" placeholder="pushover key" value=[key];
+                input type="submit";
+            }
+        }
+    }
+
+    pub fn history(notifications: &[RecentNotification]) -> Markup {
+        page(|| {
+            html! {
+                p { a href="/" { "Back" } }
+
+                @if notifications.is_empty() {
+                    p { "No notifications have been relayed yet" }
+                } @else {
+                    table {
+                        thead {
+                            tr {
+                                th { "Created" }
+                                th { "Notification" }
+                                th { "Delivered" }
+                            }
+                        }
+                        tbody {
+                            @for n in notifications {
+                                tr {
+                                    td { (n.created_at) }
+                                    td {
+                                        @match &n.url {
+                                            Some(url) => { a href=(url) { (n.text) } }
+                                            None => { (n.text) }
+                                        }
+                                    }
+                                    td { @if n.delivered { "Yes" } @else { "No" } }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    pub fn admin_users(search: Option<AccountId>, page_number: u32, users: &UserPage) -> Markup {
+        let search_param = search
+            .map(|id| format!("account_id={}&", id.0))
+            .unwrap_or_default();
+
+        page(|| {
+            html! {
+                p { "Currently polling " (users.total_polling) " accounts" }
+                p { a href="/admin/incomplete" { "Accounts without a destination" } }
+
+                form action="/admin/users" method="get" {
+                    input type="text" name="account_id" placeholder="account id" value=[search.map(|id| id.0)];
+                    input type="submit" value="Search";
+                }
+
+                table {
+                    thead {
+                        tr {
+                            th { "Account ID" }
+                            th { "Destination configured" }
+                            th { "Last polled" }
+                            th { "Polling" }
+                        }
+                    }
+                    tbody {
+                        @for user in &users.users {
+                            @let registration = &user.registration;
+                            tr {
+                                td { (registration.account_id.0) }
+                                td { @if registration.has_destination { "Yes" } @else { "No" } }
+                                td {
+                                    @match registration.last_polled_at {
+                                        Some(t) => { (t) }
+                                        None => { "Never" }
+                                    }
+                                }
+                                td {
+                                    @if user.polling {
+                                        form action={ "/admin/users/" (registration.account_id.0) "/stop" } method="post" {
+                                            button { "Stop" }
+                                        }
+                                    } @else {
+                                        "No"
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+
+                @if page_number > 0 {
+                    a href={ "/admin/users?" (search_param) "page=" (page_number - 1) } { "Previous" }
+                    " "
+                }
+                @if users.has_more {
+                    a href={ "/admin/users?" (search_param) "page=" (page_number + 1) } { "Next" }
+                }
+            }
+        })
+    }
+
+    pub fn admin_incomplete(account_ids: &[AccountId]) -> Markup {
+        page(|| {
+            html! {
+                p { (account_ids.len()) " accounts have not configured a destination" }
+
+                ul {
+                    @for account_id in account_ids {
+                        li {
+                            a href={ "/admin/users?account_id=" (account_id.0) } { (account_id.0) }
+                        }
+                    }
                 }
             }
         })
     }
 
+    fn destination(destination: &Destination) -> Markup {
+        match destination {
+            Destination::Pushover(user) => html! { "Pushover: " (user.0) },
+            Destination::Telegram(chat_id) => html! { "Telegram: " (chat_id.0) },
+            Destination::Webhook(url) => html! { "Webhook: " (url) },
+        }
+    }
+
     fn page(body: impl FnOnce() -> Markup) -> Markup {
         html! {
             (maud::DOCTYPE)
@@ -375,3 +1362,79 @@ mod html {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn session_cookie_attributes() {
+        let id = SessionId([0xab; 32]);
+
+        let cookie = session_cookie(&id, true);
+        assert!(cookie.starts_with(&format!("id={};", id.to_cookie())));
+        assert!(cookie.contains(" Path=/;"));
+        assert!(cookie.contains(" SameSite=Lax;"));
+        assert!(cookie.contains(" HttpOnly;"));
+        assert!(cookie.contains(" Secure;"));
+
+        assert!(!session_cookie(&id, false).contains("Secure"));
+        assert!(expired_session_cookie(true).contains(" Max-Age=0;"));
+    }
+
+    #[test]
+    fn csrf_token_must_match() {
+        let data = SessionData::new(OffsetDateTime::now_utc());
+        let mut session = Session(SessionId([0; 32]), data);
+        assert!(session.check_csrf_token("").is_err());
+
+        session.rotate_csrf_token();
+        let token = session.csrf_token().unwrap().to_owned();
+        assert!(session.check_csrf_token(&token).is_ok());
+        assert!(session.check_csrf_token("a-guess").is_err());
+    }
+
+    #[test]
+    fn oauth_state_is_single_use_and_expires() {
+        let issued_at = OffsetDateTime::now_utc();
+        let data = SessionData::new(issued_at);
+        let mut session = Session(SessionId([0; 32]), data);
+
+        session.set_oauth_state("state", issued_at);
+        let expected = session.take_oauth_state();
+        assert!(check_oauth_state(expected.clone(), "state", issued_at).is_ok());
+        assert!(matches!(
+            check_oauth_state(expected.clone(), "guess", issued_at),
+            Err(Error::StateParameterMismatch)
+        ));
+
+        let later = issued_at + OauthState::TTL + Duration::from_secs(1);
+        assert!(matches!(
+            check_oauth_state(expected, "state", later),
+            Err(Error::StateParameterExpired)
+        ));
+
+        // A replayed callback finds nothing to compare against
+        let replayed = session.take_oauth_state();
+        assert!(matches!(
+            check_oauth_state(replayed, "state", issued_at),
+            Err(Error::StateParameterMismatch)
+        ));
+    }
+
+    #[test]
+    fn sessions_expire_unless_logging_in() {
+        let now = OffsetDateTime::now_utc();
+        let ttl = Duration::from_secs(60);
+
+        let mut data = SessionData::new(now - Duration::from_secs(120));
+        assert!(data.is_expired(ttl, now));
+
+        // Still waiting to hear back from Stack Overflow
+        data.oauth_state = Some(OauthState {
+            state: "state".into(),
+            issued_at: now,
+        });
+        assert!(!data.is_expired(ttl, now));
+    }
+}
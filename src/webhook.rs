@@ -0,0 +1,550 @@
+use crate::{
+    domain::{AccountId, Destination, Message, OutgoingNotification},
+    error::IsTransient,
+    sink::{NotificationSink, SinkError},
+};
+use futures::{future::BoxFuture, FutureExt};
+use hmac::{Hmac, Mac};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::Serialize;
+use sha2::Sha256;
+use snafu::{ensure, ResultExt, Snafu};
+use std::{
+    env,
+    error::Error as _,
+    net::{IpAddr, Ipv4Addr, ToSocketAddrs},
+    sync::Arc,
+    time::Duration,
+};
+use tracing::{trace, trace_span, warn, Instrument};
+use url::{Host, Url};
+
+const SIGNATURE_HEADER: &str = "X-Relay-Signature";
+
+// A slow receiver shouldn't hold up everyone else's deliveries
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn parse_url(url: &str) -> Result<Url> {
+    let url = url.trim();
+    let parsed = Url::parse(url).context(InvalidUrlSnafu { url })?;
+    ensure!(
+        matches!(parsed.scheme(), "http" | "https"),
+        InvalidUrlSchemeSnafu { url }
+    );
+    ensure_public_host(&parsed)?;
+    Ok(parsed)
+}
+
+// Hostnames are checked once resolved, as that's the address that is
+// actually connected to
+fn ensure_public_host(url: &Url) -> Result<()> {
+    let addr = match url.host() {
+        Some(Host::Ipv4(ip)) => IpAddr::V4(ip),
+        Some(Host::Ipv6(ip)) => IpAddr::V6(ip),
+        _ => return Ok(()),
+    };
+    ensure!(is_public(addr), NotPublicUrlSnafu { url: url.as_str() });
+    Ok(())
+}
+
+// Webhook URLs come from users, so they mustn't be able to reach the
+// relay's own machine or network
+fn is_public(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            let shared = a == 100 && b & 0xc0 == 64;
+            let protocol_assignments = a == 192 && b == 0 && c == 0;
+            let benchmarking = a == 198 && b & 0xfe == 18;
+            let reserved = a >= 240;
+
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || shared
+                || protocol_assignments
+                || benchmarking
+                || reserved)
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+
+            // These carry an IPv4 address, which is what gets reached
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            let compatible = segments[..6].iter().all(|&s| s == 0);
+            if compatible && !ip.is_loopback() && !ip.is_unspecified() {
+                return is_public(IpAddr::V4(Ipv4Addr::from(u128::from(ip) as u32)));
+            }
+            if segments[0] == 0x2002 {
+                let [a, b] = segments[1].to_be_bytes();
+                let [c, d] = segments[2].to_be_bytes();
+                return is_public(IpAddr::V4(Ipv4Addr::new(a, b, c, d)));
+            }
+
+            // The translator that would be reached is on our network
+            let nat64 =
+                segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] || segments[..3] == [0x64, 0xff9b, 1];
+            let unique_local = segments[0] & 0xfe00 == 0xfc00;
+            let link_local = segments[0] & 0xffc0 == 0xfe80;
+            let documentation = segments[..2] == [0x2001, 0xdb8];
+
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || unique_local
+                || link_local
+                || nat64
+                || documentation)
+        }
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(display("{} is not a public address", addr))]
+struct NotPublic {
+    addr: IpAddr,
+}
+
+// Rejects the whole name if any of its addresses isn't public, so a
+// connection can't fall back to one that isn't
+#[derive(Debug)]
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(resolve_public(name.as_str().to_owned()))
+    }
+}
+
+type ResolveError = Box<dyn std::error::Error + Send + Sync>;
+
+async fn resolve_public(name: String) -> Result<Addrs, ResolveError> {
+    let addrs = tokio::task::spawn_blocking(move || (&*name, 0).to_socket_addrs()).await??;
+    let addrs: Vec<_> = addrs.collect();
+
+    if let Some(addr) = addrs.iter().find(|a| !is_public(a.ip())) {
+        return Err(NotPublicSnafu { addr: addr.ip() }.build().into());
+    }
+
+    Ok(Box::new(addrs.into_iter()))
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    secret: String,
+}
+
+impl Config {
+    // Webhooks are optional; without a secret the backend is unavailable
+    pub fn from_environment() -> Result<Option<Self>> {
+        match env::var("WEBHOOK_SECRET") {
+            Ok(secret) if secret.is_empty() => EmptySecretSnafu.fail(),
+            Ok(secret) => Ok(Some(Self { secret })),
+            Err(env::VarError::NotPresent) => Ok(None),
+            Err(e) => Err(e).context(InvalidSecretSnafu),
+        }
+    }
+
+    pub fn into_client(self) -> Client {
+        Client::new(self, true)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    client: reqwest::Client,
+    config: Config,
+    public_only: bool,
+}
+
+impl Client {
+    fn new(config: Config, public_only: bool) -> Self {
+        let builder = reqwest::Client::builder()
+            .user_agent(super::USER_AGENT)
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(REQUEST_TIMEOUT)
+            // A redirect could point anywhere, skipping the checks
+            .redirect(reqwest::redirect::Policy::none());
+
+        let builder = if public_only {
+            builder.dns_resolver(Arc::new(PublicResolver))
+        } else {
+            builder
+        };
+
+        let client = builder
+            .build()
+            .expect("Unable to configure reqwest::Client");
+
+        Self {
+            client,
+            config,
+            public_only,
+        }
+    }
+
+    pub async fn notify(
+        &self,
+        url: &Url,
+        account_id: AccountId,
+        messages: Vec<Message>,
+    ) -> Result<()> {
+        let Self {
+            client,
+            config,
+            public_only,
+        } = self;
+        let s = trace_span!("notify", count = messages.len());
+
+        #[derive(Debug, Serialize)]
+        struct Payload<'a> {
+            user: i32,
+            text: &'a str,
+            url: Option<&'a str>,
+        }
+
+        async {
+            trace!("Performing notifications");
+
+            // Addresses in the URL itself are never resolved
+            if *public_only {
+                ensure_public_host(url)?;
+            }
+
+            for message in &messages {
+                let payload = Payload {
+                    user: account_id.0,
+                    text: &message.text,
+                    url: message.url.as_deref(),
+                };
+                let body = serde_json::to_vec(&payload).context(UnableToSerializePayloadSnafu)?;
+                let signature = sign(&config.secret, &body);
+
+                let res = client
+                    .post(url.clone())
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .header(SIGNATURE_HEADER, format!("sha256={}", signature))
+                    .body(body)
+                    .send()
+                    .await
+                    .context(UnableToSendNotificationSnafu)?;
+
+                let status = res.status();
+                ensure!(status.is_success(), NotificationRejectedSnafu { status });
+            }
+
+            Ok(())
+        }
+        .instrument(s)
+        .await
+    }
+}
+
+impl NotificationSink for Client {
+    fn deliver(
+        &self,
+        notifications: Vec<OutgoingNotification>,
+    ) -> BoxFuture<'_, Result<(), SinkError>> {
+        async move {
+            for n in notifications {
+                match &n.destination {
+                    Destination::Webhook(url) => {
+                        let message = Message {
+                            text: n.text,
+                            url: n.url,
+                        };
+                        self.notify(url, n.account_id, vec![message])
+                            .await
+                            .map_err(SinkError::new)?;
+                    }
+                    destination => warn!("A webhook cannot deliver to {:?}", destination),
+                }
+            }
+
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+// Lets the receiver check that the request came from this relay
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("WEBHOOK_SECRET is invalid"))]
+    InvalidSecret {
+        source: env::VarError,
+    },
+
+    #[snafu(display("WEBHOOK_SECRET must not be empty"))]
+    EmptySecret,
+
+    #[snafu(display("The webhook URL {:?} is invalid", url))]
+    InvalidUrl {
+        source: url::ParseError,
+        url: String,
+    },
+
+    #[snafu(display("The webhook URL {:?} must use http or https", url))]
+    InvalidUrlScheme {
+        url: String,
+    },
+
+    #[snafu(display("The webhook URL {:?} must be a public address", url))]
+    NotPublicUrl {
+        url: String,
+    },
+
+    UnableToSerializePayload {
+        source: serde_json::Error,
+    },
+
+    UnableToSendNotification {
+        source: reqwest::Error,
+    },
+
+    #[snafu(display("The webhook rejected the notification ({})", status))]
+    NotificationRejected {
+        status: reqwest::StatusCode,
+    },
+}
+
+impl IsTransient for Error {
+    fn is_transient(&self) -> bool {
+        match self {
+            Self::UnableToSendNotification { source } => {
+                source.is_transient() && !is_not_public(source)
+            }
+            Self::NotificationRejected { status } => {
+                status.is_server_error() || *status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }
+            _ => false,
+        }
+    }
+}
+
+// Resolving to a private address won't change on a retry
+fn is_not_public(e: &reqwest::Error) -> bool {
+    let mut e = e.source();
+    while let Some(source) = e {
+        if source.is::<NotPublic>() {
+            return true;
+        }
+        e = source.source();
+    }
+    false
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parking_lot::Mutex;
+    use std::{net::SocketAddr, sync::Arc};
+    use warp::Filter;
+
+    type Requests = Arc<Mutex<Vec<(String, warp::hyper::body::Bytes)>>>;
+
+    // Records the signature and body of every request
+    fn mock_server(status: u16) -> (Url, Requests) {
+        let requests = Requests::default();
+        let status = warp::http::StatusCode::from_u16(status).unwrap();
+
+        let route = {
+            let requests = requests.clone();
+            warp::post()
+                .and(warp::header::<String>(SIGNATURE_HEADER))
+                .and(warp::body::bytes())
+                .map(move |signature, body| {
+                    requests.lock().push((signature, body));
+                    warp::reply::with_status("", status)
+                })
+        };
+        let (address, server) =
+            warp::serve(route).bind_ephemeral(SocketAddr::from(([127, 0, 0, 1], 0)));
+        tokio::spawn(server);
+
+        let url =
+            Url::parse(&format!("http://{address}/hook")).expect("Mock server URL is invalid");
+        (url, requests)
+    }
+
+    fn config() -> Config {
+        Config {
+            secret: "shared-secret".into(),
+        }
+    }
+
+    // The mock servers are only reachable over loopback
+    fn client() -> Client {
+        Client::new(config(), false)
+    }
+
+    #[tokio::test]
+    async fn notify_signs_the_payload() {
+        let (url, requests) = mock_server(200);
+        let message = Message {
+            text: "one".into(),
+            url: Some("https://stackoverflow.com/q/42".into()),
+        };
+
+        client()
+            .notify(&url, AccountId(7), vec![message])
+            .await
+            .unwrap();
+
+        let requests = requests.lock();
+        let (signature, body) = &requests[0];
+        let payload: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(payload["user"], 7);
+        assert_eq!(payload["text"], "one");
+        assert_eq!(payload["url"], "https://stackoverflow.com/q/42");
+        assert_eq!(
+            signature,
+            &format!("sha256={}", sign("shared-secret", body))
+        );
+    }
+
+    #[tokio::test]
+    async fn notify_retries_server_errors() {
+        let (url, _) = mock_server(503);
+        let e = client()
+            .notify(&url, AccountId(7), vec!["one".into()])
+            .await
+            .unwrap_err();
+        assert!(e.is_transient(), "{:?}", e);
+
+        let (url, _) = mock_server(404);
+        let e = client()
+            .notify(&url, AccountId(7), vec!["one".into()])
+            .await
+            .unwrap_err();
+        assert!(!e.is_transient(), "{:?}", e);
+    }
+
+    #[tokio::test]
+    async fn redirects_are_not_followed() {
+        let (target, requests) = mock_server(200);
+
+        let route = warp::any().map(move || {
+            warp::http::Response::builder()
+                .status(307)
+                .header("location", target.as_str())
+                .body("")
+        });
+        let (address, server) =
+            warp::serve(route).bind_ephemeral(SocketAddr::from(([127, 0, 0, 1], 0)));
+        tokio::spawn(server);
+        let url = Url::parse(&format!("http://{address}/hook")).unwrap();
+
+        let e = client()
+            .notify(&url, AccountId(7), vec!["one".into()])
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(e, Error::NotificationRejected { status } if status.is_redirection()),
+            "{:?}",
+            e
+        );
+        assert!(requests.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn private_destinations_are_not_contacted() {
+        let client = config().into_client();
+        let (url, requests) = mock_server(200);
+
+        let e = client
+            .notify(&url, AccountId(7), vec!["one".into()])
+            .await
+            .unwrap_err();
+        assert!(matches!(e, Error::NotPublicUrl { .. }), "{:?}", e);
+
+        // Only known to be private once the name is resolved
+        let mut by_name = url.clone();
+        by_name.set_host(Some("localhost")).unwrap();
+        let e = client
+            .notify(&by_name, AccountId(7), vec!["one".into()])
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(e, Error::UnableToSendNotification { .. }),
+            "{:?}",
+            e
+        );
+        assert!(!e.is_transient(), "{:?}", e);
+
+        assert!(requests.lock().is_empty());
+    }
+
+    #[test]
+    fn only_public_addresses_are_allowed() {
+        let public = [
+            "93.184.216.34",
+            "100.128.0.1",
+            "198.20.0.1",
+            "2606:2800:220:1:248:1893:25c8:1946",
+            "::93.184.216.34",
+            "2002:5db8:d822::1",
+        ];
+        for addr in public {
+            assert!(is_public(addr.parse().unwrap()), "{}", addr);
+        }
+
+        let private = [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "100.64.0.1",
+            "100.127.255.254",
+            "192.0.0.8",
+            "198.18.0.1",
+            "198.19.255.255",
+            "224.0.0.1",
+            "239.255.255.250",
+            "240.0.0.1",
+            "ff02::1",
+            "64:ff9b::7f00:1",
+            "64:ff9b::5db8:d822",
+            "64:ff9b:1::a00:1",
+            "::127.0.0.1",
+            "::10.0.0.1",
+            "2002:7f00:1::",
+            "2002:a00:1::1",
+        ];
+        for addr in private {
+            assert!(!is_public(addr.parse().unwrap()), "{}", addr);
+        }
+    }
+
+    #[test]
+    fn only_http_urls_are_accepted() {
+        assert!(parse_url(" https://example.com/hook ").is_ok());
+        assert!(parse_url("ftp://example.com/hook").is_err());
+        assert!(parse_url("not a url").is_err());
+        assert!(parse_url("http://127.0.0.1/hook").is_err());
+        assert!(parse_url("http://[::1]/hook").is_err());
+    }
+}